@@ -15,6 +15,8 @@ pub struct Flags {
     pub offstreet_parking: Option<String>,
     pub sidewalks: Option<String>,
     pub gtfs: Option<String>,
+    // Which day's service to use from the GTFS calendar, like "monday"
+    pub gtfs_day: String,
     pub neighborhoods: Option<String>,
     pub clip: Option<String>,
     pub output: String,
@@ -41,7 +43,7 @@ pub fn convert(flags: &Flags, timer: &mut abstutil::Timer) -> RawMap {
     }
     if let Some(ref path) = flags.gtfs {
         timer.start("load GTFS");
-        map.bus_routes = gtfs::load(path).unwrap();
+        map.bus_routes = gtfs::load(path, &flags.gtfs_day).unwrap();
         timer.stop("load GTFS");
     }
 
@@ -9,6 +9,9 @@ fn main() {
         offstreet_parking: args.optional("--offstreet_parking"),
         sidewalks: args.optional("--sidewalks"),
         gtfs: args.optional("--gtfs"),
+        gtfs_day: args
+            .optional("--gtfs_day")
+            .unwrap_or_else(|| "monday".to_string()),
         neighborhoods: args.optional("--neighborhoods"),
         clip: args.optional("--clip"),
         output: args.required("--output"),
@@ -86,7 +86,16 @@ pub fn extract_osm(
         let mut tags = tags_to_map(&way.tags);
         tags.insert(osm::OSM_WAY_ID.to_string(), way.id.to_string());
 
-        if is_road(&tags) {
+        if is_light_rail(&tags) {
+            roads.push((
+                way.id,
+                RawRoad {
+                    center_points: pts,
+                    osm_tags: tags,
+                    turn_restrictions: Vec::new(),
+                },
+            ));
+        } else if is_road(&tags) {
             // If there's no parking data in OSM already, then assume no parking and mark that it's
             // inferred.
             if !tags.contains_key(osm::PARKING_LEFT)
@@ -309,6 +318,12 @@ fn is_road(tags: &BTreeMap<String, String>) -> bool {
     true
 }
 
+// Trains are modeled like vehicles on a road with just tracks.
+fn is_light_rail(tags: &BTreeMap<String, String>) -> bool {
+    tags.get(osm::RAILWAY) == Some(&"light_rail".to_string())
+        || tags.get(osm::RAILWAY) == Some(&"tram".to_string())
+}
+
 fn is_bldg(tags: &BTreeMap<String, String>) -> bool {
     tags.contains_key("building")
 }
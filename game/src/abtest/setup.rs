@@ -178,6 +178,31 @@ fn launch_test(test: &ABTest, ui: &mut UI, ctx: &mut EventCtx) -> ABTestMode {
                                     .sim_flags
                                     .opts
                                     .recalc_lanechanging,
+                                reroute_blocked_after: current_flags
+                                    .sim_flags
+                                    .opts
+                                    .reroute_blocked_after,
+                                midblock_lanechanging: current_flags
+                                    .sim_flags
+                                    .opts
+                                    .midblock_lanechanging,
+                                check_gridlock_every: current_flags
+                                    .sim_flags
+                                    .opts
+                                    .check_gridlock_every,
+                                resolve_gridlock: current_flags.sim_flags.opts.resolve_gridlock,
+                                travel_times_from: current_flags
+                                    .sim_flags
+                                    .opts
+                                    .travel_times_from
+                                    .clone(),
+                                paths_from: current_flags.sim_flags.opts.paths_from.clone(),
+                                bus_holding: current_flags.sim_flags.opts.bus_holding.clone(),
+                                emission_factors_from: current_flags
+                                    .sim_flags
+                                    .opts
+                                    .emission_factors_from
+                                    .clone(),
                             },
                         },
                         ..current_flags.clone()
@@ -6,7 +6,7 @@ use abstutil::prettyprint_usize;
 use ezgui::{hotkey, Color, EventCtx, GfxCtx, Key, Line, ModalMenu, Text};
 use geom::Time;
 use map_model::PathConstraints;
-use sim::CarID;
+use sim::{AgentID, CarID};
 use std::collections::BTreeMap;
 
 pub struct InfoPanel {
@@ -129,12 +129,16 @@ fn info_for(id: ID, ui: &UI, ctx: &EventCtx) -> Text {
                 "{} total agents crossed",
                 prettyprint_usize(sim.get_analytics().thruput_stats.count_per_road.get(r.id))
             )));
+            if let Some(e) = sim.get_analytics().emissions.per_road.get(&r.id) {
+                txt.add(Line(format!("Emissions so far: {}", e.describe())));
+            }
 
             if l.lane_type.is_for_moving_vehicles() {
                 for constraint in vec![
                     PathConstraints::Car,
                     PathConstraints::Bike,
                     PathConstraints::Bus,
+                    PathConstraints::Train,
                 ] {
                     if constraint.can_use(l, map) {
                         txt.add(Line(format!(
@@ -169,6 +173,15 @@ fn info_for(id: ID, ui: &UI, ctx: &EventCtx) -> Text {
                 txt.add(Line(format!("{} turning", accepted.len())));
             }
 
+            let (conflicts, severe) = sim.get_analytics().conflicts.count(id);
+            if conflicts > 0 {
+                txt.add(Line(format!(
+                    "{} safety conflicts so far, {} near-misses",
+                    prettyprint_usize(conflicts),
+                    prettyprint_usize(severe)
+                )));
+            }
+
             let cnt = sim.count_trips_involving_border(id);
             if cnt.nonzero() {
                 txt.add(Line(""));
@@ -233,6 +246,12 @@ fn info_for(id: ID, ui: &UI, ctx: &EventCtx) -> Text {
                 // TODO Wrap
                 txt.add(Line(line));
             }
+            if let Some(e) = sim
+                .agent_to_trip(AgentID::Car(id))
+                .and_then(|t| sim.get_analytics().emissions.per_trip.get(&t))
+            {
+                txt.add(Line(format!("Emissions this trip: {}", e.describe())));
+            }
 
             // TODO blocked since when
             // TODO dist along trip
@@ -277,6 +296,13 @@ fn info_for(id: ID, ui: &UI, ctx: &EventCtx) -> Text {
                     "  {} passengers total (any stop)",
                     prettyprint_usize(passengers.get(r.id))
                 )));
+                let pass_ups = sim.get_analytics().bus_pass_ups(sim.time(), r.id).get(id);
+                if pass_ups > 0 {
+                    txt.add(Line(format!(
+                        "  {} people left behind by a full bus here",
+                        prettyprint_usize(pass_ups)
+                    )));
+                }
             }
         }
         ID::Area(id) => {
@@ -291,6 +291,11 @@ fn can_change_lane_type(l: LaneID, new_lt: LaneType, map: &Map) -> Option<String
         return None;
     }
 
+    // Tracks aren't just paint.
+    if map.get_l(l).is_light_rail() {
+        return Some(format!("You can't change light rail tracks"));
+    }
+
     // Only one parking lane per side.
     if proposed_lts
         .iter()
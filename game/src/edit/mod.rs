@@ -18,6 +18,7 @@ use ezgui::{
     ModalMenu, Text, WrappedWizard,
 };
 use map_model::{ControlStopSign, ControlTrafficSignal, EditCmd, LaneID, MapEdits};
+use sim::Sim;
 use std::collections::BTreeSet;
 
 pub struct EditMode {
@@ -25,29 +26,36 @@ pub struct EditMode {
     menu: ModalMenu,
     general_tools: MenuUnderButton,
     mode: GameplayMode,
+    // If we came from a simulation in progress, the sandbox mode underneath is waiting for it.
+    // Also remember the edits it was running with.
+    suspended_sim: Option<(Sim, MapEdits)>,
 
     lane_editor: lanes::LaneEditor,
 }
 
 impl EditMode {
-    pub fn new(ctx: &EventCtx, mode: GameplayMode) -> EditMode {
+    pub fn new(
+        ctx: &EventCtx,
+        mode: GameplayMode,
+        suspended_sim: Option<(Sim, MapEdits)>,
+    ) -> EditMode {
+        let mut choices = vec![
+            (hotkey(Key::Escape), "back to sandbox mode"),
+            (hotkey(Key::S), "save edits"),
+            (hotkey(Key::L), "load different edits"),
+            (lctrl(Key::Z), "undo"),
+            (hotkey(Key::Num1), "1) ..."),
+            (hotkey(Key::Num2), "2) ..."),
+            (hotkey(Key::Num3), "3) ..."),
+            (hotkey(Key::Num4), "4) ..."),
+            (hotkey(Key::Num5), "5) ..."),
+        ];
+        if suspended_sim.is_some() {
+            choices.insert(1, (hotkey(Key::R), "resume simulation with edits"));
+        }
         EditMode {
             common: CommonState::new(ctx),
-            menu: ModalMenu::new(
-                "Map Edit Mode",
-                vec![
-                    (hotkey(Key::Escape), "back to sandbox mode"),
-                    (hotkey(Key::S), "save edits"),
-                    (hotkey(Key::L), "load different edits"),
-                    (lctrl(Key::Z), "undo"),
-                    (hotkey(Key::Num1), "1) ..."),
-                    (hotkey(Key::Num2), "2) ..."),
-                    (hotkey(Key::Num3), "3) ..."),
-                    (hotkey(Key::Num4), "4) ..."),
-                    (hotkey(Key::Num5), "5) ..."),
-                ],
-                ctx,
-            ),
+            menu: ModalMenu::new("Map Edit Mode", choices, ctx),
             general_tools: MenuUnderButton::new(
                 "assets/ui/hamburger.png",
                 "General",
@@ -56,6 +64,7 @@ impl EditMode {
                 ctx,
             ),
             mode,
+            suspended_sim,
             lane_editor: lanes::LaneEditor::setup(ctx),
         }
     }
@@ -178,7 +187,20 @@ impl State for EditMode {
                 .recalculate_pathfinding_after_edits(&mut Timer::new("apply pending map edits"));
             // Parking state might've changed
             ui.primary.clear_sim();
-            return Transition::Replace(Box::new(SandboxMode::new(ctx, ui, self.mode.clone())));
+            let sandbox = Box::new(SandboxMode::new(ctx, ui, self.mode.clone()));
+            if self.suspended_sim.is_some() {
+                return Transition::PopThenReplace(sandbox);
+            }
+            return Transition::Replace(sandbox);
+        } else if self.suspended_sim.is_some() && self.menu.action("resume simulation with edits") {
+            let mut timer = Timer::new("apply map edits to the simulation");
+            ui.primary
+                .map
+                .recalculate_pathfinding_after_edits(&mut timer);
+            let (mut sim, old_edits) = self.suspended_sim.take().unwrap();
+            sim.handle_live_edits(&old_edits, &ui.primary.map, &mut timer);
+            ui.primary.sim = sim;
+            return Transition::Pop;
         }
 
         if let Some(ID::Intersection(id)) = ui.primary.current_selection {
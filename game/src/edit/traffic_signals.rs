@@ -13,7 +13,8 @@ use ezgui::{
 };
 use geom::{Duration, Time};
 use map_model::{
-    ControlTrafficSignal, EditCmd, IntersectionID, Phase, TurnGroupID, TurnPriority, TurnType,
+    trace_corridor, ActuatedTiming, ControlTrafficSignal, EditCmd, IntersectionID, Phase,
+    TransitPriority, TurnGroupID, TurnPriority, TurnType,
 };
 use std::collections::BTreeSet;
 use std::time::Instant;
@@ -35,6 +36,8 @@ impl TrafficSignalEditor {
                 (hotkey(Key::UpArrow), "select previous phase"),
                 (hotkey(Key::DownArrow), "select next phase"),
                 (hotkey(Key::D), "change phase duration"),
+                (hotkey(Key::A), "toggle actuated control for current phase"),
+                (hotkey(Key::T), "toggle transit signal priority"),
                 (hotkey(Key::K), "move current phase up"),
                 (hotkey(Key::J), "move current phase down"),
                 (hotkey(Key::Backspace), "delete current phase"),
@@ -47,6 +50,7 @@ impl TrafficSignalEditor {
                     "convert to dedicated pedestrian scramble phase",
                 ),
                 (hotkey(Key::O), "change signal offset"),
+                (hotkey(Key::C), "coordinate signals along a corridor"),
                 (lctrl(Key::P), "preview changes"),
                 (hotkey(Key::Escape), "quit"),
             ],
@@ -67,10 +71,14 @@ impl State for TrafficSignalEditor {
 
         self.menu.event(ctx);
         // TODO This really needs to be shown in the diagram!
-        self.menu.set_info(
-            ctx,
-            Text::from(Line(format!("Signal offset: {}", orig_signal.offset))),
-        );
+        let mut txt = Text::from(Line(format!("Signal offset: {}", orig_signal.offset)));
+        if let Some(ref tsp) = orig_signal.transit_priority {
+            txt.add(Line(format!(
+                "Transit priority: up to +{} green, -{} red",
+                tsp.max_green_extension, tsp.max_red_truncation
+            )));
+        }
+        self.menu.set_info(ctx, txt);
         ctx.canvas.handle_event(ctx.input);
         self.diagram.event(ctx, &mut self.menu);
 
@@ -141,6 +149,8 @@ impl State for TrafficSignalEditor {
             ));
         } else if self.menu.action("change signal offset") {
             return Transition::Push(change_offset(orig_signal.offset));
+        } else if self.menu.action("coordinate signals along a corridor") {
+            return Transition::Push(pick_corridor(self.diagram.i));
         } else if self.menu.action("choose a preset signal") {
             return Transition::Push(change_preset(self.diagram.i));
         } else if self.menu.action("reset to original") {
@@ -214,6 +224,28 @@ impl State for TrafficSignalEditor {
             new_signal.convert_to_ped_scramble(&ui.primary.map);
             change_traffic_signal(new_signal, ui, ctx);
             self.diagram = TrafficSignalDiagram::new(self.diagram.i, 0, ui, ctx);
+        } else if self
+            .menu
+            .action("toggle actuated control for current phase")
+        {
+            let mut new_signal = orig_signal.clone();
+            let phase = &mut new_signal.phases[current_phase];
+            phase.actuated = if phase.actuated.is_some() {
+                None
+            } else {
+                Some(ActuatedTiming::new())
+            };
+            change_traffic_signal(new_signal, ui, ctx);
+            self.diagram = TrafficSignalDiagram::new(self.diagram.i, current_phase, ui, ctx);
+        } else if self.menu.action("toggle transit signal priority") {
+            let mut new_signal = orig_signal.clone();
+            new_signal.transit_priority = if new_signal.transit_priority.is_some() {
+                None
+            } else {
+                Some(TransitPriority::new())
+            };
+            change_traffic_signal(new_signal, ui, ctx);
+            self.diagram = TrafficSignalDiagram::new(self.diagram.i, current_phase, ui, ctx);
         }
 
         if self.menu.action("preview changes") {
@@ -371,6 +403,55 @@ fn change_preset(i: IntersectionID) -> Box<dyn State> {
     }))
 }
 
+fn pick_corridor(i: IntersectionID) -> Box<dyn State> {
+    WizardState::new(Box::new(move |wiz, ctx, ui| {
+        let (_, road) =
+            wiz.wrap(ctx)
+                .choose("Coordinate signals heading out along which road?", || {
+                    ui.primary
+                        .map
+                        .get_i(i)
+                        .roads
+                        .iter()
+                        .map(|r| {
+                            Choice::new(
+                                format!("{} ({})", ui.primary.map.get_r(*r).get_name(), r),
+                                *r,
+                            )
+                        })
+                        .collect()
+                })?;
+        let roads = trace_corridor(&ui.primary.map, i, road);
+        match map_model::coordinate_corridor(&ui.primary.map, &roads, None, None) {
+            Ok(timing) => {
+                println!(
+                    "Coordinated {} signals along {} roads. Cycle length {}, bandwidth {}",
+                    timing.num_signals,
+                    roads.len(),
+                    timing.cycle_length,
+                    timing.bandwidth
+                );
+                Some(Transition::PopWithData(Box::new(move |state, ui, ctx| {
+                    let mut editor = state.downcast_mut::<TrafficSignalEditor>().unwrap();
+                    let mut edits = ui.primary.map.get_edits().clone();
+                    edits.commands.extend(timing.edits);
+                    apply_map_edits(&mut ui.primary, &ui.cs, ctx, edits);
+                    editor.diagram = TrafficSignalDiagram::new(
+                        editor.diagram.i,
+                        editor.diagram.current_phase(),
+                        ui,
+                        ctx,
+                    );
+                })))
+            }
+            Err(err) => Some(Transition::Replace(msg(
+                "Couldn't coordinate this corridor",
+                vec![err],
+            ))),
+        }
+    }))
+}
+
 fn check_for_missing_groups(
     mut signal: ControlTrafficSignal,
     diagram: &mut TrafficSignalDiagram,
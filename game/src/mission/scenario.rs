@@ -13,8 +13,8 @@ use ezgui::{
 use geom::{Distance, Duration, PolyLine, Time};
 use map_model::{BuildingID, IntersectionID, Map, Neighborhood};
 use sim::{
-    BorderSpawnOverTime, DrivingGoal, OriginDestination, Scenario, SeedParkedCars, SidewalkPOI,
-    SidewalkSpot, SpawnOverTime, SpawnTrip,
+    BorderSpawnOverTime, DrivingGoal, ModeChoiceModel, OriginDestination, Scenario, SeedParkedCars,
+    SidewalkPOI, SidewalkSpot, SpawnOverTime, SpawnTrip,
 };
 use std::collections::BTreeSet;
 
@@ -46,13 +46,19 @@ impl ScenarioManager {
             // trips_from_bldg and trips_from_border
             match trip {
                 // TODO CarAppearing might be from a border
-                SpawnTrip::CarAppearing { .. } => {}
-                SpawnTrip::MaybeUsingParkedCar(_, b, _) => {
+                SpawnTrip::CarAppearing { .. }
+                | SpawnTrip::Delivery { .. }
+                | SpawnTrip::Emergency { .. } => {}
+                SpawnTrip::MaybeUsingParkedCar(_, b, _)
+                | SpawnTrip::DriveThenTransit { start: b, .. } => {
                     trips_from_bldg.insert(*b, idx);
                 }
                 SpawnTrip::UsingBike(_, ref spot, _)
                 | SpawnTrip::JustWalking(_, ref spot, _)
-                | SpawnTrip::UsingTransit(_, ref spot, _, _, _, _) => match spot.connection {
+                | SpawnTrip::UsingTransit(_, ref spot, _, _, _, _)
+                | SpawnTrip::BikeThenTransit {
+                    start: ref spot, ..
+                } => match spot.connection {
                     SidewalkPOI::Building(b) => {
                         trips_from_bldg.insert(b, idx);
                     }
@@ -66,6 +72,7 @@ impl ScenarioManager {
             // trips_to_bldg and trips_to_border
             match trip {
                 SpawnTrip::CarAppearing { ref goal, .. }
+                | SpawnTrip::Delivery { ref goal, .. }
                 | SpawnTrip::MaybeUsingParkedCar(_, _, ref goal)
                 | SpawnTrip::UsingBike(_, _, ref goal) => match goal {
                     DrivingGoal::ParkNear(b) => {
@@ -76,7 +83,9 @@ impl ScenarioManager {
                     }
                 },
                 SpawnTrip::JustWalking(_, _, ref spot)
-                | SpawnTrip::UsingTransit(_, _, ref spot, _, _, _) => match spot.connection {
+                | SpawnTrip::UsingTransit(_, _, ref spot, _, _, _)
+                | SpawnTrip::DriveThenTransit { goal: ref spot, .. }
+                | SpawnTrip::BikeThenTransit { goal: ref spot, .. } => match spot.connection {
                     SidewalkPOI::Building(b) => {
                         trips_to_bldg.insert(b, idx);
                     }
@@ -85,6 +94,9 @@ impl ScenarioManager {
                     }
                     _ => {}
                 },
+                SpawnTrip::Emergency { ref goal, .. } => {
+                    trips_to_bldg.insert(*goal, idx);
+                }
             }
         }
 
@@ -361,6 +373,8 @@ fn edit_scenario(map: &Map, scenario: &mut Scenario, mut wizard: WrappedWizard)
         x if x == spawn => {
             let (start_time, stop_time) =
                 pick_time_range(&mut wizard, "Start spawning when?", "Stop spawning when?")?;
+            let by_percent = "Pick modes using fixed percentages";
+            let by_time = "Pick modes by comparing travel times";
             scenario.spawn_over_time.push(SpawnOverTime {
                 num_agents: wizard.input_usize("Spawn how many agents?")?,
                 start_time,
@@ -376,6 +390,16 @@ fn edit_scenario(map: &Map, scenario: &mut Scenario, mut wizard: WrappedWizard)
                 percent_use_transit: wizard.input_percent(
                     "What percent of the walking trips will consider taking transit?",
                 )?,
+                mode_choice: if wizard
+                    .choose_string("How should agents pick their mode?", || {
+                        vec![by_percent, by_time]
+                    })?
+                    == by_time
+                {
+                    Some(ModeChoiceModel::new())
+                } else {
+                    None
+                },
             });
         }
         x if x == spawn_border => {
@@ -411,6 +435,7 @@ fn edit_scenario(map: &Map, scenario: &mut Scenario, mut wizard: WrappedWizard)
                         goal: OriginDestination::Neighborhood(dst.to_string()),
                         percent_biking: 0.1,
                         percent_use_transit: 0.2,
+                        mode_choice: None,
                     });
                 }
             }
@@ -552,6 +577,28 @@ fn describe(trip: &SpawnTrip, home: OD) -> String {
             start.lane(),
             driving_goal(goal)
         ),
+        SpawnTrip::Delivery {
+            depart,
+            start,
+            stops,
+            goal,
+        } => format!(
+            "{}: truck appears at {}, makes {} stops, goes to {}",
+            depart,
+            start.lane(),
+            stops.len(),
+            driving_goal(goal)
+        ),
+        SpawnTrip::Emergency {
+            depart,
+            start,
+            goal,
+        } => format!(
+            "{}: emergency vehicle appears at {}, responds to {}",
+            depart,
+            start.lane(),
+            goal
+        ),
         SpawnTrip::MaybeUsingParkedCar(depart, start_bldg, goal) => format!(
             "{}: try to drive from {} to {}",
             depart,
@@ -581,6 +628,40 @@ fn describe(trip: &SpawnTrip, home: OD) -> String {
             sidewalk_spot(goal),
             route
         ),
+        SpawnTrip::DriveThenTransit {
+            depart,
+            start,
+            park_near,
+            goal,
+            route,
+            ..
+        } => format!(
+            "{}: try to drive from {} to park near {}, then bus to {} using {}",
+            depart,
+            if OD::Bldg(*start) == home {
+                "HERE".to_string()
+            } else {
+                start.to_string()
+            },
+            park_near,
+            sidewalk_spot(goal),
+            route
+        ),
+        SpawnTrip::BikeThenTransit {
+            depart,
+            start,
+            park_near,
+            goal,
+            route,
+            ..
+        } => format!(
+            "{}: bike from {} to near {}, then bus to {} using {}",
+            depart,
+            sidewalk_spot(start),
+            park_near,
+            sidewalk_spot(goal),
+            route
+        ),
     }
 }
 
@@ -596,18 +677,26 @@ fn other_endpt(trip: &SpawnTrip, home: OD, map: &Map) -> ID {
     };
 
     let (from, to) = match trip {
-        SpawnTrip::CarAppearing { start, goal, .. } => (
+        SpawnTrip::CarAppearing { start, goal, .. } | SpawnTrip::Delivery { start, goal, .. } => (
             ID::Intersection(map.get_l(start.lane()).src_i),
             driving_goal(goal),
         ),
+        SpawnTrip::Emergency { start, goal, .. } => (
+            ID::Intersection(map.get_l(start.lane()).src_i),
+            ID::Building(*goal),
+        ),
         SpawnTrip::MaybeUsingParkedCar(_, start_bldg, goal) => {
             (ID::Building(*start_bldg), driving_goal(goal))
         }
         SpawnTrip::UsingBike(_, start, goal) => (sidewalk_spot(start), driving_goal(goal)),
         SpawnTrip::JustWalking(_, start, goal) => (sidewalk_spot(start), sidewalk_spot(goal)),
-        SpawnTrip::UsingTransit(_, start, goal, _, _, _) => {
+        SpawnTrip::UsingTransit(_, start, goal, _, _, _)
+        | SpawnTrip::BikeThenTransit { start, goal, .. } => {
             (sidewalk_spot(start), sidewalk_spot(goal))
         }
+        SpawnTrip::DriveThenTransit { start, goal, .. } => {
+            (ID::Building(*start), sidewalk_spot(goal))
+        }
     };
     let home_id = match home {
         OD::Bldg(b) => ID::Building(b),
@@ -83,7 +83,7 @@ impl DrawIntersection {
             IntersectionType::Construction => {
                 default_geom.push(cs.get("construction hatching"), i.polygon.clone());
             }
-            IntersectionType::TrafficSignal => {}
+            IntersectionType::TrafficSignal | IntersectionType::Roundabout => {}
         }
 
         DrawIntersection {
@@ -147,9 +147,9 @@ impl Renderable for DrawIntersection {
                     .map(|(_, t)| *t != ctx.sim.time())
                     .unwrap_or(true);
                 if recalc {
-                    let (_, phase, t) = signal.current_phase_and_remaining_time(ctx.sim.time());
+                    let (idx, t) = ctx.sim.current_phase_and_remaining_time(self.id, ctx.map);
                     let mut batch = GeomBatch::new();
-                    draw_signal_phase(phase, self.id, Some(t), &mut batch, ctx);
+                    draw_signal_phase(&signal.phases[idx], self.id, Some(t), &mut batch, ctx);
                     *maybe_redraw = Some((g.prerender.upload(batch), ctx.sim.time()));
                 }
                 g.redraw(&maybe_redraw.as_ref().unwrap().0);
@@ -56,6 +56,7 @@ impl DrawLane {
                 LaneType::Construction => {
                     cs.get_def("construction background", Color::rgb(255, 109, 0))
                 }
+                LaneType::LightRail => cs.get_def("light rail track", Color::rgb(120, 105, 90)),
             },
             polygon.clone(),
         );
@@ -106,6 +107,16 @@ impl DrawLane {
                         polygon.clone(),
                     );
                 }
+                LaneType::LightRail => {
+                    let color = cs.get_def("rails", Color::grey(0.6));
+                    // Standard gauge is about 1.4m
+                    for rail in vec![
+                        lane.lane_center_pts.shift_right(Distance::meters(0.7)),
+                        lane.lane_center_pts.shift_left(Distance::meters(0.7)),
+                    ] {
+                        draw.push(color, rail.get(timer).make_polygons(Distance::meters(0.15)));
+                    }
+                }
             };
         }
 
@@ -402,6 +402,13 @@ impl AgentColorScheme {
                 Some(VehicleType::Car) => cs.get_def("unzoomed car", Color::RED.alpha(0.5)),
                 Some(VehicleType::Bike) => cs.get_def("unzoomed bike", Color::GREEN.alpha(0.5)),
                 Some(VehicleType::Bus) => cs.get_def("unzoomed bus", Color::BLUE.alpha(0.5)),
+                Some(VehicleType::Train) => cs.get_def("unzoomed train", Color::PURPLE.alpha(0.5)),
+                Some(VehicleType::Truck) => {
+                    cs.get_def("unzoomed truck", Color::rgb(165, 103, 42).alpha(0.5))
+                }
+                Some(VehicleType::Emergency) => {
+                    cs.get_def("unzoomed emergency vehicle", Color::PINK.alpha(0.8))
+                }
                 None => cs.get_def("unzoomed pedestrian", Color::ORANGE.alpha(0.5)),
             },
             _ => self.by_metadata(&agent.metadata),
@@ -424,6 +431,12 @@ impl AgentColorScheme {
             AgentColorScheme::VehicleTypes => {
                 if input.id.1 == VehicleType::Bus {
                     cs.get_def("bus", Color::rgb(50, 133, 117))
+                } else if input.id.1 == VehicleType::Train {
+                    cs.get_def("train", Color::rgb(136, 84, 161))
+                } else if input.id.1 == VehicleType::Truck {
+                    cs.get_def("truck", Color::rgb(165, 103, 42))
+                } else if input.id.1 == VehicleType::Emergency {
+                    cs.get_def("emergency vehicle", Color::PINK)
                 } else {
                     match input.status {
                         CarStatus::Moving => cs.get_def("moving car", Color::CYAN),
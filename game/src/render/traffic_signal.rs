@@ -111,7 +111,8 @@ pub fn draw_signal_phase(
     let box_height = 6.5 * radius;
     let center = ctx.map.get_i(i).polygon.center();
     let top_left = center.offset(-box_width / 2.0, -box_height / 2.0);
-    let percent = time_left.unwrap() / phase.duration;
+    // Actuated phases might run longer than their nominal duration.
+    let percent = (time_left.unwrap() / phase.duration).min(1.0);
     // TODO Tune colors.
     batch.push(
         ctx.cs.get_def("traffic signal box", Color::grey(0.5)),
@@ -182,11 +183,7 @@ impl TrafficSignalDiagram {
         );
         let mut labels = Vec::new();
         for (idx, phase) in phases.iter().enumerate() {
-            labels.push(Text::from(Line(format!(
-                "Phase {}: {}",
-                idx + 1,
-                phase.duration
-            ))));
+            labels.push(Text::from(Line(describe_phase(idx, phase))));
         }
 
         TrafficSignalDiagram {
@@ -261,7 +258,7 @@ fn make_new_scroller(i: IntersectionID, draw_ctx: &DrawCtx, ctx: &EventCtx) -> N
             );
         }
         txt.push((
-            Text::from(Line(format!("Phase {}: {}", idx + 1, phase.duration))),
+            Text::from(Line(describe_phase(idx, phase))),
             ScreenPt::new(10.0 + (bounds.max_x - bounds.min_x) * zoom, y_offset * zoom),
         ));
         y_offset += bounds.max_y - bounds.min_y;
@@ -269,3 +266,16 @@ fn make_new_scroller(i: IntersectionID, draw_ctx: &DrawCtx, ctx: &EventCtx) -> N
 
     NewScroller::new(master_batch, txt, zoom, ctx)
 }
+
+fn describe_phase(idx: usize, phase: &Phase) -> String {
+    match phase.actuated {
+        Some(ref a) => format!(
+            "Phase {}: actuated, {} to {} (+{} per arrival)",
+            idx + 1,
+            a.min_green,
+            a.max_green,
+            a.extension
+        ),
+        None => format!("Phase {}: {}", idx + 1, phase.duration),
+    }
+}
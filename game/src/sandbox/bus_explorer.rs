@@ -6,7 +6,7 @@ use ezgui::{
     Choice, Color, EventCtx, GeomBatch, GfxCtx, Key, Line, MenuUnderButton, Text, WarpingItemSlider,
 };
 use geom::{Circle, Distance, Pt2D};
-use map_model::{BusRoute, BusRouteID, BusStopID, PathConstraints, PathRequest, PathStep};
+use map_model::{BusRoute, BusRouteID, BusStopID, PathRequest, PathStep};
 
 pub struct ShowBusRoute {
     colorer: RoadColorer,
@@ -47,7 +47,7 @@ impl ShowBusRoute {
                 .pathfind(PathRequest {
                     start: bs1.driving_pos,
                     end: bs2.driving_pos,
-                    constraints: PathConstraints::Bus,
+                    constraints: route.route_type,
                 })
                 .unwrap()
                 .get_steps()
@@ -58,12 +58,19 @@ impl ShowBusRoute {
             }
         }
 
+        let headway_cv = ui
+            .primary
+            .sim
+            .get_analytics()
+            .bus_headway_cv(ui.primary.sim.time(), route.id);
         let mut labels = Vec::new();
         for (idx, bs) in route.stops.iter().enumerate() {
-            labels.push((
-                Text::from(Line(format!("{}", idx + 1))),
-                map.get_bs(*bs).sidewalk_pos.pt(map),
-            ));
+            let mut txt = Text::from(Line(format!("{}", idx + 1)));
+            // Higher means more bunching
+            if let Some(cv) = headway_cv.get(bs) {
+                txt.add(Line(format!("headway CV {:.2}", cv)));
+            }
+            labels.push((txt, map.get_bs(*bs).sidewalk_pos.pt(map)));
         }
 
         ShowBusRoute {
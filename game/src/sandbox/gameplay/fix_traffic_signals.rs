@@ -1,20 +1,23 @@
+use crate::edit::apply_map_edits;
 use crate::game::{msg, Transition};
 use crate::sandbox::gameplay::faster_trips::small_faster_trips_panel;
-use crate::sandbox::gameplay::{manage_overlays, GameplayState};
+use crate::sandbox::gameplay::{manage_overlays, GameplayMode, GameplayState};
 use crate::sandbox::overlays::Overlays;
+use crate::sandbox::SandboxMode;
 use crate::ui::UI;
 use ezgui::{hotkey, EventCtx, Key, ModalMenu};
 use geom::{Duration, Statistic, Time};
-use map_model::{IntersectionID, Map};
+use map_model::{webster_timing, EditCmd, IntersectionID, Map};
 use sim::{Analytics, BorderSpawnOverTime, OriginDestination, Scenario, TripMode};
 
 pub struct FixTrafficSignals {
     time: Time,
     once: bool,
+    mode: GameplayMode,
 }
 
 impl FixTrafficSignals {
-    pub fn new(ctx: &EventCtx) -> (ModalMenu, Box<dyn GameplayState>) {
+    pub fn new(ctx: &EventCtx, mode: GameplayMode) -> (ModalMenu, Box<dyn GameplayState>) {
         (
             ModalMenu::new(
                 "Fix traffic signals",
@@ -23,12 +26,14 @@ impl FixTrafficSignals {
                     (hotkey(Key::D), "hide finished trip distribution"),
                     (hotkey(Key::H), "help"),
                     (hotkey(Key::S), "final score"),
+                    (hotkey(Key::T), "retime all signals using baseline traffic"),
                 ],
                 ctx,
             ),
             Box::new(FixTrafficSignals {
                 time: Time::START_OF_DAY,
                 once: true,
+                mode,
             }),
         )
     }
@@ -95,6 +100,24 @@ impl GameplayState for FixTrafficSignals {
                 ])));
         }
 
+        if menu.action("retime all signals using baseline traffic") {
+            let num_changed = retime_signals(ctx, ui, prebaked);
+            if num_changed == 0 {
+                return Some(Transition::Push(msg(
+                    "Retime signals",
+                    vec!["No baseline traffic was recorded through any traffic signal"],
+                )));
+            }
+            println!("Retimed {} traffic signals", num_changed);
+            // The new timing only applies from the start of the day.
+            ui.primary.clear_sim();
+            return Some(Transition::Replace(Box::new(SandboxMode::new(
+                ctx,
+                ui,
+                self.mode.clone(),
+            ))));
+        }
+
         if menu.action("final score") {
             return Some(Transition::Push(msg(
                 "Final score",
@@ -114,6 +137,32 @@ impl GameplayState for FixTrafficSignals {
     }
 }
 
+// Returns the number of signals changed
+fn retime_signals(ctx: &mut EventCtx, ui: &mut UI, prebaked: &Analytics) -> usize {
+    let flows = prebaked.peak_turn_group_flows();
+    let mut edits = ui.primary.map.get_edits().clone();
+    let mut num_changed = 0;
+    for i in ui.primary.map.all_intersections() {
+        if let Some(signal) = ui.primary.map.maybe_get_traffic_signal(i.id) {
+            if let Some(new_signal) = flows
+                .get(&i.id)
+                .and_then(|flows| webster_timing(signal, flows))
+            {
+                if &new_signal != signal {
+                    edits
+                        .commands
+                        .push(EditCmd::ChangeTrafficSignal(new_signal));
+                    num_changed += 1;
+                }
+            }
+        }
+    }
+    if num_changed > 0 {
+        apply_map_edits(&mut ui.primary, &ui.cs, ctx, edits);
+    }
+    num_changed
+}
+
 fn final_score(ui: &UI, prebaked: &Analytics) -> Vec<String> {
     let time = ui.primary.sim.time();
     let now = ui
@@ -151,7 +151,7 @@ impl GameplayRunner {
             GameplayMode::CreateGridlock => create_gridlock::CreateGridlock::new(ctx),
             GameplayMode::FasterTrips(trip_mode) => faster_trips::FasterTrips::new(trip_mode, ctx),
             GameplayMode::FixTrafficSignals | GameplayMode::FixTrafficSignalsTutorial(_) => {
-                fix_traffic_signals::FixTrafficSignals::new(ctx)
+                fix_traffic_signals::FixTrafficSignals::new(ctx, mode.clone())
             }
         };
         let prebaked = ctx.loading_screen("instantiate scenario", |_, timer| {
@@ -1,14 +1,17 @@
 use crate::common::{Plot, Series};
+use crate::edit::apply_map_edits;
 use crate::game::{msg, Transition, WizardState};
 use crate::helpers::rotating_color_total;
 use crate::sandbox::gameplay::{cmp_duration_shorter, manage_overlays, GameplayState};
 use crate::sandbox::overlays::Overlays;
 use crate::sandbox::{bus_explorer, SandboxMode};
 use crate::ui::UI;
+use abstutil::Timer;
 use ezgui::{hotkey, Choice, EventCtx, Key, Line, ModalMenu, Text};
 use geom::{Duration, Statistic, Time};
-use map_model::BusRouteID;
-use sim::Analytics;
+use map_model::{BusRouteID, EditCmd, PathRequest, PathStep, TransitPriority};
+use sim::{Analytics, HoldingStrategy};
+use std::collections::BTreeSet;
 
 pub struct OptimizeBus {
     route: BusRouteID,
@@ -26,6 +29,8 @@ impl OptimizeBus {
                     (hotkey(Key::E), "show bus route"),
                     (hotkey(Key::T), "show delays over time"),
                     (hotkey(Key::S), "change statistic"),
+                    (hotkey(Key::G), "toggle transit signal priority along route"),
+                    (hotkey(Key::O), "change bus holding"),
                     (hotkey(Key::H), "help"),
                 ],
                 ctx,
@@ -115,6 +120,77 @@ impl GameplayState for OptimizeBus {
                 },
             ))));
         }
+        if menu.action("toggle transit signal priority along route") {
+            let old_edits = ui.primary.map.get_edits().clone();
+            if toggle_transit_priority(self.route, ctx, ui) == 0 {
+                return Some(Transition::Push(msg(
+                    "Transit signal priority",
+                    vec!["This route doesn't pass through any traffic signals"],
+                )));
+            }
+            // Keep the simulation going; the changed signals pick up the new timing.
+            let mut timer = Timer::new("apply transit signal priority to the simulation");
+            ui.primary
+                .map
+                .recalculate_pathfinding_after_edits(&mut timer);
+            ui.primary
+                .sim
+                .handle_live_edits(&old_edits, &ui.primary.map, &mut timer);
+        }
+        if menu.action("change bus holding") {
+            let route = self.route;
+            return Some(Transition::Push(WizardState::new(Box::new(
+                move |wiz, ctx, ui| {
+                    let mut wizard = wiz.wrap(ctx);
+                    let none = "Don't hold buses";
+                    let headway = "Hold buses to even out the gaps between them";
+                    let schedule = "Hold buses to the timetable";
+                    let mut choices = vec![none, headway];
+                    if !ui.primary.map.get_br(route).departures.is_empty() {
+                        choices.push(schedule);
+                    }
+                    let choice = wizard
+                        .choose_string("How should buses on this route hold?", || {
+                            choices.clone()
+                        })?;
+                    let holding = if choice == headway {
+                        Some(wizard.input_something(
+                            "Leave at least how long after the previous bus? (ex: 10:00)",
+                            None,
+                            Box::new(|line| {
+                                Duration::parse(&line).ok().map(HoldingStrategy::Headway)
+                            }),
+                        )?)
+                    } else if choice == schedule {
+                        Some(HoldingStrategy::Schedule)
+                    } else {
+                        None
+                    };
+                    let spacing = if holding.is_some() {
+                        wizard.input_usize("Hold at every how many stops?")?.max(1)
+                    } else {
+                        1
+                    };
+                    let time_points = ui
+                        .primary
+                        .map
+                        .get_br(route)
+                        .stops
+                        .iter()
+                        .step_by(spacing)
+                        .cloned()
+                        .collect();
+                    if ui.primary.sim.set_bus_holding(route, time_points, holding) {
+                        Some(Transition::Pop)
+                    } else {
+                        Some(Transition::Replace(msg(
+                            "Bus holding",
+                            vec!["This route isn't running in the simulation"],
+                        )))
+                    }
+                },
+            ))));
+        }
         if menu.action("help") {
             return Some(Transition::Push(msg(
                 "Help",
@@ -123,6 +199,7 @@ impl GameplayState for OptimizeBus {
                     "Then use edit mode to try to speed things up.",
                     "Try making dedicated bus lanes",
                     "and adjusting traffic signals.",
+                    "Signals along the route can also give buses priority.",
                 ],
             )));
         }
@@ -160,6 +237,43 @@ fn bus_route_panel(id: BusRouteID, stat: Statistic, ui: &UI, prebaked: &Analytic
             txt.append(Line("no arrivals yet"));
         }
     }
+
+    // Only routes with a timetable have anything here
+    let adherence = ui
+        .primary
+        .sim
+        .get_analytics()
+        .schedule_adherence(ui.primary.sim.time(), id);
+    if !adherence.is_empty() {
+        txt.add(Line("Average lateness versus the timetable"));
+        for (idx, stop) in route.stops.iter().enumerate() {
+            txt.add(Line(format!("Stop {}: ", idx + 1)));
+            if let Some(list) = adherence.get(stop) {
+                let total = list
+                    .iter()
+                    .fold(Duration::ZERO, |sum, (_, late)| sum + *late);
+                txt.append(Line((total / (list.len() as f64)).to_string()));
+            } else {
+                txt.append(Line("no arrivals yet"));
+            }
+        }
+    }
+
+    let loads = ui
+        .primary
+        .sim
+        .get_analytics()
+        .bus_load_profile(ui.primary.sim.time(), id);
+    txt.add(Line("Average passengers leaving each stop"));
+    for (idx, stop) in route.stops.iter().enumerate() {
+        txt.add(Line(format!("Stop {}: ", idx + 1)));
+        if let Some(list) = loads.get(stop) {
+            let total: usize = list.iter().map(|(_, load)| *load).sum();
+            txt.append(Line(format!("{:.1}", (total as f64) / (list.len() as f64))));
+        } else {
+            txt.append(Line("no departures yet"));
+        }
+    }
     txt
 }
 
@@ -193,3 +307,47 @@ fn bus_delays(id: BusRouteID, ui: &UI, ctx: &mut EventCtx) -> Plot<Duration> {
         ctx,
     )
 }
+
+// Turn on transit signal priority for every signal along the route, or turn it off if they all
+// have it already. Returns the number of signals along the route.
+fn toggle_transit_priority(id: BusRouteID, ctx: &mut EventCtx, ui: &mut UI) -> usize {
+    let map = &ui.primary.map;
+    let route = map.get_br(id);
+    let mut signals = BTreeSet::new();
+    for (idx, stop1) in route.stops.iter().enumerate() {
+        let stop2 = route.stops[(idx + 1) % route.stops.len()];
+        if let Some(path) = map.pathfind(PathRequest {
+            start: map.get_bs(*stop1).driving_pos,
+            end: map.get_bs(stop2).driving_pos,
+            constraints: route.route_type,
+        }) {
+            for step in path.get_steps() {
+                if let PathStep::Turn(t) = step {
+                    if map.maybe_get_traffic_signal(t.parent).is_some() {
+                        signals.insert(t.parent);
+                    }
+                }
+            }
+        }
+    }
+
+    let enable = signals
+        .iter()
+        .any(|i| map.get_traffic_signal(*i).transit_priority.is_none());
+    let mut edits = map.get_edits().clone();
+    for i in &signals {
+        let mut signal = map.get_traffic_signal(*i).clone();
+        if signal.transit_priority.is_some() != enable {
+            signal.transit_priority = if enable {
+                Some(TransitPriority::new())
+            } else {
+                None
+            };
+            edits.commands.push(EditCmd::ChangeTrafficSignal(signal));
+        }
+    }
+    if !signals.is_empty() {
+        apply_map_edits(&mut ui.primary, &ui.cs, ctx, edits);
+    }
+    signals.len()
+}
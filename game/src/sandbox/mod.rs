@@ -251,8 +251,20 @@ impl State for SandboxMode {
         }
 
         if self.menu.action("edit mode") {
-            ui.primary.clear_sim();
-            return Transition::Replace(Box::new(EditMode::new(ctx, self.gameplay.mode.clone())));
+            // Stash a simulation in progress, so it can continue with the edits.
+            let old_sim = ui.primary.clear_sim();
+            if old_sim.is_empty() {
+                return Transition::Replace(Box::new(EditMode::new(
+                    ctx,
+                    self.gameplay.mode.clone(),
+                    None,
+                )));
+            }
+            return Transition::Push(Box::new(EditMode::new(
+                ctx,
+                self.gameplay.mode.clone(),
+                Some((old_sim, ui.primary.map.get_edits().clone())),
+            )));
         }
 
         if let Some(t) = self.common.event(ctx, ui) {
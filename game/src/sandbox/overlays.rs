@@ -13,7 +13,7 @@ use ezgui::{
 };
 use geom::{Distance, Duration, PolyLine, Statistic, Time};
 use map_model::{IntersectionID, LaneID, PathConstraints, PathStep, RoadID};
-use sim::{Analytics, ParkingSpot, TripMode};
+use sim::{Analytics, ParkingSpot, TripMode, MAX_PET, SEVERE_PET};
 use std::collections::{BTreeMap, HashSet};
 
 pub enum Overlays {
@@ -24,6 +24,8 @@ pub enum Overlays {
     FinishedTrips(Time, Plot<usize>),
     FinishedTripsHistogram(Time, Histogram),
     Chokepoints(Time, ObjectColorer),
+    Emissions(Time, ObjectColorer),
+    Conflicts(Time, ObjectColorer),
     BikeNetwork(RoadColorer),
     BikePathCosts(RoadColorer),
     BusNetwork(RoadColorer),
@@ -77,6 +79,8 @@ impl Overlays {
                                 Choice::new("bike network", ()).key(Key::B),
                                 Choice::new("bike path costs", ()).key(Key::X),
                                 Choice::new("bus network", ()).key(Key::U),
+                                Choice::new("emissions", ()).key(Key::E),
+                                Choice::new("safety conflicts", ()).key(Key::S),
                             ]
                         })?;
                     Some(Transition::PopWithData(Box::new(move |state, ui, ctx| {
@@ -91,6 +95,8 @@ impl Overlays {
                             "bike network" => Overlays::bike_network(ctx, ui),
                             "bike path costs" => Overlays::bike_path_costs(ctx, ui),
                             "bus network" => Overlays::bus_network(ctx, ui),
+                            "emissions" => Overlays::emissions(ctx, ui),
+                            "safety conflicts" => Overlays::conflicts(ctx, ui),
                             _ => unreachable!(),
                         };
                     })))
@@ -132,6 +138,12 @@ impl Overlays {
             Overlays::Chokepoints(t, _) if now != *t => {
                 *self = Overlays::chokepoints(ctx, ui);
             }
+            Overlays::Emissions(t, _) if now != *t => {
+                *self = Overlays::emissions(ctx, ui);
+            }
+            Overlays::Conflicts(t, _) if now != *t => {
+                *self = Overlays::conflicts(ctx, ui);
+            }
             _ => {}
         };
         None
@@ -150,7 +162,9 @@ impl Overlays {
             }
             Overlays::IntersectionDelay(_, ref heatmap)
             | Overlays::CumulativeThroughput(_, ref heatmap)
-            | Overlays::Chokepoints(_, ref heatmap) => {
+            | Overlays::Chokepoints(_, ref heatmap)
+            | Overlays::Emissions(_, ref heatmap)
+            | Overlays::Conflicts(_, ref heatmap) => {
                 heatmap.draw(g, ui);
                 true
             }
@@ -511,6 +525,113 @@ impl Overlays {
         Overlays::Chokepoints(ui.primary.sim.time(), colorer.build(ctx, &ui.primary.map))
     }
 
+    // CO2 so far per meter of road, so long roads don't look worse just for being long.
+    // Intersections are just colored by their total.
+    fn emissions(ctx: &EventCtx, ui: &UI) -> Overlays {
+        let light = Color::GREEN;
+        let medium = Color::YELLOW;
+        let heavy = Color::RED;
+        let stats = &ui.primary.sim.get_analytics().emissions;
+        let mut colorer = ObjectColorerBuilder::new(
+            Text::prompt(&format!("Emissions ({} total)", stats.total.describe())),
+            vec![
+                ("< 50%ile CO2", light),
+                ("< 90%ile CO2", medium),
+                (">= 90%ile CO2", heavy),
+            ],
+        );
+
+        let mut roads: Vec<(RoadID, f64)> = stats
+            .per_road
+            .iter()
+            .map(|(r, e)| {
+                let len = ui.primary.map.get_r(*r).center_pts.length();
+                (*r, e.co2 / len.inner_meters().max(1.0))
+            })
+            .collect();
+        roads.sort_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap());
+        let p50_idx = ((roads.len() as f64) * 0.5) as usize;
+        let p90_idx = ((roads.len() as f64) * 0.9) as usize;
+        for (idx, (r, _)) in roads.into_iter().enumerate() {
+            let color = if idx < p50_idx {
+                light
+            } else if idx < p90_idx {
+                medium
+            } else {
+                heavy
+            };
+            colorer.add(ID::Road(r), color);
+        }
+
+        let mut intersections: Vec<(IntersectionID, f64)> = stats
+            .per_intersection
+            .iter()
+            .map(|(i, e)| (*i, e.co2))
+            .collect();
+        intersections.sort_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap());
+        let p50_idx = ((intersections.len() as f64) * 0.5) as usize;
+        let p90_idx = ((intersections.len() as f64) * 0.9) as usize;
+        for (idx, (i, _)) in intersections.into_iter().enumerate() {
+            let color = if idx < p50_idx {
+                light
+            } else if idx < p90_idx {
+                medium
+            } else {
+                heavy
+            };
+            colorer.add(ID::Intersection(i), color);
+        }
+
+        Overlays::Emissions(ui.primary.sim.time(), colorer.build(ctx, &ui.primary.map))
+    }
+
+    // Intersections with any near-misses are the worst, then the ones with the most conflicts.
+    fn conflicts(ctx: &EventCtx, ui: &UI) -> Overlays {
+        let few = Color::YELLOW;
+        let many = Color::ORANGE;
+        let severe = Color::RED;
+        let detector = &ui.primary.sim.get_analytics().conflicts;
+        let total = detector.all_conflicts().count();
+        let total_severe = detector.all_conflicts().filter(|c| c.is_severe()).count();
+        let mut colorer = ObjectColorerBuilder::new(
+            Text::prompt(&format!(
+                "{} conflicts with PET < {}, {} near-misses under {}",
+                prettyprint_usize(total),
+                MAX_PET,
+                prettyprint_usize(total_severe),
+                SEVERE_PET
+            )),
+            vec![
+                ("< 50%ile conflicts", few),
+                (">= 50%ile conflicts", many),
+                ("near-misses", severe),
+            ],
+        );
+
+        let mut counts: Vec<(IntersectionID, usize, usize)> = detector
+            .per_intersection
+            .keys()
+            .map(|i| {
+                let (cnt, severe) = detector.count(*i);
+                (*i, cnt, severe)
+            })
+            .collect();
+        counts.sort_by_key(|(_, cnt, _)| *cnt);
+        let p50_idx = ((counts.len() as f64) * 0.5) as usize;
+        for (idx, (i, _, num_severe)) in counts.into_iter().enumerate() {
+            let color = if num_severe > 0 {
+                severe
+            } else if idx < p50_idx {
+                few
+            } else {
+                many
+            };
+            colorer.add(ID::Intersection(i), color);
+        }
+
+        Overlays::Conflicts(ui.primary.sim.time(), colorer.build(ctx, &ui.primary.map))
+    }
+
     fn bike_network(ctx: &EventCtx, ui: &UI) -> Overlays {
         let mut colorer = RoadColorerBuilder::new(
             Text::prompt("bike networks"),
@@ -1,26 +1,53 @@
 use abstutil::elapsed_seconds;
 use failure::Error;
-use geom::LonLat;
+use geom::{Duration, LonLat, Time};
 use itertools::Itertools;
 use serde_derive::{Deserialize, Serialize};
-use std::collections::{BTreeSet, HashMap};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fs::File;
+use std::path::Path;
 use std::time::Instant;
 
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub struct Route {
     pub name: String,
     pub stops: Vec<LonLat>,
+    // When a bus leaves the first stop to serve the first direction. Sorted, and empty if the feed
+    // has no times.
+    pub departures: Vec<Time>,
+    // Parallel to stops. How long after departing the first stop the bus is scheduled to reach
+    // each stop, assuming the second direction starts as soon as the first finishes.
+    pub stop_offsets: Vec<Duration>,
+    // If the route has a second direction, its stops come after the first's, starting at this
+    // index.
+    #[serde(default)]
+    pub return_stop: Option<usize>,
+    // When a bus leaves the return stop to serve the second direction. Sorted.
+    #[serde(default)]
+    pub return_departures: Vec<Time>,
+    // Served by trains instead of buses
+    pub light_rail: bool,
 }
 
-pub fn load(dir_path: &str) -> Result<Vec<Route>, Error> {
+// One scheduled run along a directed route
+struct TripTimes {
+    // (stop, arrival time)
+    stops: Vec<(LonLat, Time)>,
+}
+
+// Only trips running on the service day are kept, if the feed has a calendar. The day is a column
+// in calendar.txt, like "monday".
+pub fn load(dir_path: &str, service_day: &str) -> Result<Vec<Route>, Error> {
     println!("Loading GTFS from {}", dir_path);
     let timer = Instant::now();
 
-    let mut route_id_to_name: HashMap<String, String> = HashMap::new();
+    // (name, light rail)
+    let mut route_id_to_name: HashMap<String, (String, bool)> = HashMap::new();
     for rec in csv::Reader::from_reader(File::open(format!("{}/routes.txt", dir_path))?).records() {
         let rec = rec?;
-        route_id_to_name.insert(rec[0].to_string(), rec[2].to_string());
+        // rec[5] is route_type. 0 is tram or light rail, 1 is subway.
+        let light_rail = &rec[5] == "0" || &rec[5] == "1";
+        route_id_to_name.insert(rec[0].to_string(), (rec[2].to_string(), light_rail));
     }
 
     let mut stop_id_to_pt: HashMap<String, LonLat> = HashMap::new();
@@ -31,50 +58,156 @@ pub fn load(dir_path: &str) -> Result<Vec<Route>, Error> {
         stop_id_to_pt.insert(rec[0].to_string(), LonLat::new(lon, lat));
     }
 
+    let mut services_today: Option<HashSet<String>> = None;
+    let calendar_path = format!("{}/calendar.txt", dir_path);
+    if Path::new(&calendar_path).exists() {
+        let mut reader = csv::Reader::from_reader(File::open(calendar_path)?);
+        let day = reader
+            .headers()?
+            .iter()
+            .position(|h| h == service_day)
+            .ok_or_else(|| {
+                failure::err_msg(format!("calendar.txt has no {} column", service_day))
+            })?;
+        let mut services = HashSet::new();
+        for rec in reader.records() {
+            let rec = rec?;
+            if &rec[day] == "1" {
+                services.insert(rec[0].to_string());
+            }
+        }
+        services_today = Some(services);
+    }
+
     let mut trip_id_to_route_id_and_direction: HashMap<String, (String, bool)> = HashMap::new();
     for rec in csv::Reader::from_reader(File::open(format!("{}/trips.txt", dir_path))?).records() {
         let rec = rec?;
+        if let Some(ref services) = services_today {
+            if !services.contains(&rec[1]) {
+                continue;
+            }
+        }
         trip_id_to_route_id_and_direction
             .insert(rec[2].to_string(), (rec[0].to_string(), &rec[5] == "0"));
     }
 
-    // Each (directed) route has many trips. Ignore all but the first and assume the list of stops
-    // is the same. Also assume that records with the same trip are contiguous and that
-    // stop_sequence is monotonic.
-    let mut directed_routes: HashMap<(String, bool), Vec<LonLat>> = HashMap::new();
+    // Some trips are described as a pattern repeating every so often, instead of listing every
+    // run.
+    // (start, end, headway)
+    let mut frequencies: HashMap<String, Vec<(Time, Time, Duration)>> = HashMap::new();
+    let frequencies_path = format!("{}/frequencies.txt", dir_path);
+    if Path::new(&frequencies_path).exists() {
+        for rec in csv::Reader::from_reader(File::open(frequencies_path)?).records() {
+            let rec = rec?;
+            frequencies
+                .entry(rec[0].to_string())
+                .or_insert_with(Vec::new)
+                .push((
+                    Time::START_OF_DAY + Duration::parse(&rec[1])?,
+                    Time::START_OF_DAY + Duration::parse(&rec[2])?,
+                    Duration::seconds(rec[3].parse()?),
+                ));
+        }
+    }
+
+    // Each (directed) route has many trips. Assume the list of stops and the time between them is
+    // the same as the first trip; only remember when the others start. Also assume that records
+    // with the same trip are contiguous and that stop_sequence is monotonic.
+    let mut directed_routes: HashMap<(String, bool), (TripTimes, Vec<Time>)> = HashMap::new();
     let mut reader = csv::Reader::from_reader(File::open(format!("{}/stop_times.txt", dir_path))?);
     for (key, group) in reader
         .records()
         .group_by(|rec| rec.as_ref().unwrap()[0].to_string())
         .into_iter()
     {
-        let (route_id, forwards) = trip_id_to_route_id_and_direction[&key].clone();
-        if directed_routes.contains_key(&(route_id.clone(), forwards)) {
+        let (route_id, forwards) = match trip_id_to_route_id_and_direction.get(&key) {
+            Some(pair) => pair.clone(),
+            // Not running today
+            None => continue,
+        };
+        let mut trip = TripTimes { stops: Vec::new() };
+        for rec in group {
+            let rec = rec?;
+            // Intermediate stops might not have a time.
+            let time = if rec[1].is_empty() {
+                trip.stops
+                    .last()
+                    .map(|(_, t)| *t)
+                    .unwrap_or(Time::START_OF_DAY)
+            } else {
+                Time::START_OF_DAY + Duration::parse(&rec[1])?
+            };
+            trip.stops.push((stop_id_to_pt[&rec[3]], time));
+        }
+        if trip.stops.is_empty() {
             continue;
         }
-        directed_routes.insert(
-            (route_id, forwards),
-            group.map(|rec| stop_id_to_pt[&rec.unwrap()[3]]).collect(),
-        );
+
+        let first_time = trip.stops[0].1;
+        let mut starts = Vec::new();
+        if let Some(list) = frequencies.get(&key) {
+            for (start, end, headway) in list {
+                let mut t = *start;
+                while t < *end {
+                    starts.push(t);
+                    t = t + *headway;
+                }
+            }
+        } else {
+            starts.push(first_time);
+        }
+
+        directed_routes
+            .entry((route_id, forwards))
+            .or_insert_with(|| (trip, Vec::new()))
+            .1
+            .extend(starts);
     }
 
-    // Group together the pairs of directed routes
+    // Group together the pairs of directed routes. The simulation runs buses around the whole
+    // loop, but each direction keeps its own departures.
     let route_ids: BTreeSet<String> = directed_routes
         .keys()
         .map(|(id, _)| id.to_string())
         .collect();
     let mut results = Vec::new();
     for route_id in route_ids {
-        let mut stops = directed_routes
-            .remove(&(route_id.clone(), true))
-            .unwrap_or_else(Vec::new);
-        if let Some(more_stops) = directed_routes.remove(&(route_id.clone(), false)) {
-            stops.extend(more_stops);
+        let mut stops = Vec::new();
+        let mut stop_offsets = Vec::new();
+        // (index of the first stop, departures from it)
+        let mut directions: Vec<(usize, Vec<Time>)> = Vec::new();
+        for forwards in vec![true, false] {
+            if let Some((trip, mut starts)) = directed_routes.remove(&(route_id.clone(), forwards))
+            {
+                // Assume the second direction starts as soon as the first finishes.
+                let base = stop_offsets.last().cloned().unwrap_or(Duration::ZERO);
+                let first_time = trip.stops[0].1;
+                let first_stop = stops.len();
+                for (pt, time) in trip.stops {
+                    stops.push(pt);
+                    stop_offsets.push(base + (time - first_time));
+                }
+                starts.sort();
+                starts.dedup();
+                directions.push((first_stop, starts));
+            }
         }
         assert!(!stops.is_empty());
+        let (name, light_rail) = route_id_to_name[&route_id].clone();
+        let mut directions = directions.into_iter();
+        let departures = directions.next().unwrap().1;
+        let (return_stop, return_departures) = match directions.next() {
+            Some((idx, starts)) => (Some(idx), starts),
+            None => (None, Vec::new()),
+        };
         results.push(Route {
-            name: route_id_to_name[&route_id].to_string(),
+            name,
             stops,
+            departures,
+            stop_offsets,
+            return_stop,
+            return_departures,
+            light_rail,
         });
     }
     assert!(directed_routes.is_empty());
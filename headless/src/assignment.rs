@@ -0,0 +1,116 @@
+use abstutil::Timer;
+use geom::{Duration, Time};
+use map_model::Map;
+use sim::{Scenario, Sim, SimFlags, TravelTimes, TripPaths};
+
+// Trips departing late in the day get this long to finish.
+const MAX_OVERTIME: Duration = Duration::const_seconds(4.0 * 3600.0);
+
+// Repeatedly simulate a scenario. After each run, average the observed travel times with the
+// previous runs (the method of successive averages) and reroute a shrinking fraction of trips
+// using them, until the relative gap is small enough. Saves the final path of every trip's
+// vehicle, which can be reused with --paths_from.
+pub fn run(
+    sim_flags: &SimFlags,
+    max_iterations: usize,
+    gap_threshold: f64,
+    output: String,
+) -> Result<(), String> {
+    let mut timer = Timer::new("traffic assignment");
+    // TODO Support the other things SimFlags can load
+    let scenario: Scenario = if sim_flags.load.ends_with(".bin") {
+        abstutil::maybe_read_binary(sim_flags.load.clone(), &mut timer).ok()
+    } else {
+        None
+    }
+    .ok_or_else(|| {
+        format!(
+            "Traffic assignment needs a scenario, but {} isn't one",
+            sim_flags.load
+        )
+    })?;
+    let map = Map::new(abstutil::path_map(&scenario.map_name), false, &mut timer);
+    timer.done();
+
+    let mut costs: Option<TravelTimes> = None;
+    let mut paths = TripPaths::new();
+    let mut converged = false;
+    for iteration in 1..=max_iterations {
+        let mut timer = Timer::new(&format!("assignment iteration {}", iteration));
+        let mut opts = sim_flags.opts.clone();
+        opts.run_name = format!("{} assignment {}", scenario.scenario_name, iteration);
+        if opts.check_gridlock_every.is_none() {
+            opts.check_gridlock_every = Some(Duration::minutes(1));
+        }
+        let mut sim = Sim::new(&map, opts, &mut timer);
+        if let Some(ref tt) = costs {
+            sim.use_travel_times(tt.clone());
+        }
+        sim.use_fixed_paths(paths.keep_for_iteration(iteration));
+        sim.record_paths();
+        scenario.instantiate(&mut sim, &map, &mut sim_flags.make_rng(), &mut timer);
+        timer.done();
+        let finished = run_iteration(&mut sim, &map, sim_flags.opts.resolve_gridlock);
+
+        let observed = &sim.get_analytics().travel_times;
+        if let Some(ref mut tt) = costs {
+            tt.blend(observed, 1.0 / (iteration as f64));
+        } else {
+            costs = Some(observed.clone());
+        }
+        paths = sim.get_recorded_paths().unwrap().clone();
+
+        let mut timer = Timer::new("calculate relative gap");
+        let gap = paths.relative_gap(costs.as_mut().unwrap(), &map, &mut timer);
+        timer.done();
+        println!(
+            "Assignment iteration {}: {} vehicle trips, relative gap {}",
+            iteration,
+            paths.len(),
+            gap
+        );
+        // Trips that never finished would make the gap look better than it is.
+        if !finished {
+            println!(
+                "Iteration {} didn't finish, so it hasn't converged",
+                iteration
+            );
+        } else if gap <= gap_threshold {
+            println!("Converged after {} iterations", iteration);
+            converged = true;
+            break;
+        }
+    }
+    if !converged {
+        println!("Didn't converge after {} iterations", max_iterations);
+    }
+
+    println!("Saving {} paths to {}", paths.len(), output);
+    abstutil::write_binary(output, &paths);
+    Ok(())
+}
+
+// Runs until every trip is done. False if vehicles gridlocked (and the sim isn't resolving that
+// itself), or the sim ran long past the end of the day.
+fn run_iteration(sim: &mut Sim, map: &Map, resolve_gridlock: bool) -> bool {
+    let give_up = Time::START_OF_DAY + Duration::hours(24) + MAX_OVERTIME;
+    while !sim.is_done() {
+        if !resolve_gridlock {
+            if let Some((time, cars, _, intersections)) = sim.get_analytics().gridlocks.first() {
+                println!(
+                    "At {}, {} vehicles are gridlocked near {:?}; stopping this iteration",
+                    time,
+                    cars.len(),
+                    intersections
+                );
+                return false;
+            }
+        }
+        if sim.time() >= give_up {
+            println!("Still going at {}; stopping this iteration", sim.time());
+            return false;
+        }
+        sim.step(map, Duration::minutes(1));
+    }
+    true
+}
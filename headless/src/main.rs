@@ -1,17 +1,43 @@
+mod assignment;
+
 use abstutil::{CmdArgs, Timer};
-use geom::Time;
+use geom::{Duration, Time};
 use sim::{GetDrawAgents, Scenario, SimFlags};
 
 fn main() {
     let mut args = CmdArgs::new();
-    let sim_flags = SimFlags::from_args(&mut args);
+    let mut sim_flags = SimFlags::from_args(&mut args);
     let save_at = args.optional_parse("--save_at", Time::parse);
     let num_agents = args.optional_parse("--num_agents", |s| s.parse::<usize>());
     let enable_profiler = args.enabled("--enable_profiler");
     // Every 0.1s, pretend to draw everything to make sure there are no bugs.
     let paranoia = args.enabled("--paranoia");
+    // Stop as soon as any vehicles are gridlocked, instead of running forever.
+    let abort_on_gridlock = args.enabled("--abort_on_gridlock");
+    // Save observed travel times at the end, to route vehicles in a later run.
+    let save_travel_times = args.optional("--save_travel_times");
+    // Instead of one run, iterate to find equilibrium routes and save them here.
+    let assign_paths = args.optional("--assign_paths");
+    let assign_iterations = args
+        .optional_parse("--assign_iterations", |s| s.parse::<usize>())
+        .unwrap_or(10);
+    let assign_gap = args
+        .optional_parse("--assign_gap", |s| s.parse::<f64>())
+        .unwrap_or(0.01);
     args.done();
 
+    if let Some(path) = assign_paths {
+        if let Err(err) = assignment::run(&sim_flags, assign_iterations, assign_gap, path) {
+            println!("{}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if abort_on_gridlock && sim_flags.opts.check_gridlock_every.is_none() {
+        sim_flags.opts.check_gridlock_every = Some(Duration::minutes(1));
+    }
+
     let mut timer = Timer::new("setup headless");
     let (map, mut sim, mut rng) = sim_flags.load(&mut timer);
 
@@ -39,6 +65,7 @@ fn main() {
         }
     }
     let timer = Timer::new("run sim until done");
+    let gridlocks_reported = std::cell::Cell::new(0);
     sim.run_until_done(
         &map,
         move |sim, map| {
@@ -56,11 +83,35 @@ fn main() {
             if paranoia {
                 sim.get_all_draw_cars(map);
             }
+            if abort_on_gridlock {
+                if let Some((time, cars, lanes, intersections)) =
+                    sim.get_analytics().gridlocks.first()
+                {
+                    println!(
+                        "Aborting: at {}, {:?} are gridlocked on {:?} near {:?}",
+                        time, cars, lanes, intersections
+                    );
+                    std::process::exit(1);
+                }
+            }
+            let gridlocks = &sim.get_analytics().gridlocks;
+            for (time, cars, _, intersections) in &gridlocks[gridlocks_reported.get()..] {
+                println!(
+                    "At {}, {} vehicles are gridlocked near {:?}",
+                    time,
+                    cars.len(),
+                    intersections
+                );
+            }
+            gridlocks_reported.set(gridlocks.len());
         },
         None,
     );
     timer.done();
     println!("Done at {}", sim.time());
+    if let Some(path) = save_travel_times {
+        abstutil::write_binary(path, &sim.get_analytics().travel_times);
+    }
     if enable_profiler && save_at.is_none() {
         #[cfg(feature = "profiler")]
         {
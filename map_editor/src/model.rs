@@ -223,6 +223,7 @@ impl Model {
             IntersectionType::StopSign => Color::RED,
             IntersectionType::Border => Color::BLUE,
             IntersectionType::Construction => Color::ORANGE,
+            IntersectionType::Roundabout => Color::YELLOW,
         };
 
         let poly = if self.intersection_geom && !self.map.roads_per_intersection(id).is_empty() {
@@ -272,7 +273,7 @@ impl Model {
             }
             IntersectionType::Border => IntersectionType::StopSign,
             // These shouldn't exist in a basemap!
-            IntersectionType::Construction => unreachable!(),
+            IntersectionType::Construction | IntersectionType::Roundabout => unreachable!(),
         };
         self.map
             .intersections
@@ -615,6 +616,7 @@ impl Model {
             LaneType::Biking => Color::rgb(15, 125, 75),
             LaneType::SharedLeftTurn => Color::YELLOW,
             LaneType::Construction => Color::rgb(255, 109, 0),
+            LaneType::LightRail => Color::rgb(120, 105, 90),
         };
         if unset {
             match color {
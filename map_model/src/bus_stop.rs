@@ -1,4 +1,5 @@
-use crate::{LaneID, Position};
+use crate::{LaneID, PathConstraints, Position};
+use geom::{Duration, Time};
 use serde_derive::{Deserialize, Serialize};
 use std::fmt;
 
@@ -45,4 +46,18 @@ pub struct BusRoute {
     pub id: BusRouteID,
     pub name: String,
     pub stops: Vec<BusStopID>,
+    // When a bus should leave the first stop to serve the route. If empty, there's no timetable.
+    #[serde(default)]
+    pub departures: Vec<Time>,
+    // Parallel to stops. How long after departing the first stop the bus should reach each one.
+    #[serde(default)]
+    pub stop_offsets: Vec<Duration>,
+    // If the route has a second direction, buses serving it start from this stop instead, leaving
+    // at these times.
+    #[serde(default)]
+    pub return_stop: Option<usize>,
+    #[serde(default)]
+    pub return_departures: Vec<Time>,
+    // Bus or Train
+    pub route_type: PathConstraints,
 }
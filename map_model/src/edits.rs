@@ -21,7 +21,7 @@ pub struct MapEdits {
     pub dirty: bool,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum EditCmd {
     ChangeLaneType {
         id: LaneID,
@@ -153,6 +153,66 @@ impl MapEdits {
         self.changed_intersections.extend(changed_traffic_signals);
     }
 
+    // Which intersections might work differently than they did with some older edits? Includes
+    // the ends of any lanes that changed. Assumes update_derived has been called on both.
+    pub fn changed_since(&self, old: &MapEdits, map: &Map) -> BTreeSet<IntersectionID> {
+        let (old_lts, old_intersections) = old.latest_cmds();
+        let (new_lts, new_intersections) = self.latest_cmds();
+
+        let mut lanes: BTreeSet<LaneID> = old
+            .reversed_lanes
+            .symmetric_difference(&self.reversed_lanes)
+            .cloned()
+            .collect();
+        for l in old_lts.keys().chain(new_lts.keys()) {
+            if old_lts.get(l) != new_lts.get(l) {
+                lanes.insert(*l);
+            }
+        }
+
+        let mut changed = BTreeSet::new();
+        for l in lanes {
+            let lane = map.get_l(l);
+            changed.insert(lane.src_i);
+            changed.insert(lane.dst_i);
+        }
+        for i in old_intersections.keys().chain(new_intersections.keys()) {
+            if old_intersections.get(i) != new_intersections.get(i) {
+                changed.insert(*i);
+            }
+        }
+        changed
+    }
+
+    // The last lane type set for every lane, and the last command touching every intersection
+    fn latest_cmds(
+        &self,
+    ) -> (
+        BTreeMap<LaneID, LaneType>,
+        BTreeMap<IntersectionID, &EditCmd>,
+    ) {
+        let mut lts = BTreeMap::new();
+        let mut intersections = BTreeMap::new();
+        for cmd in &self.commands {
+            match cmd {
+                EditCmd::ChangeLaneType { id, lt, .. } => {
+                    lts.insert(*id, *lt);
+                }
+                EditCmd::ReverseLane { .. } => {}
+                EditCmd::ChangeStopSign(ss) => {
+                    intersections.insert(ss.id, cmd);
+                }
+                EditCmd::ChangeTrafficSignal(ts) => {
+                    intersections.insert(ts.id, cmd);
+                }
+                EditCmd::CloseIntersection { id, .. } | EditCmd::UncloseIntersection(id, _) => {
+                    intersections.insert(*id, cmd);
+                }
+            }
+        }
+        (lts, intersections)
+    }
+
     // Assumes update_derived has been called.
     pub(crate) fn compress(&mut self, map: &Map) {
         let orig_cmds: Vec<EditCmd> = self.commands.drain(..).collect();
@@ -195,7 +255,8 @@ impl MapEdits {
                     }
                     assert!(found);
                 }
-                IntersectionType::Border => unreachable!(),
+                // These can only be closed, which is handled above.
+                IntersectionType::Border | IntersectionType::Roundabout => unreachable!(),
             }
         }
     }
@@ -21,6 +21,9 @@ pub enum IntersectionType {
     TrafficSignal,
     Border,
     Construction,
+    // Vehicles entering yield to those already circulating. Every intersection along the ring gets
+    // this type.
+    Roundabout,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -58,6 +61,10 @@ impl Intersection {
         self.intersection_type == IntersectionType::TrafficSignal
     }
 
+    pub fn is_roundabout(&self) -> bool {
+        self.intersection_type == IntersectionType::Roundabout
+    }
+
     pub fn get_incoming_lanes(&self, map: &Map, constraints: PathConstraints) -> Vec<LaneID> {
         self.incoming_lanes
             .iter()
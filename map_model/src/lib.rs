@@ -12,6 +12,7 @@ pub mod osm;
 mod pathfind;
 pub mod raw;
 mod road;
+mod signal_timing;
 mod stop_signs;
 mod traffic_signals;
 mod traversable;
@@ -26,10 +27,13 @@ pub use crate::lane::{Lane, LaneID, LaneType, PARKING_SPOT_LENGTH};
 pub use crate::make::RoadSpec;
 pub use crate::map::Map;
 pub use crate::neighborhood::{FullNeighborhoodInfo, Neighborhood, NeighborhoodBuilder};
-pub use crate::pathfind::{Path, PathConstraints, PathRequest, PathStep};
+pub use crate::pathfind::{Path, PathConstraints, PathRequest, PathStep, VehiclePathfinder};
 pub use crate::road::{DirectedRoadID, Road, RoadID};
+pub use crate::signal_timing::{
+    coordinate_corridor, trace_corridor, webster_timing, CorridorTiming,
+};
 pub use crate::stop_signs::{ControlStopSign, RoadWithStopSign};
-pub use crate::traffic_signals::{ControlTrafficSignal, Phase};
+pub use crate::traffic_signals::{ActuatedTiming, ControlTrafficSignal, Phase, TransitPriority};
 pub use crate::traversable::{Position, Traversable};
 pub use crate::turn::{Turn, TurnGroup, TurnGroupID, TurnID, TurnPriority, TurnType};
 use abstutil::Cloneable;
@@ -45,3 +49,4 @@ impl Cloneable for MapEdits {}
 impl Cloneable for Neighborhood {}
 impl Cloneable for NeighborhoodBuilder {}
 impl Cloneable for raw::RestrictionType {}
+impl Cloneable for RoadID {}
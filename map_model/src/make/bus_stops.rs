@@ -4,10 +4,13 @@ use crate::{
     Position,
 };
 use abstutil::{MultiMap, Timer};
-use geom::{Bounds, Distance, GPSBounds, HashablePt2D, Pt2D};
+use geom::{Bounds, Distance, Duration, FindClosest, GPSBounds, HashablePt2D, Pt2D};
 use gtfs;
 use std::collections::{BTreeMap, HashMap, HashSet};
 
+// How far a light rail station can be from a sidewalk, and from the tracks
+const MAX_STATION_DIST: Distance = Distance::const_meters(50.0);
+
 pub fn make_bus_stops(
     map: &Map,
     bus_routes: &Vec<gtfs::Route>,
@@ -17,113 +20,195 @@ pub fn make_bus_stops(
 ) -> (BTreeMap<BusStopID, BusStop>, Vec<BusRoute>) {
     timer.start("make bus stops");
     let mut bus_stop_pts: HashSet<HashablePt2D> = HashSet::new();
-    let mut route_lookups: HashMap<String, Vec<HashablePt2D>> = HashMap::new();
+    let mut station_pts: HashSet<HashablePt2D> = HashSet::new();
+    // Also remember when the bus is scheduled to reach each stop. Buses and trains sharing a stop
+    // need their own, so the point alone isn't enough; also remember if it's a station. The index
+    // into the original stops is for finding where the return direction starts.
+    let mut route_lookups: HashMap<String, Vec<(HashablePt2D, bool, Duration, usize)>> =
+        HashMap::new();
     for route in bus_routes {
-        for gps in &route.stops {
+        for (idx, (gps, offset)) in route
+            .stops
+            .iter()
+            .zip(route.stop_offsets.iter())
+            .enumerate()
+        {
             if let Some(pt) = Pt2D::from_gps(*gps, gps_bounds) {
                 let hash_pt = pt.to_hashable();
-                bus_stop_pts.insert(hash_pt);
+                if route.light_rail {
+                    station_pts.insert(hash_pt);
+                } else {
+                    bus_stop_pts.insert(hash_pt);
+                }
                 route_lookups
                     .entry(route.name.clone())
                     .or_insert_with(Vec::new)
-                    .push(hash_pt);
+                    .push((hash_pt, route.light_rail, *offset, idx));
             }
         }
     }
 
-    let mut stops_per_sidewalk: MultiMap<LaneID, (Distance, HashablePt2D)> = MultiMap::new();
-    for (pt, pos) in find_sidewalk_points(
-        bounds,
-        bus_stop_pts,
-        map.all_lanes(),
-        Distance::meters(10.0),
-        timer,
-    )
-    .into_iter()
-    {
-        stops_per_sidewalk.insert(pos.lane(), (pos.dist_along(), pt));
+    let mut stops_per_sidewalk: MultiMap<LaneID, (Distance, HashablePt2D, bool)> = MultiMap::new();
+    // Stations tend to be further from the sidewalk than bus stops.
+    for (pts, max_dist_away, station) in vec![
+        (bus_stop_pts, Distance::meters(10.0), false),
+        (station_pts, MAX_STATION_DIST, true),
+    ] {
+        for (pt, pos) in find_sidewalk_points(bounds, pts, map.all_lanes(), max_dist_away, timer) {
+            stops_per_sidewalk.insert(pos.lane(), (pos.dist_along(), pt, station));
+        }
     }
-    let mut point_to_stop_id: HashMap<HashablePt2D, BusStopID> = HashMap::new();
+    let mut point_to_stop_id: HashMap<(HashablePt2D, bool), BusStopID> = HashMap::new();
     let mut bus_stops: BTreeMap<BusStopID, BusStop> = BTreeMap::new();
 
+    let mut closest_track: FindClosest<LaneID> = FindClosest::new(bounds);
+    for l in map.all_lanes() {
+        if l.is_light_rail() {
+            closest_track.add(l.id, l.lane_center_pts.points());
+        }
+    }
+
     for (sidewalk_id, dists_set) in stops_per_sidewalk.consume().into_iter() {
         let road = map.get_parent(sidewalk_id);
-        if let Ok(driving_lane) =
-            road.find_closest_lane(sidewalk_id, vec![LaneType::Driving, LaneType::Bus])
-        {
-            let mut dists: Vec<(Distance, HashablePt2D)> = dists_set.into_iter().collect();
-            dists.sort_by_key(|(dist, _)| *dist);
-            for (idx, (dist_along, orig_pt)) in dists.into_iter().enumerate() {
-                let stop_id = BusStopID {
-                    sidewalk: sidewalk_id,
-                    idx,
-                };
-                point_to_stop_id.insert(orig_pt, stop_id);
-                let sidewalk_pos = Position::new(sidewalk_id, dist_along);
-                let driving_pos = sidewalk_pos.equiv_pos(driving_lane, Distance::ZERO, map);
-                bus_stops.insert(
-                    stop_id,
-                    BusStop {
-                        id: stop_id,
-                        sidewalk_pos,
-                        driving_pos,
-                    },
-                );
-            }
-        } else {
-            timer.warn(format!(
-                "Can't find driving lane next to {}: {:?} and {:?}",
-                sidewalk_id, road.children_forwards, road.children_backwards
-            ));
+        let driving_lane = road
+            .find_closest_lane(sidewalk_id, vec![LaneType::Driving, LaneType::Bus])
+            .ok();
+        let mut dists: Vec<(Distance, HashablePt2D, bool)> = dists_set.into_iter().collect();
+        dists.sort_by_key(|(dist, _, _)| *dist);
+        for (idx, (dist_along, orig_pt, station)) in dists.into_iter().enumerate() {
+            let sidewalk_pos = Position::new(sidewalk_id, dist_along);
+            let driving_pos = if station {
+                find_track(sidewalk_pos, &closest_track, map)
+            } else {
+                driving_lane.map(|l| sidewalk_pos.equiv_pos(l, Distance::ZERO, map))
+            };
+            let driving_pos = match driving_pos {
+                Some(pos) => pos,
+                None => {
+                    timer.warn(format!(
+                        "Can't find a lane for vehicles to stop next to {}: {:?} and {:?}",
+                        sidewalk_id, road.children_forwards, road.children_backwards
+                    ));
+                    continue;
+                }
+            };
+            let stop_id = BusStopID {
+                sidewalk: sidewalk_id,
+                idx,
+            };
+            point_to_stop_id.insert((orig_pt, station), stop_id);
+            bus_stops.insert(
+                stop_id,
+                BusStop {
+                    id: stop_id,
+                    sidewalk_pos,
+                    driving_pos,
+                },
+            );
         }
     }
 
     let mut routes: Vec<BusRoute> = Vec::new();
     for route in bus_routes {
         let route_name = route.name.to_string();
-        let stops: Vec<BusStopID> = route_lookups
-            .remove(&route_name)
-            .unwrap_or_else(Vec::new)
-            .into_iter()
-            .filter_map(|pt| point_to_stop_id.get(&pt))
-            .cloned()
-            .collect();
+        let mut stops = Vec::new();
+        let mut stop_offsets = Vec::new();
+        let mut return_stop = None;
+        for (pt, station, offset, orig_idx) in
+            route_lookups.remove(&route_name).unwrap_or_else(Vec::new)
+        {
+            if let Some(id) = point_to_stop_id.get(&(pt, station)) {
+                if return_stop.is_none() && route.return_stop.map(|i| orig_idx >= i) == Some(true) {
+                    return_stop = Some(stops.len());
+                }
+                stops.push(*id);
+                stop_offsets.push(offset);
+            }
+        }
         let id = BusRouteID(routes.len());
         routes.push(BusRoute {
             id,
             name: route_name.to_string(),
             stops,
+            departures: route.departures.clone(),
+            stop_offsets,
+            return_departures: if return_stop.is_some() {
+                route.return_departures.clone()
+            } else {
+                Vec::new()
+            },
+            return_stop,
+            route_type: if route.light_rail {
+                PathConstraints::Train
+            } else {
+                PathConstraints::Bus
+            },
         });
     }
     timer.stop("make bus stops");
     (bus_stops, routes)
 }
 
+// Streetcars stop right next to the sidewalk. Otherwise, the station is probably next to separate
+// tracks.
+fn find_track(
+    sidewalk_pos: Position,
+    closest_track: &FindClosest<LaneID>,
+    map: &Map,
+) -> Option<Position> {
+    if let Ok(l) = map
+        .get_parent(sidewalk_pos.lane())
+        .find_closest_lane(sidewalk_pos.lane(), vec![LaneType::LightRail])
+    {
+        return Some(sidewalk_pos.equiv_pos(l, Distance::ZERO, map));
+    }
+    let (l, pt) = closest_track.closest_pt(sidewalk_pos.pt(map), MAX_STATION_DIST)?;
+    let dist_along = map.get_l(l).dist_along_of_point(pt)?;
+    Some(Position::new(l, dist_along))
+}
+
 pub fn fix_bus_route(map: &Map, r: &mut BusRoute) -> bool {
     // Trim out stops if needed; map borders sometimes mean some paths don't work.
     let mut stops = Vec::new();
-    for stop in r.stops.drain(..) {
-        if stops.is_empty() {
-            stops.push(stop);
-        } else {
-            if check_stops(*stops.last().unwrap(), stop, map) {
-                stops.push(stop);
+    let mut stop_offsets = Vec::new();
+    let mut return_stop = None;
+    for (idx, (stop, offset)) in r.stops.drain(..).zip(r.stop_offsets.drain(..)).enumerate() {
+        if stops.is_empty() || check_stops(*stops.last().unwrap(), stop, r.route_type, map) {
+            if return_stop.is_none() && r.return_stop.map(|i| idx >= i) == Some(true) {
+                return_stop = Some(stops.len());
             }
+            stops.push(stop);
+            stop_offsets.push(offset);
         }
     }
     // Don't forget the last and first
     while stops.len() >= 2 {
-        if check_stops(*stops.last().unwrap(), stops[0], map) {
+        if check_stops(*stops.last().unwrap(), stops[0], r.route_type, map) {
             break;
         }
         // TODO Or the front one
         stops.pop();
+        stop_offsets.pop();
+    }
+    // The return direction might've been trimmed entirely.
+    if return_stop.map(|i| i >= stops.len()) == Some(true) {
+        return_stop = None;
+    }
+    if return_stop.is_none() {
+        r.return_departures.clear();
     }
     r.stops = stops;
+    r.stop_offsets = stop_offsets;
+    r.return_stop = return_stop;
     r.stops.len() >= 2
 }
 
-fn check_stops(stop1: BusStopID, stop2: BusStopID, map: &Map) -> bool {
+fn check_stops(
+    stop1: BusStopID,
+    stop2: BusStopID,
+    constraints: PathConstraints,
+    map: &Map,
+) -> bool {
     let bs1 = map.get_bs(stop1);
     let bs2 = map.get_bs(stop2);
     // This is coming up because the dist_along's are in a bad order. But why should
@@ -133,7 +218,7 @@ fn check_stops(stop1: BusStopID, stop2: BusStopID, map: &Map) -> bool {
         .pathfind(PathRequest {
             start: bs1.driving_pos,
             end: bs2.driving_pos,
-            constraints: PathConstraints::Bus,
+            constraints,
         })
         .is_some();
     ok1 && ok2
@@ -20,6 +20,16 @@ pub fn get_lane_types(osm_tags: &BTreeMap<String, String>) -> (Vec<LaneType>, Ve
     if osm_tags.get(osm::HIGHWAY) == Some(&"footway".to_string()) {
         return (vec![LaneType::Sidewalk], Vec::new());
     }
+    if osm_tags.get(osm::RAILWAY) == Some(&"light_rail".to_string())
+        || osm_tags.get(osm::RAILWAY) == Some(&"tram".to_string())
+    {
+        // OSM usually maps each track as its own way. Trains could use a track in either
+        // direction, unless it's tagged otherwise.
+        if osm_tags.get("oneway") == Some(&"yes".to_string()) {
+            return (vec![LaneType::LightRail], Vec::new());
+        }
+        return (vec![LaneType::LightRail], vec![LaneType::LightRail]);
+    }
 
     // TODO Reversible roads should be handled differently?
     let oneway = osm_tags.get("oneway") == Some(&"yes".to_string())
@@ -196,6 +206,7 @@ impl RoadSpec {
             LaneType::Bus => 'u',
             LaneType::SharedLeftTurn => 'l',
             LaneType::Construction => 'c',
+            LaneType::LightRail => 'r',
         }
     }
 
@@ -208,6 +219,7 @@ impl RoadSpec {
             'u' => Some(LaneType::Bus),
             'l' => Some(LaneType::SharedLeftTurn),
             'c' => Some(LaneType::Construction),
+            'r' => Some(LaneType::LightRail),
             _ => None,
         }
     }
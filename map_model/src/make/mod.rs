@@ -2,6 +2,7 @@ mod buildings;
 mod bus_stops;
 pub mod initial;
 mod remove_disconnected;
+mod roundabouts;
 mod sidewalk_finder;
 mod turns;
 
@@ -9,4 +10,5 @@ pub use self::buildings::make_all_buildings;
 pub use self::bus_stops::{fix_bus_route, make_bus_stops};
 pub use self::initial::lane_specs::{get_lane_types, RoadSpec};
 pub use self::remove_disconnected::remove_disconnected_roads;
+pub use self::roundabouts::find_roundabouts;
 pub use self::turns::make_all_turns;
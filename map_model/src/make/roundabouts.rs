@@ -0,0 +1,53 @@
+use crate::{Intersection, IntersectionID, IntersectionType, Road};
+use abstutil::Timer;
+use std::collections::BTreeSet;
+
+// OSM models a roundabout as a ring of one-way junction=roundabout ways, and every node where an
+// approach road meets the ring becomes its own intersection. Find each ring and mark all of its
+// intersections, so they're controlled together. Signalized roundabouts are left alone.
+pub fn find_roundabouts(
+    roads: &Vec<Road>,
+    intersections: &mut Vec<Intersection>,
+    timer: &mut Timer,
+) {
+    let mut visited: BTreeSet<IntersectionID> = BTreeSet::new();
+    let mut num_rings = 0;
+    for start in roads.iter().filter(|r| r.is_roundabout()) {
+        if visited.contains(&start.src_i) {
+            continue;
+        }
+
+        // Flood along roundabout roads to find the whole ring.
+        let mut ring: BTreeSet<IntersectionID> = BTreeSet::new();
+        let mut queue = vec![start.src_i];
+        while let Some(i) = queue.pop() {
+            if ring.contains(&i) {
+                continue;
+            }
+            ring.insert(i);
+            for r in &intersections[i.0].roads {
+                let road = &roads[r.0];
+                if road.is_roundabout() {
+                    queue.push(road.src_i);
+                    queue.push(road.dst_i);
+                }
+            }
+        }
+        visited.extend(ring.iter().cloned());
+
+        if ring
+            .iter()
+            .any(|i| intersections[i.0].intersection_type == IntersectionType::TrafficSignal)
+        {
+            continue;
+        }
+        num_rings += 1;
+        for i in ring {
+            let i = &mut intersections[i.0];
+            if i.intersection_type == IntersectionType::StopSign {
+                i.intersection_type = IntersectionType::Roundabout;
+            }
+        }
+    }
+    timer.note(format!("Found {} roundabouts", num_rings));
+}
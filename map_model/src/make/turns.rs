@@ -606,9 +606,10 @@ fn get_sidewalk<'a>(lanes: &'a Vec<Lane>, children: &Vec<(LaneID, LaneType)>) ->
 }
 
 fn filter_vehicle_lanes(lanes: &Vec<(LaneID, LaneType)>, preferred: LaneType) -> Vec<LaneID> {
-    let preferred = filter_lanes(lanes, preferred);
-    if !preferred.is_empty() {
-        return preferred;
+    let matches = filter_lanes(lanes, preferred);
+    // Tracks only ever connect to other tracks.
+    if !matches.is_empty() || preferred == LaneType::LightRail {
+        return matches;
     }
     filter_lanes(lanes, LaneType::Driving)
 }
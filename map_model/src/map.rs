@@ -1,10 +1,13 @@
-use crate::pathfind::Pathfinder;
+use crate::pathfind::{
+    pathfind_with_costs, pathfind_with_dynamic_costs, Pathfinder, VehiclePathfinder,
+};
 use crate::raw::{OriginalIntersection, OriginalRoad, RawMap};
 use crate::{
     connectivity, make, Area, AreaID, Building, BuildingID, BusRoute, BusRouteID, BusStop,
     BusStopID, ControlStopSign, ControlTrafficSignal, EditCmd, EditEffects, Intersection,
     IntersectionID, IntersectionType, Lane, LaneID, LaneType, MapEdits, Path, PathConstraints,
-    PathRequest, Position, Road, RoadID, Turn, TurnGroupID, TurnID, TurnType, LANE_THICKNESS,
+    PathRequest, Position, Road, RoadID, Turn, TurnGroupID, TurnID, TurnPriority, TurnType,
+    LANE_THICKNESS,
 };
 use abstutil::{deserialize_btreemap, serialize_btreemap, Error, Timer};
 use geom::{Bounds, Distance, GPSBounds, Polygon, Pt2D};
@@ -122,7 +125,9 @@ impl Map {
                     IntersectionType::TrafficSignal => {
                         traffic_signals.insert(i.id, ControlTrafficSignal::new(&m, i.id, timer));
                     }
-                    IntersectionType::Border | IntersectionType::Construction => {}
+                    IntersectionType::Border
+                    | IntersectionType::Construction
+                    | IntersectionType::Roundabout => {}
                 };
             }
             m.stop_signs = stop_signs;
@@ -595,6 +600,37 @@ impl Map {
         self.pathfinder.as_ref().unwrap().pathfind(req, self)
     }
 
+    // Only for vehicles. See pathfind_with_costs for details.
+    pub fn pathfind_with_costs<F: Fn(&Lane, &Turn, usize) -> Option<usize>>(
+        &self,
+        req: PathRequest,
+        cost: F,
+    ) -> Option<Path> {
+        pathfind_with_costs(&req, self, cost)
+    }
+
+    // Only for vehicles. See pathfind_with_dynamic_costs for details.
+    pub fn pathfind_with_dynamic_costs<F: Fn(&Lane, &Turn, usize, usize) -> Option<usize>>(
+        &self,
+        req: PathRequest,
+        cost: F,
+    ) -> Option<Path> {
+        pathfind_with_dynamic_costs(&req, self, cost)
+    }
+
+    // Only for vehicles. Build once and reuse for many requests with the same costs.
+    pub fn vehicle_pathfinder_with_costs(
+        &self,
+        constraints: PathConstraints,
+        cost_fn: &dyn Fn(&Lane, &Turn, usize) -> usize,
+    ) -> VehiclePathfinder {
+        assert!(!self.pathfinder_dirty);
+        self.pathfinder
+            .as_ref()
+            .unwrap()
+            .vehicle_graph_with_costs(self, constraints, cost_fn)
+    }
+
     pub fn should_use_transit(
         &self,
         start: Position,
@@ -606,6 +642,21 @@ impl Map {
             .should_use_transit(self, start, end)
     }
 
+    // At roundabouts, vehicles already on the ring have priority over everybody entering.
+    // Pedestrians have priority at the crosswalks.
+    pub fn get_roundabout_priority(&self, t: TurnID) -> TurnPriority {
+        match self.get_t(t).turn_type {
+            TurnType::SharedSidewalkCorner | TurnType::Crosswalk => TurnPriority::Protected,
+            _ => {
+                if self.get_parent(t.src).is_roundabout() {
+                    TurnPriority::Protected
+                } else {
+                    TurnPriority::Yield
+                }
+            }
+        }
+    }
+
     // None for SharedSidewalkCorners
     pub fn get_turn_group(&self, t: TurnID) -> Option<TurnGroupID> {
         if let Some(ref ts) = self.maybe_get_traffic_signal(t.parent) {
@@ -684,6 +735,13 @@ impl Map {
         for id in &effects.changed_roads {
             let stops = self.get_r(*id).all_bus_stops(self);
             for s in stops {
+                // Tracks can't be edited, so stations don't need to move.
+                if self
+                    .get_l(self.get_bs(s).driving_pos.lane())
+                    .is_light_rail()
+                {
+                    continue;
+                }
                 let sidewalk_pos = self.get_bs(s).sidewalk_pos;
                 // Must exist, because we aren't allowed to orphan a bus stop.
                 let driving_lane = self
@@ -889,6 +947,8 @@ fn make_half_map(
         }
     }
 
+    make::find_roundabouts(&map.roads, &mut map.intersections, timer);
+
     for t in map.turns.values_mut() {
         t.lookup_idx = map.turn_lookup.len();
         map.turn_lookup.push(t.id);
@@ -1082,6 +1142,8 @@ impl EditCmd {
                         map.traffic_signals
                             .insert(id, ControlTrafficSignal::new(map, id, timer));
                     }
+                    // Priority comes from the roads
+                    IntersectionType::Roundabout => {}
                     IntersectionType::Border | IntersectionType::Construction => unreachable!(),
                 }
                 effects.changed_intersections.insert(id);
@@ -1171,7 +1233,7 @@ fn recalculate_turns(
 
     match i.intersection_type {
         // Stop sign policy doesn't depend on incoming lane types. Leave edits alone.
-        IntersectionType::StopSign => {}
+        IntersectionType::StopSign | IntersectionType::Roundabout => {}
         IntersectionType::TrafficSignal => {
             map.traffic_signals
                 .insert(id, ControlTrafficSignal::new(map, id, timer));
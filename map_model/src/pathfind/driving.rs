@@ -4,6 +4,8 @@ use fast_paths::{FastGraph, InputGraph, PathCalculator};
 use geom::Distance;
 use serde_derive::{Deserialize, Serialize};
 use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
 use thread_local::ThreadLocal;
 
 #[derive(Serialize, Deserialize)]
@@ -22,6 +24,17 @@ impl VehiclePathfinder {
         map: &Map,
         constraints: PathConstraints,
         seed: Option<&VehiclePathfinder>,
+    ) -> VehiclePathfinder {
+        VehiclePathfinder::new_with_costs(map, constraints, seed, &|_, _, cost| cost)
+    }
+
+    // The caller can adjust the normal cost of each lane and the turn after it. Much faster than
+    // pathfind_with_costs for lots of requests, but slow to build, even with a seed.
+    pub fn new_with_costs(
+        map: &Map,
+        constraints: PathConstraints,
+        seed: Option<&VehiclePathfinder>,
+        cost_fn: &dyn Fn(&Lane, &Turn, usize) -> usize,
     ) -> VehiclePathfinder {
         // Insert every lane as a node. Even if the lane type is wrong now, it might change later,
         // and we want the node in the graph. Do this first, so the IDs of all the nodes doesn't
@@ -30,7 +43,7 @@ impl VehiclePathfinder {
         for l in map.all_lanes() {
             nodes.get_or_insert(l.id);
         }
-        let input_graph = make_input_graph(map, &nodes, constraints);
+        let input_graph = make_input_graph(map, &nodes, constraints, cost_fn);
 
         // All VehiclePathfinders have the same nodes (lanes), so if we're not the first being
         // built, seed from the node ordering.
@@ -84,16 +97,95 @@ impl VehiclePathfinder {
         // ordering.
         // TODO Make sure the result of this is deterministic and equivalent to computing from
         // scratch.
-        let input_graph = make_input_graph(map, &self.nodes, self.constraints);
+        let input_graph = make_input_graph(map, &self.nodes, self.constraints, &|_, _, cost| cost);
         let node_ordering = self.graph.get_node_ordering();
         self.graph = fast_paths::prepare_with_order(&input_graph, &node_ordering).unwrap();
     }
 }
 
+// Like VehiclePathfinder, except the caller can adjust the cost of each lane and the turn after
+// it, or ban the pair entirely by returning None. The callback is also given the normal cost.
+// This doesn't use the contraction hierarchy, so it's much slower; only use it for the occasional
+// request.
+pub fn pathfind_with_costs<F: Fn(&Lane, &Turn, usize) -> Option<usize>>(
+    req: &PathRequest,
+    map: &Map,
+    cost_fn: F,
+) -> Option<Path> {
+    pathfind_with_dynamic_costs(req, map, |lane, turn, cost, _| cost_fn(lane, turn, cost))
+}
+
+// Like pathfind_with_costs, but the callback is also given the cost of reaching the lane, so
+// costs can depend on when the vehicle gets there.
+pub fn pathfind_with_dynamic_costs<F: Fn(&Lane, &Turn, usize, usize) -> Option<usize>>(
+    req: &PathRequest,
+    map: &Map,
+    cost_fn: F,
+) -> Option<Path> {
+    assert!(!map.get_l(req.start.lane()).is_sidewalk());
+    let start = req.start.lane();
+    let end = req.end.lane();
+
+    // Starting past the end of the same lane means looping around back to it, so the start itself
+    // doesn't count as reaching the end.
+    let loop_around = start == end && req.start.dist_along() > req.end.dist_along();
+
+    let mut best_cost: HashMap<LaneID, usize> = HashMap::new();
+    let mut backrefs: HashMap<LaneID, TurnID> = HashMap::new();
+    let mut queue: BinaryHeap<(Reverse<usize>, LaneID)> = BinaryHeap::new();
+    if !loop_around {
+        best_cost.insert(start, 0);
+    }
+    queue.push((Reverse(0), start));
+    let mut first = true;
+
+    while let Some((Reverse(cost_so_far), current)) = queue.pop() {
+        if current == end && !(loop_around && first) {
+            let mut steps = vec![PathStep::Lane(end)];
+            let mut l = end;
+            while l != start || (loop_around && steps.len() == 1) {
+                let t = backrefs[&l];
+                steps.push(PathStep::Turn(t));
+                steps.push(PathStep::Lane(t.src));
+                l = t.src;
+            }
+            steps.reverse();
+            let total_length = steps.iter().fold(Distance::ZERO, |sum, step| {
+                sum + step.as_traversable().length(map)
+            });
+            return Some(Path::new(map, steps, req.end.dist_along(), total_length));
+        }
+        if !first && cost_so_far > best_cost[&current] {
+            continue;
+        }
+        first = false;
+
+        let lane = map.get_l(current);
+        for turn in map.get_turns_for(current, req.constraints) {
+            if let Some(c) = cost_fn(
+                lane,
+                turn,
+                cost(lane, turn, req.constraints, map),
+                cost_so_far,
+            ) {
+                let next_cost = cost_so_far + c;
+                let next = turn.id.dst;
+                if best_cost.get(&next).map(|c| next_cost < *c).unwrap_or(true) {
+                    best_cost.insert(next, next_cost);
+                    backrefs.insert(next, turn.id);
+                    queue.push((Reverse(next_cost), next));
+                }
+            }
+        }
+    }
+    None
+}
+
 fn make_input_graph(
     map: &Map,
     nodes: &NodeMap<LaneID>,
     constraints: PathConstraints,
+    cost_fn: &dyn Fn(&Lane, &Turn, usize) -> usize,
 ) -> InputGraph {
     let mut input_graph = InputGraph::new();
     let num_lanes = map.all_lanes().len();
@@ -106,7 +198,7 @@ fn make_input_graph(
                 input_graph.add_edge(
                     from,
                     nodes.get(turn.id.dst),
-                    cost(l, turn, constraints, map),
+                    cost_fn(l, turn, cost(l, turn, constraints, map)),
                 );
             }
         }
@@ -127,7 +219,7 @@ pub fn cost(lane: &Lane, turn: &Turn, constraints: PathConstraints, map: &Map) -
     // TODO Could cost turns differently.
 
     match constraints {
-        PathConstraints::Car => {
+        PathConstraints::Car | PathConstraints::Train => {
             // Prefer slightly longer route on faster roads
             let t1 = lane.length() / map.get_r(lane.parent).get_speed_limit();
             let t2 = turn.geom.length() / map.get_parent(turn.id.dst).get_speed_limit();
@@ -2,11 +2,12 @@ mod driving;
 mod node_map;
 mod walking;
 
-pub use self::driving::cost;
-use self::driving::VehiclePathfinder;
+pub use self::driving::{
+    cost, pathfind_with_costs, pathfind_with_dynamic_costs, VehiclePathfinder,
+};
 use self::walking::SidewalkPathfinder;
 use crate::{
-    osm, BusRouteID, BusStopID, Lane, LaneID, LaneType, Map, Position, Traversable, TurnID,
+    osm, BusRouteID, BusStopID, Lane, LaneID, LaneType, Map, Position, Traversable, Turn, TurnID,
 };
 use abstutil::Timer;
 use geom::{Distance, PolyLine};
@@ -170,6 +171,14 @@ impl Path {
         self.total_length += self.steps[idx].as_traversable().length(map);
     }
 
+    // Move over to a parallel lane partway along the current step. Like modify_step, trusting the
+    // caller to fix up the following steps.
+    pub fn change_current_lane(&mut self, lane: LaneID, map: &Map) {
+        self.total_length -= self.steps[0].as_traversable().length(map);
+        self.steps[0] = PathStep::Lane(lane);
+        self.total_length += map.get_l(lane).length();
+    }
+
     pub fn current_step(&self) -> PathStep {
         self.steps[0]
     }
@@ -279,16 +288,67 @@ impl Path {
     pub fn get_steps(&self) -> &VecDeque<PathStep> {
         &self.steps
     }
+
+    pub fn end_dist(&self) -> Distance {
+        self.end_dist
+    }
+
+    // Where the current step finishes. A new path spliced in after the current step has to start
+    // here.
+    pub fn current_step_end(&self, map: &Map) -> Position {
+        match self.steps[0] {
+            PathStep::Lane(l) => Position::new(l, map.get_l(l).length()),
+            PathStep::ContraflowLane(l) => Position::new(l, Distance::ZERO),
+            PathStep::Turn(t) => {
+                // Crosswalks and sidewalk corners can lead to either end of the next sidewalk.
+                let dst = map.get_l(t.dst);
+                if dst.src_i == t.parent {
+                    Position::new(t.dst, Distance::ZERO)
+                } else {
+                    Position::new(t.dst, dst.length())
+                }
+            }
+        }
+    }
+
+    // Map edits might delete turns or change lane types after this path was calculated. The
+    // current step is assumed to be fine, since somebody's already on it.
+    pub fn is_valid_after_current_step(&self, constraints: PathConstraints, map: &Map) -> bool {
+        self.steps.iter().skip(1).all(|step| match step {
+            PathStep::Lane(l) | PathStep::ContraflowLane(l) => {
+                constraints.can_use(map.get_l(*l), map)
+            }
+            PathStep::Turn(t) => map.maybe_get_t(*t).is_some(),
+        })
+    }
+
+    // Keep the current step, then follow a different path. The other path has to start at
+    // current_step_end().
+    pub fn replace_after_current_step(&mut self, other: Path, map: &Map) {
+        // The old steps might not exist anymore, so don't look up their length.
+        let current = self.steps[0];
+        self.steps.truncate(1);
+        self.total_length = self.crossed_so_far + current.as_traversable().length(map);
+        let mut other_steps = other.steps;
+        if other_steps.front() == Some(&current) {
+            other_steps.pop_front();
+        }
+        for step in other_steps {
+            self.add(step, map);
+        }
+        self.end_dist = other.end_dist;
+    }
 }
 
 // Who's asking for a path?
 // TODO This is an awful name.
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
 pub enum PathConstraints {
     Pedestrian,
     Car,
     Bike,
     Bus,
+    Train,
 }
 
 impl PathConstraints {
@@ -299,6 +359,7 @@ impl PathConstraints {
             LaneType::Driving => PathConstraints::Car,
             LaneType::Biking => PathConstraints::Bike,
             LaneType::Bus => PathConstraints::Bus,
+            LaneType::LightRail => PathConstraints::Train,
             _ => panic!("PathConstraints::from_lt({:?}) doesn't make sense", lt),
         }
     }
@@ -321,6 +382,7 @@ impl PathConstraints {
                 }
             }
             PathConstraints::Bus => l.is_driving() || l.is_bus(),
+            PathConstraints::Train => l.is_light_rail(),
         }
     }
 }
@@ -394,6 +456,9 @@ pub struct Pathfinder {
     car_graph: VehiclePathfinder,
     bike_graph: VehiclePathfinder,
     bus_graph: VehiclePathfinder,
+    // Maps saved before light rail have no tracks to route on.
+    #[serde(default)]
+    train_graph: Option<VehiclePathfinder>,
     walking_graph: SidewalkPathfinder,
     // TODO Option just during initialization! Ewww.
     walking_with_transit_graph: Option<SidewalkPathfinder>,
@@ -415,6 +480,14 @@ impl Pathfinder {
         let bus_graph = VehiclePathfinder::new(map, PathConstraints::Bus, Some(&car_graph));
         timer.stop("prepare pathfinding for buses");
 
+        timer.start("prepare pathfinding for trains");
+        let train_graph = Some(VehiclePathfinder::new(
+            map,
+            PathConstraints::Train,
+            Some(&car_graph),
+        ));
+        timer.stop("prepare pathfinding for trains");
+
         timer.start("prepare pathfinding for pedestrians");
         let walking_graph = SidewalkPathfinder::new(map, false);
         timer.stop("prepare pathfinding for pedestrians");
@@ -423,6 +496,7 @@ impl Pathfinder {
             car_graph,
             bike_graph,
             bus_graph,
+            train_graph,
             walking_graph,
             walking_with_transit_graph: None,
         }
@@ -438,9 +512,27 @@ impl Pathfinder {
             PathConstraints::Car => self.car_graph.pathfind(&req, map),
             PathConstraints::Bike => self.bike_graph.pathfind(&req, map),
             PathConstraints::Bus => self.bus_graph.pathfind(&req, map),
+            PathConstraints::Train => self.train_graph.as_ref()?.pathfind(&req, map),
         }
     }
 
+    // Only for vehicles. See VehiclePathfinder::new_with_costs.
+    pub fn vehicle_graph_with_costs(
+        &self,
+        map: &Map,
+        constraints: PathConstraints,
+        cost_fn: &dyn Fn(&Lane, &Turn, usize) -> usize,
+    ) -> VehiclePathfinder {
+        let seed = match constraints {
+            PathConstraints::Pedestrian => unreachable!(),
+            PathConstraints::Car => Some(&self.car_graph),
+            PathConstraints::Bike => Some(&self.bike_graph),
+            PathConstraints::Bus => Some(&self.bus_graph),
+            PathConstraints::Train => self.train_graph.as_ref(),
+        };
+        VehiclePathfinder::new_with_costs(map, constraints, seed, cost_fn)
+    }
+
     pub fn should_use_transit(
         &self,
         map: &Map,
@@ -466,6 +558,12 @@ impl Pathfinder {
         self.bus_graph.apply_edits(map);
         timer.stop("apply edits to bus pathfinding");
 
+        timer.start("apply edits to train pathfinding");
+        if let Some(ref mut g) = self.train_graph {
+            g.apply_edits(map);
+        }
+        timer.stop("apply edits to train pathfinding");
+
         timer.start("apply edits to pedestrian pathfinding");
         self.walking_graph.apply_edits(map);
         timer.stop("apply edits to pedestrian pathfinding");
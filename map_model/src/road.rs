@@ -211,6 +211,10 @@ impl Road {
         }
     }
 
+    pub fn is_roundabout(&self) -> bool {
+        self.osm_tags.get("junction") == Some(&"roundabout".to_string())
+    }
+
     pub fn get_speed_limit(&self) -> Speed {
         // TODO Should probably cache this
         if let Some(limit) = self.osm_tags.get(osm::MAXSPEED) {
@@ -227,6 +231,9 @@ impl Road {
         {
             return Speed::miles_per_hour(40.0);
         }
+        if self.osm_tags.get(osm::RAILWAY) == Some(&"light_rail".to_string()) {
+            return Speed::miles_per_hour(35.0);
+        }
         Speed::miles_per_hour(20.0)
     }
 
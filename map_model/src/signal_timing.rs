@@ -0,0 +1,309 @@
+use crate::{
+    ControlTrafficSignal, EditCmd, IntersectionID, Map, RoadID, TurnGroupID, TurnPriority,
+};
+use geom::{Duration, Speed};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+// Unnamed roads all look alike, so don't wander off forever.
+const MAX_CORRIDOR_ROADS: usize = 20;
+
+// Vehicles per hour of green that one lane can discharge.
+const SATURATION_FLOW_PER_LANE: f64 = 1800.0;
+// Startup and clearance time wasted every phase.
+const LOST_TIME_PER_PHASE: Duration = Duration::const_seconds(4.0);
+// Long enough for pedestrians to start crossing.
+const MIN_PHASE_DURATION: Duration = Duration::const_seconds(10.0);
+const MIN_CYCLE_LENGTH: Duration = Duration::const_seconds(40.0);
+const MAX_CYCLE_LENGTH: Duration = Duration::const_seconds(150.0);
+// Webster's formula blows up as the intersection approaches saturation.
+const MAX_FLOW_RATIO: f64 = 0.9;
+
+// The result of coordinating the signals along a corridor.
+pub struct CorridorTiming {
+    pub edits: Vec<EditCmd>,
+    // How long a platoon passing the first signal can be and still make it through every signal
+    // without stopping.
+    pub bandwidth: Duration,
+    pub cycle_length: Duration,
+    pub num_signals: usize,
+}
+
+// Solve offsets for the traffic signals along a corridor, so that vehicles traveling in the order
+// of `roads` at `speed` (or the speed limit of each road, if not specified) hit a green wave.
+// Coordination only makes sense when every signal shares a cycle length; if one isn't given, the
+// longest existing cycle is used. Signals with a different cycle have their phases stretched
+// proportionally. Signals with actuated phases can't be coordinated.
+//
+// This maximizes the one-way bandwidth: the band is as wide as the shortest green window for the
+// corridor movement, and it's centered in every signal's window.
+pub fn coordinate_corridor(
+    map: &Map,
+    roads: &Vec<RoadID>,
+    speed: Option<Speed>,
+    cycle_length: Option<Duration>,
+) -> Result<CorridorTiming, String> {
+    if roads.len() < 2 {
+        return Err("A corridor needs at least two roads".to_string());
+    }
+
+    // Find every signal along the way, and how long it takes to reach it from the start of the
+    // first road.
+    let mut signals: Vec<(IntersectionID, TurnGroupID, Duration)> = Vec::new();
+    let mut travel_time = Duration::ZERO;
+    for pair in roads.windows(2) {
+        let r1 = map.get_r(pair[0]);
+        let r2 = map.get_r(pair[1]);
+        let i = if r1.dst_i == r2.src_i || r1.dst_i == r2.dst_i {
+            r1.dst_i
+        } else if r1.src_i == r2.src_i || r1.src_i == r2.dst_i {
+            r1.src_i
+        } else {
+            return Err(format!("{} and {} aren't connected", r1.id, r2.id));
+        };
+        travel_time += r1.center_pts.length() / speed.unwrap_or_else(|| r1.get_speed_limit());
+
+        if let Some(signal) = map.maybe_get_traffic_signal(i) {
+            // Actuated phases don't last a fixed time, so there's no cycle to line up.
+            if signal.phases.iter().any(|p| p.actuated.is_some()) {
+                return Err(format!(
+                    "{} has actuated phases; make them fixed-time before coordinating",
+                    i
+                ));
+            }
+            let group = TurnGroupID {
+                from: r1.id,
+                to: r2.id,
+                crosswalk: None,
+            };
+            if !signal.turn_groups.contains_key(&group) {
+                return Err(format!("{} has no movement from {} to {}", i, r1.id, r2.id));
+            }
+            signals.push((i, group, travel_time));
+        }
+    }
+    if signals.is_empty() {
+        return Err("No traffic signals along the corridor".to_string());
+    }
+
+    let cycle_length = cycle_length.unwrap_or_else(|| {
+        signals
+            .iter()
+            .map(|(i, _, _)| cycle_length_of(map.get_traffic_signal(*i)))
+            .max()
+            .unwrap()
+    });
+
+    let mut bandwidth = cycle_length;
+    // The time (relative to the start of the day) when the center of the band enters the first
+    // road. Set by the first signal, which keeps its offset.
+    let mut band_center: Option<Duration> = None;
+    let mut edits = Vec::new();
+    for (i, group, travel_time) in signals.iter() {
+        let mut signal = map.get_traffic_signal(*i).clone();
+        let old_cycle = cycle_length_of(&signal);
+        if old_cycle != cycle_length {
+            for phase in signal.phases.iter_mut() {
+                phase.duration = phase.duration * (cycle_length / old_cycle);
+            }
+        }
+        let (green_start, green_length) = green_window(&signal, *group)
+            .ok_or_else(|| format!("{} never lets {:?} go", i, group))?;
+        bandwidth = bandwidth.min(green_length);
+
+        let green_center = green_start + green_length / 2.0;
+        match band_center {
+            None => {
+                band_center = Some(modulo(
+                    green_center - signal.offset - *travel_time,
+                    cycle_length,
+                ));
+            }
+            Some(center) => {
+                signal.offset = modulo(green_center - center - *travel_time, cycle_length);
+            }
+        }
+
+        if &signal != map.get_traffic_signal(*i) {
+            edits.push(EditCmd::ChangeTrafficSignal(signal));
+        }
+    }
+
+    Ok(CorridorTiming {
+        edits,
+        bandwidth,
+        cycle_length,
+        num_signals: signals.len(),
+    })
+}
+
+// Starting at an intersection and heading out along a road, keep following roads with the same
+// name. Also includes the same-named road leading into the start, so the first signal gets
+// coordinated too.
+pub fn trace_corridor(map: &Map, start: IntersectionID, first_road: RoadID) -> Vec<RoadID> {
+    let name = map.get_r(first_road).get_name();
+    let mut roads = Vec::new();
+    let mut visited = HashSet::new();
+    if let Some(r) = map
+        .get_i(start)
+        .roads
+        .iter()
+        .find(|r| **r != first_road && map.get_r(**r).get_name() == name)
+    {
+        roads.push(*r);
+        visited.insert(*r);
+    }
+
+    let mut current = first_road;
+    let mut at = start;
+    loop {
+        roads.push(current);
+        visited.insert(current);
+        if roads.len() >= MAX_CORRIDOR_ROADS {
+            break;
+        }
+        let r = map.get_r(current);
+        at = if r.src_i == at { r.dst_i } else { r.src_i };
+        // Stop at the first road that isn't obviously the continuation.
+        match map
+            .get_i(at)
+            .roads
+            .iter()
+            .find(|next| !visited.contains(*next) && map.get_r(**next).get_name() == name)
+        {
+            Some(next) => {
+                current = *next;
+            }
+            None => {
+                break;
+            }
+        }
+    }
+    roads
+}
+
+// Keep the signal's phases, but pick the cycle length and split the green time using Webster's
+// method. `flows` are measured in vehicles per hour; turn groups that are missing had no traffic.
+// Returns None if there's no demand at all, in which case the existing timing is as good as any.
+pub fn webster_timing(
+    signal: &ControlTrafficSignal,
+    flows: &BTreeMap<TurnGroupID, f64>,
+) -> Option<ControlTrafficSignal> {
+    // The critical flow ratio of each phase is the busiest group it serves, relative to how much
+    // that group could possibly discharge.
+    let mut phase_ratios = vec![0.0; signal.phases.len()];
+    for group in signal.turn_groups.values() {
+        if group.id.crosswalk.is_some() {
+            continue;
+        }
+        let flow = flows.get(&group.id).cloned().unwrap_or(0.0);
+        if flow == 0.0 {
+            continue;
+        }
+        let lanes: BTreeSet<_> = group.members.iter().map(|t| t.src).collect();
+        let ratio = flow / (SATURATION_FLOW_PER_LANE * (lanes.len() as f64));
+
+        // Only count phases where the group is protected, unless it's never protected.
+        let mut served: Vec<usize> = Vec::new();
+        for priority in vec![TurnPriority::Protected, TurnPriority::Yield] {
+            served = (0..signal.phases.len())
+                .filter(|idx| signal.phases[*idx].get_priority_of_group(group.id) == priority)
+                .collect();
+            if !served.is_empty() {
+                break;
+            }
+        }
+        // A group spanning multiple phases splits its load between them.
+        for idx in &served {
+            phase_ratios[*idx] = f64::max(phase_ratios[*idx], ratio / (served.len() as f64));
+        }
+    }
+
+    let mut total_ratio: f64 = phase_ratios.iter().sum();
+    if total_ratio == 0.0 {
+        return None;
+    }
+    if total_ratio > MAX_FLOW_RATIO {
+        for r in phase_ratios.iter_mut() {
+            *r *= MAX_FLOW_RATIO / total_ratio;
+        }
+        total_ratio = MAX_FLOW_RATIO;
+    }
+
+    let lost_time = LOST_TIME_PER_PHASE * (signal.phases.len() as f64);
+    let cycle_length =
+        Duration::seconds((1.5 * lost_time.inner_seconds() + 5.0) / (1.0 - total_ratio))
+            .max(MIN_CYCLE_LENGTH)
+            .min(MAX_CYCLE_LENGTH);
+    let effective_green = (cycle_length - lost_time).max(Duration::ZERO);
+
+    let mut new_signal = signal.clone();
+    for (phase, ratio) in new_signal.phases.iter_mut().zip(phase_ratios) {
+        phase.duration = Duration::seconds(
+            (LOST_TIME_PER_PHASE + effective_green * (ratio / total_ratio))
+                .max(MIN_PHASE_DURATION)
+                .inner_seconds()
+                .round(),
+        );
+    }
+    Some(new_signal)
+}
+
+fn cycle_length_of(signal: &ControlTrafficSignal) -> Duration {
+    let mut cycle_length = Duration::ZERO;
+    for p in &signal.phases {
+        cycle_length += p.duration;
+    }
+    cycle_length
+}
+
+// When during the cycle can this group go, and for how long? Prefers the longest contiguous run
+// of phases where the group is protected, falling back to yielding.
+fn green_window(signal: &ControlTrafficSignal, group: TurnGroupID) -> Option<(Duration, Duration)> {
+    for priority in vec![TurnPriority::Protected, TurnPriority::Yield] {
+        let allowed: Vec<bool> = signal
+            .phases
+            .iter()
+            .map(|p| p.get_priority_of_group(group) >= priority)
+            .collect();
+        if allowed.iter().all(|x| *x) {
+            return Some((Duration::ZERO, cycle_length_of(signal)));
+        }
+
+        let mut starts = Vec::new();
+        let mut t = Duration::ZERO;
+        for p in &signal.phases {
+            starts.push(t);
+            t += p.duration;
+        }
+
+        let n = signal.phases.len();
+        let mut best: Option<(Duration, Duration)> = None;
+        for idx in 0..n {
+            if !allowed[idx] || allowed[(idx + n - 1) % n] {
+                continue;
+            }
+            let mut length = Duration::ZERO;
+            let mut j = idx;
+            while allowed[j] {
+                length += signal.phases[j].duration;
+                j = (j + 1) % n;
+            }
+            if best.map(|(_, l)| length > l).unwrap_or(true) {
+                best = Some((starts[idx], length));
+            }
+        }
+        if best.is_some() {
+            return best;
+        }
+    }
+    None
+}
+
+fn modulo(x: Duration, m: Duration) -> Duration {
+    let result = x % m;
+    if result < Duration::ZERO {
+        result + m
+    } else {
+        result
+    }
+}
@@ -9,6 +9,9 @@ pub struct ControlTrafficSignal {
     pub id: IntersectionID,
     pub phases: Vec<Phase>,
     pub offset: Duration,
+    // If set, buses can hold the green or end a red early.
+    #[serde(default)]
+    pub transit_priority: Option<TransitPriority>,
 
     #[serde(
         serialize_with = "serialize_btreemap",
@@ -21,7 +24,34 @@ pub struct ControlTrafficSignal {
 pub struct Phase {
     pub protected_groups: BTreeSet<TurnGroupID>,
     pub yield_groups: BTreeSet<TurnGroupID>,
+    // Fixed-time phases always last this long. Actuated phases only use this as a nominal value,
+    // for the purposes of drawing and figuring out the starting phase.
     pub duration: Duration,
+    #[serde(default)]
+    pub actuated: Option<ActuatedTiming>,
+}
+
+// Instead of lasting a fixed duration, an actuated phase responds to vehicles and pedestrians
+// waiting for it.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct ActuatedTiming {
+    // Always stay green at least this long.
+    pub min_green: Duration,
+    // Never stay green longer than this, even if more traffic keeps arriving.
+    pub max_green: Duration,
+    // After min_green, stay green as long as somebody new arrives within this gap.
+    pub extension: Duration,
+    // If nobody's waiting for this phase when it would start, skip it entirely.
+    pub skip_if_no_demand: bool,
+}
+
+// When a bus is approaching, the signal bends its timing a bit, within these bounds.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct TransitPriority {
+    // Stay green at most this much longer than usual, if a bus is about to arrive.
+    pub max_green_extension: Duration,
+    // End a phase that doesn't serve the bus at most this much earlier than usual.
+    pub max_red_truncation: Duration,
 }
 
 impl ControlTrafficSignal {
@@ -108,6 +138,15 @@ impl ControlTrafficSignal {
         }
 
         for phase in &self.phases {
+            if let Some(ref actuated) = phase.actuated {
+                if actuated.min_green > actuated.max_green || actuated.max_green == Duration::ZERO {
+                    return Err(format!(
+                        "Traffic signal {} has an actuated phase with min green {}, max green {}",
+                        self.id, actuated.min_green, actuated.max_green
+                    ));
+                }
+            }
+
             // Do any of the priority groups in one phase conflict?
             for g1 in phase.protected_groups.iter().map(|g| &self.turn_groups[g]) {
                 for g2 in phase.protected_groups.iter().map(|g| &self.turn_groups[g]) {
@@ -164,6 +203,7 @@ impl ControlTrafficSignal {
             id: intersection,
             phases,
             offset: Duration::ZERO,
+            transit_priority: None,
             turn_groups,
         };
         // This must succeed
@@ -192,6 +232,7 @@ impl ControlTrafficSignal {
             id: i,
             phases,
             offset: Duration::ZERO,
+            transit_priority: None,
             turn_groups: TurnGroup::for_i(i, map),
         };
         ts.validate().ok()
@@ -239,6 +280,7 @@ impl ControlTrafficSignal {
             id: i,
             phases,
             offset: Duration::ZERO,
+            transit_priority: None,
             turn_groups,
         };
         ts.validate().ok()
@@ -282,6 +324,7 @@ impl ControlTrafficSignal {
             id: i,
             phases,
             offset: Duration::ZERO,
+            transit_priority: None,
             turn_groups: TurnGroup::for_i(i, map),
         };
         ts.validate().ok()
@@ -324,6 +367,7 @@ impl ControlTrafficSignal {
             id: i,
             phases,
             offset: Duration::ZERO,
+            transit_priority: None,
             turn_groups: TurnGroup::for_i(i, map),
         };
         ts.validate().ok()
@@ -377,6 +421,7 @@ impl ControlTrafficSignal {
             id: i,
             phases,
             offset: Duration::ZERO,
+            transit_priority: None,
             turn_groups: TurnGroup::for_i(i, map),
         };
         ts.validate().ok()
@@ -403,6 +448,7 @@ impl ControlTrafficSignal {
             id: i,
             phases: vec![all_walk, all_yield],
             offset: Duration::ZERO,
+            transit_priority: None,
             turn_groups,
         };
         // This must succeed
@@ -440,6 +486,7 @@ impl ControlTrafficSignal {
             id: i,
             phases,
             offset: Duration::ZERO,
+            transit_priority: None,
             turn_groups,
         };
         ts.validate().ok()
@@ -484,6 +531,15 @@ impl Phase {
             protected_groups: BTreeSet::new(),
             yield_groups: BTreeSet::new(),
             duration: Duration::seconds(30.0),
+            actuated: None,
+        }
+    }
+
+    // The longest this phase could possibly last.
+    pub fn max_duration(&self) -> Duration {
+        match self.actuated {
+            Some(ref actuated) => actuated.max_green,
+            None => self.duration,
         }
     }
 
@@ -552,6 +608,26 @@ impl Phase {
     }
 }
 
+impl ActuatedTiming {
+    pub fn new() -> ActuatedTiming {
+        ActuatedTiming {
+            min_green: Duration::seconds(10.0),
+            max_green: Duration::seconds(60.0),
+            extension: Duration::seconds(3.0),
+            skip_if_no_demand: true,
+        }
+    }
+}
+
+impl TransitPriority {
+    pub fn new() -> TransitPriority {
+        TransitPriority {
+            max_green_extension: Duration::seconds(10.0),
+            max_red_truncation: Duration::seconds(10.0),
+        }
+    }
+}
+
 // Add all possible protected groups to existing phases.
 fn expand_all_phases(phases: &mut Vec<Phase>, turn_groups: &BTreeMap<TurnGroupID, TurnGroup>) {
     for phase in phases.iter_mut() {
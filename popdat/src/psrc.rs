@@ -16,6 +16,8 @@ pub struct Trip {
     pub purpose: (Purpose, Purpose),
     pub trip_time: Duration,
     pub trip_dist: Distance,
+    // (household, person within household)
+    pub person: (usize, usize),
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -39,7 +41,7 @@ pub enum Mode {
     Transit,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
 pub enum Purpose {
     Home,
     Work,
@@ -63,8 +65,25 @@ pub fn import_trips(
 
     let mut trips = Vec::new();
     let (reader, done) = FileWithProgress::new(trips_path)?;
-    for rec in csv::Reader::from_reader(reader).records() {
-        let rec = rec?;
+    let mut reader = csv::Reader::from_reader(reader);
+    // The other columns are fixed, but the household and person IDs are looked up by name.
+    let headers = reader.headers()?.clone();
+    let column = |name: &str| {
+        headers
+            .iter()
+            .position(|h| h == name)
+            .ok_or_else(|| failure::err_msg(format!("{} has no {} column", trips_path, name)))
+    };
+    let (hhno, pno) = (column("hhno")?, column("pno")?);
+    let mut bad_rows = 0;
+    for rec in reader.records() {
+        let rec = match rec {
+            Ok(rec) => rec,
+            Err(_) => {
+                bad_rows += 1;
+                continue;
+            }
+        };
 
         // opcl
         let from = skip_fail!(parcels.get(rec[15].trim_end_matches(".0"))).clone();
@@ -82,9 +101,24 @@ pub fn import_trips(
             continue;
         }
 
-        // deptm
-        let depart_at =
-            Time::START_OF_DAY + Duration::minutes(rec[4].trim_end_matches(".0").parse::<usize>()?);
+        // deptm, travtime, travdist, hhno, pno
+        let parsed = (|| {
+            Some((
+                rec[4].trim_end_matches(".0").parse::<usize>().ok()?,
+                rec[25].parse::<f64>().ok()?,
+                rec[24].parse::<f64>().ok()?,
+                rec[hhno].trim_end_matches(".0").parse::<usize>().ok()?,
+                rec[pno].trim_end_matches(".0").parse::<usize>().ok()?,
+            ))
+        })();
+        let (deptm, travtime, travdist, household, person) = match parsed {
+            Some(x) => x,
+            None => {
+                bad_rows += 1;
+                continue;
+            }
+        };
+        let depart_at = Time::START_OF_DAY + Duration::minutes(deptm);
 
         // mode
         let mode = skip_fail!(get_mode(&rec[13]));
@@ -92,10 +126,9 @@ pub fn import_trips(
         // opurp and dpurp
         let purpose = (get_purpose(&rec[16]), get_purpose(&rec[7]));
 
-        // travtime
-        let trip_time = Duration::f64_minutes(rec[25].parse::<f64>()?);
-        // travdist
-        let trip_dist = Distance::miles(rec[24].parse::<f64>()?);
+        let trip_time = Duration::f64_minutes(travtime);
+        let trip_dist = Distance::miles(travdist);
+        let person = (household, person);
 
         trips.push(Trip {
             from,
@@ -105,10 +138,17 @@ pub fn import_trips(
             mode,
             trip_time,
             trip_dist,
+            person,
         });
     }
     done(timer);
 
+    if bad_rows > 0 {
+        timer.warn(format!(
+            "Skipped {} trips that couldn't be parsed",
+            prettyprint_usize(bad_rows)
+        ));
+    }
     timer.note(format!("{} trips total", prettyprint_usize(trips.len())));
 
     trips.sort_by_key(|t| t.depart_at);
@@ -3,7 +3,7 @@ use crate::PopDat;
 use abstutil::Timer;
 use geom::{Distance, Duration, LonLat, Polygon, Pt2D, Time};
 use map_model::{BuildingID, IntersectionID, Map, PathConstraints, Position};
-use sim::{DrivingGoal, Scenario, SidewalkSpot, SpawnTrip, TripSpec};
+use sim::{DrivingGoal, PersonID, PersonSpec, Scenario, SidewalkSpot, SpawnTrip, TripSpec};
 use std::collections::{BTreeMap, HashMap};
 
 #[derive(Clone, Debug)]
@@ -16,6 +16,8 @@ pub struct Trip {
     // These are an upper bound when TripEndpt::Border is involved.
     pub trip_time: Duration,
     pub trip_dist: Distance,
+    // (household, person within household)
+    pub person: (usize, usize),
 }
 
 #[derive(Clone, Debug)]
@@ -112,6 +114,50 @@ impl Trip {
             }
         }
     }
+
+    // PSRC splits park-and-ride into two trips: driving or biking to a lot, then riding transit
+    // from there. If this trip and the next are such a pair, express them as one trip.
+    fn to_park_and_ride(&self, next: &Trip, map: &Map) -> Option<SpawnTrip> {
+        if self.purpose.1 != Purpose::ParkAndRideTransfer
+            || next.purpose.0 != Purpose::ParkAndRideTransfer
+            || next.mode != Mode::Transit
+        {
+            return None;
+        }
+        let park_near = match self.to {
+            TripEndpt::Building(b) => b,
+            TripEndpt::Border(_, _) => {
+                return None;
+            }
+        };
+        let goal = next.to.end_sidewalk_spot(map);
+        let (stop1, stop2, route) = map.should_use_transit(
+            SidewalkSpot::building(park_near, map).sidewalk_pos,
+            goal.sidewalk_pos,
+        )?;
+        // TODO Handle people driving in from a border too
+        match (self.mode, &self.from) {
+            (Mode::Drive, TripEndpt::Building(b)) => Some(SpawnTrip::DriveThenTransit {
+                depart: self.depart_at,
+                start: *b,
+                park_near,
+                goal,
+                route,
+                stop1,
+                stop2,
+            }),
+            (Mode::Bike, TripEndpt::Building(b)) => Some(SpawnTrip::BikeThenTransit {
+                depart: self.depart_at,
+                start: SidewalkSpot::building(*b, map),
+                park_near,
+                goal,
+                route,
+                stop1,
+                stop2,
+            }),
+            _ => None,
+        }
+    }
 }
 
 impl TripEndpt {
@@ -249,6 +295,7 @@ pub fn clip_trips(map: &Map, timer: &mut Timer) -> (Vec<Trip>, HashMap<BuildingI
             mode: trip.mode,
             trip_time: trip.trip_time,
             trip_dist: trip.trip_dist,
+            person: trip.person,
         };
 
         match (&trip.from, &trip.to) {
@@ -280,13 +327,52 @@ pub fn clip_trips(map: &Map, timer: &mut Timer) -> (Vec<Trip>, HashMap<BuildingI
 pub fn trips_to_scenario(map: &Map, timer: &mut Timer) -> Scenario {
     let (trips, _) = clip_trips(map, timer);
     // TODO Don't clone trips for parallelize
-    let individ_trips = timer
-        .parallelize("turn PSRC trips into SpawnTrips", trips.clone(), |trip| {
-            trip.to_spawn_trip(map)
-        })
-        .into_iter()
-        .flatten()
-        .collect();
+    let spawn_trips = timer.parallelize("turn PSRC trips into SpawnTrips", trips.clone(), |trip| {
+        trip.to_spawn_trip(map)
+    });
+
+    // Chain together trips made by the same person.
+    let mut trips_per_person: BTreeMap<(usize, usize), Vec<(&Trip, SpawnTrip)>> = BTreeMap::new();
+    for (trip, maybe_spawn) in trips.iter().zip(spawn_trips) {
+        if let Some(spawn) = maybe_spawn {
+            trips_per_person
+                .entry(trip.person)
+                .or_insert_with(Vec::new)
+                .push((trip, spawn));
+        }
+    }
+    let mut individ_trips = Vec::new();
+    let mut people = Vec::new();
+    for (_, mut person_trips) in trips_per_person {
+        if person_trips.len() == 1 {
+            individ_trips.push(person_trips.pop().unwrap().1);
+            continue;
+        }
+        person_trips.sort_by_key(|(trip, _)| trip.depart_at);
+        let mut chain = Vec::new();
+        let mut last_arrival: Option<Time> = None;
+        let mut iter = person_trips.into_iter().peekable();
+        while let Some((trip, mut spawn)) = iter.next() {
+            // Keep the planned time between trips, even if the previous one runs late.
+            let dwell = match last_arrival {
+                Some(t) if trip.depart_at > t => trip.depart_at - t,
+                _ => Duration::ZERO,
+            };
+            last_arrival = Some(trip.end_time());
+            if let Some(merged) = iter
+                .peek()
+                .and_then(|(next, _)| trip.to_park_and_ride(next, map))
+            {
+                last_arrival = Some(iter.next().unwrap().0.end_time());
+                spawn = merged;
+            }
+            chain.push((spawn, dwell));
+        }
+        people.push(PersonSpec {
+            id: PersonID(people.len()),
+            trips: chain,
+        });
+    }
 
     // How many parked cars do we need to spawn near each building?
     // TODO This assumes trips are instantaneous. At runtime, somebody might try to use a parked
@@ -322,5 +408,7 @@ pub fn trips_to_scenario(map: &Map, timer: &mut Timer) -> Scenario {
         border_spawn_over_time: Vec::new(),
         individ_trips,
         individ_parked_cars,
+        people,
+        incidents: Vec::new(),
     }
 }
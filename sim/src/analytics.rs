@@ -1,19 +1,31 @@
-use crate::{AgentID, CarID, Event, TripID, TripMode, VehicleType};
+use crate::{
+    AgentID, CarID, ConflictDetector, EmissionFactors, EmissionStats, Event, Incident, TravelTimes,
+    TripID, TripMode, VehicleType,
+};
 use abstutil::Counter;
 use derivative::Derivative;
 use geom::{Distance, Duration, DurationHistogram, PercentageHistogram, Time};
 use map_model::{
-    BusRouteID, BusStopID, IntersectionID, Map, Path, PathRequest, RoadID, Traversable, TurnGroupID,
+    BuildingID, BusRouteID, BusStopID, IntersectionID, LaneID, Map, Path, PathRequest, RoadID,
+    Traversable, TurnGroupID,
 };
 use serde_derive::{Deserialize, Serialize};
 use std::collections::{BTreeMap, VecDeque};
 
+const MAX_TRAVERSAL_TIME: Duration = Duration::const_seconds(3600.0);
+
 #[derive(Serialize, Deserialize, Derivative)]
 pub struct Analytics {
     pub thruput_stats: ThruputStats,
     #[serde(skip_serializing, skip_deserializing)]
     pub(crate) test_expectations: VecDeque<Event>,
     pub bus_arrivals: Vec<(Time, CarID, BusRouteID, BusStopID)>,
+    // For routes with a timetable: arrival time, bus, route, stop, scheduled arrival time
+    pub bus_timetable_arrivals: Vec<(Time, CarID, BusRouteID, BusStopID, Time)>,
+    // Departure time, bus, route, stop, passengers on board
+    pub bus_loads: Vec<(Time, CarID, BusRouteID, BusStopID, usize)>,
+    // People left behind by a full bus
+    pub bus_pass_ups: Vec<(Time, CarID, BusRouteID, BusStopID)>,
     #[serde(skip_serializing, skip_deserializing)]
     pub total_bus_passengers: Counter<BusRouteID>,
     // TODO Hack: No TripMode means aborted
@@ -22,6 +34,23 @@ pub struct Analytics {
     // TODO This subsumes finished_trips
     pub trip_log: Vec<(Time, TripID, Option<PathRequest>, String)>,
     pub intersection_delays: BTreeMap<IntersectionID, Vec<(Time, Duration)>>,
+    // True when the incident started, false when it ended
+    pub incidents: Vec<(Time, Incident, bool)>,
+    pub gridlocks: Vec<(Time, Vec<CarID>, Vec<LaneID>, Vec<IntersectionID>)>,
+    // When a truck finished double-parking, the truck, lane, how long it stopped, and how long the
+    // vehicle behind it was delayed
+    pub double_parking: Vec<(Time, CarID, LaneID, Duration, Duration)>,
+    // When an emergency vehicle arrived, where, and how long it took since dispatch
+    pub emergency_responses: Vec<(Time, CarID, BuildingID, Duration)>,
+    pub travel_times: TravelTimes,
+    pub emissions: EmissionStats,
+    // Near-misses at intersections
+    pub conflicts: ConflictDetector,
+    // Where each vehicle is and when it got there
+    #[serde(skip_serializing, skip_deserializing)]
+    vehicle_entered: BTreeMap<CarID, (Traversable, Time)>,
+    #[serde(skip_serializing, skip_deserializing)]
+    last_pruned: Option<Time>,
 }
 
 #[derive(Serialize, Deserialize, Derivative)]
@@ -33,6 +62,7 @@ pub struct ThruputStats {
 
     raw_per_road: Vec<(Time, TripMode, RoadID)>,
     raw_per_intersection: Vec<(Time, TripMode, IntersectionID)>,
+    raw_per_turn_group: Vec<(Time, TripMode, IntersectionID, TurnGroupID)>,
 
     // Unlike everything else in Analytics, this is just for a moment in time.
     pub demand: BTreeMap<TurnGroupID, usize>,
@@ -46,14 +76,27 @@ impl Analytics {
                 count_per_intersection: Counter::new(),
                 raw_per_road: Vec::new(),
                 raw_per_intersection: Vec::new(),
+                raw_per_turn_group: Vec::new(),
                 demand: BTreeMap::new(),
             },
             test_expectations: VecDeque::new(),
             bus_arrivals: Vec::new(),
+            bus_timetable_arrivals: Vec::new(),
+            bus_loads: Vec::new(),
+            bus_pass_ups: Vec::new(),
             total_bus_passengers: Counter::new(),
             finished_trips: Vec::new(),
             trip_log: Vec::new(),
             intersection_delays: BTreeMap::new(),
+            incidents: Vec::new(),
+            gridlocks: Vec::new(),
+            double_parking: Vec::new(),
+            emergency_responses: Vec::new(),
+            travel_times: TravelTimes::new(Duration::minutes(15)),
+            emissions: EmissionStats::new(EmissionFactors::default_factors()),
+            conflicts: ConflictDetector::new(),
+            vehicle_entered: BTreeMap::new(),
+            last_pruned: None,
         }
     }
 
@@ -66,9 +109,11 @@ impl Analytics {
             let mode = match a {
                 AgentID::Pedestrian(_) => TripMode::Walk,
                 AgentID::Car(c) => match c.1 {
-                    VehicleType::Car => TripMode::Drive,
+                    VehicleType::Car | VehicleType::Truck | VehicleType::Emergency => {
+                        TripMode::Drive
+                    }
                     VehicleType::Bike => TripMode::Bike,
-                    VehicleType::Bus => TripMode::Transit,
+                    VehicleType::Bus | VehicleType::Train => TripMode::Transit,
                 },
             };
 
@@ -90,9 +135,47 @@ impl Analytics {
 
                     if let Some(id) = map.get_turn_group(t) {
                         *self.thruput_stats.demand.entry(id).or_insert(0) -= 1;
+                        if raw_thruput {
+                            self.thruput_stats
+                                .raw_per_turn_group
+                                .push((time, mode, t.parent, id));
+                        }
                     }
                 }
             };
+
+            // Travel times
+            if let AgentID::Car(c) = a {
+                if let Some((prev, entered)) = self.vehicle_entered.insert(c, (to, time)) {
+                    self.travel_times.record(prev, entered, time - entered);
+                }
+            }
+            // Vehicles can vanish without an event saying so, like deleted buses. Anybody
+            // that's been on the same lane or turn this long is probably gone.
+            if self
+                .last_pruned
+                .map(|t| time - t >= MAX_TRAVERSAL_TIME)
+                .unwrap_or(true)
+            {
+                self.last_pruned = Some(time);
+                self.vehicle_entered =
+                    std::mem::replace(&mut self.vehicle_entered, BTreeMap::new())
+                        .into_iter()
+                        .filter(|(_, (_, entered))| time - *entered < MAX_TRAVERSAL_TIME)
+                        .collect();
+            }
+
+            // Safety conflicts
+            self.conflicts.agent_entered(a, to, time, map);
+        }
+        // The vehicle didn't finish crossing the last step, so don't record anything for it.
+        match ev {
+            Event::CarReachedParkingSpot(c, _)
+            | Event::CarOrBikeReachedBorder(c, _)
+            | Event::BikeStoppedAtSidewalk(c, _) => {
+                self.vehicle_entered.remove(&c);
+            }
+            _ => {}
         }
 
         // Test expectations
@@ -105,6 +188,16 @@ impl Analytics {
         if let Event::BusArrivedAtStop(bus, route, stop) = ev {
             self.bus_arrivals.push((time, bus, route, stop));
         }
+        if let Event::BusArrivalVsTimetable(bus, route, stop, scheduled) = ev {
+            self.bus_timetable_arrivals
+                .push((time, bus, route, stop, scheduled));
+        }
+        if let Event::BusLoadLeavingStop(bus, route, stop, load) = ev {
+            self.bus_loads.push((time, bus, route, stop, load));
+        }
+        if let Event::PedPassedUpByBus(_, bus, route, stop) = ev {
+            self.bus_pass_ups.push((time, bus, route, stop));
+        }
 
         // Bus passengers
         if let Event::PedEntersBus(_, _, route) = ev {
@@ -118,6 +211,27 @@ impl Analytics {
             self.finished_trips.push((time, id, None, Duration::ZERO));
         }
 
+        // Double-parked trucks
+        if let Event::DoubleParked(truck, l, dwell, delay) = ev {
+            self.double_parking.push((time, truck, l, dwell, delay));
+        }
+
+        // Emergency response times
+        if let Event::EmergencyResponse(car, b, dt) = ev {
+            self.emergency_responses.push((time, car, b, dt));
+        }
+
+        // Emissions
+        if let Event::VehicleDrove(car, trip, on, ref modes) = ev {
+            self.emissions.record(car.1, trip, on, modes, map);
+        }
+
+        // Where vehicles were partway through turns
+        if let Event::VehicleMovedOnTurn(car, length, turn, start, dist, ref profile) = ev {
+            self.conflicts
+                .vehicle_moved(car, length, turn, start, dist, profile.clone());
+        }
+
         // Intersection delays
         if let Event::IntersectionDelayMeasured(id, delay) = ev {
             self.intersection_delays
@@ -142,6 +256,15 @@ impl Analytics {
             Event::PathAmended(path) => {
                 self.record_demand(&path, map);
             }
+            Event::IncidentStarted(incident) => {
+                self.incidents.push((time, incident, true));
+            }
+            Event::IncidentEnded(incident) => {
+                self.incidents.push((time, incident, false));
+            }
+            Event::Gridlock(cars, lanes, intersections) => {
+                self.gridlocks.push((time, cars, lanes, intersections));
+            }
             _ => {}
         }
     }
@@ -288,6 +411,99 @@ impl Analytics {
         delays_to_stop
     }
 
+    // How irregular the gaps between buses are at each stop: the standard deviation of the time
+    // between arrivals, divided by the average. 0 means perfectly even spacing; bunching makes it
+    // bigger.
+    pub fn bus_headway_cv(&self, now: Time, r: BusRouteID) -> BTreeMap<BusStopID, f64> {
+        let mut arrivals_per_stop: BTreeMap<BusStopID, Vec<Time>> = BTreeMap::new();
+        for (t, _, route, stop) in &self.bus_arrivals {
+            if *t > now {
+                break;
+            }
+            if *route == r {
+                arrivals_per_stop
+                    .entry(*stop)
+                    .or_insert_with(Vec::new)
+                    .push(*t);
+            }
+        }
+
+        let mut results = BTreeMap::new();
+        for (stop, arrivals) in arrivals_per_stop {
+            let headways: Vec<f64> = arrivals
+                .windows(2)
+                .map(|pair| (pair[1] - pair[0]).inner_seconds())
+                .collect();
+            if headways.len() < 2 {
+                continue;
+            }
+            let n = headways.len() as f64;
+            let mean = headways.iter().sum::<f64>() / n;
+            if mean == 0.0 {
+                continue;
+            }
+            let variance = headways.iter().map(|h| (h - mean).powi(2)).sum::<f64>() / n;
+            results.insert(stop, variance.sqrt() / mean);
+        }
+        results
+    }
+
+    // For each stop, a list of (scheduled arrival, how late the bus was). Early buses are negative.
+    pub fn schedule_adherence(
+        &self,
+        now: Time,
+        r: BusRouteID,
+    ) -> BTreeMap<BusStopID, Vec<(Time, Duration)>> {
+        let mut per_stop: BTreeMap<BusStopID, Vec<(Time, Duration)>> = BTreeMap::new();
+        for (t, _, route, stop, scheduled) in &self.bus_timetable_arrivals {
+            if *t > now {
+                break;
+            }
+            if *route == r {
+                per_stop
+                    .entry(*stop)
+                    .or_insert_with(Vec::new)
+                    .push((*scheduled, *t - *scheduled));
+            }
+        }
+        per_stop
+    }
+
+    // For the segment of the route starting at each stop, a list of (departure time, passengers on
+    // board)
+    pub fn bus_load_profile(
+        &self,
+        now: Time,
+        r: BusRouteID,
+    ) -> BTreeMap<BusStopID, Vec<(Time, usize)>> {
+        let mut per_stop: BTreeMap<BusStopID, Vec<(Time, usize)>> = BTreeMap::new();
+        for (t, _, route, stop, load) in &self.bus_loads {
+            if *t > now {
+                break;
+            }
+            if *route == r {
+                per_stop
+                    .entry(*stop)
+                    .or_insert_with(Vec::new)
+                    .push((*t, *load));
+            }
+        }
+        per_stop
+    }
+
+    pub fn bus_pass_ups(&self, now: Time, r: BusRouteID) -> Counter<BusStopID> {
+        let mut cnt = Counter::new();
+        for (t, _, route, stop) in &self.bus_pass_ups {
+            if *t > now {
+                break;
+            }
+            if *route == r {
+                cnt.inc(*stop);
+            }
+        }
+        cnt
+    }
+
     // Slightly misleading -- TripMode::Transit means buses, not pedestrians taking transit
     pub fn throughput_road(
         &self,
@@ -348,6 +564,43 @@ impl Analytics {
         per_mode
     }
 
+    // For every intersection, the number of vehicles per hour crossing each turn group during the
+    // intersection's busiest hour. All groups share one hour, since they compete for the same
+    // cycle. Pedestrians aren't counted.
+    pub fn peak_turn_group_flows(&self) -> BTreeMap<IntersectionID, BTreeMap<TurnGroupID, f64>> {
+        let mut per_hour: BTreeMap<(IntersectionID, usize), BTreeMap<TurnGroupID, usize>> =
+            BTreeMap::new();
+        for (t, m, i, g) in &self.thruput_stats.raw_per_turn_group {
+            if *m == TripMode::Walk {
+                continue;
+            }
+            let hour = (*t - Time::START_OF_DAY) / Duration::hours(1);
+            *per_hour
+                .entry((*i, hour as usize))
+                .or_insert_with(BTreeMap::new)
+                .entry(*g)
+                .or_insert(0) += 1;
+        }
+
+        // Ties go to the earliest hour.
+        let mut peak: BTreeMap<IntersectionID, (usize, BTreeMap<TurnGroupID, usize>)> =
+            BTreeMap::new();
+        for ((i, _), groups) in per_hour {
+            let total: usize = groups.values().sum();
+            if peak.get(&i).map(|(best, _)| total > *best).unwrap_or(true) {
+                peak.insert(i, (total, groups));
+            }
+        }
+        peak.into_iter()
+            .map(|(i, (_, groups))| {
+                (
+                    i,
+                    groups.into_iter().map(|(g, cnt)| (g, cnt as f64)).collect(),
+                )
+            })
+            .collect()
+    }
+
     pub fn get_trip_phases(&self, trip: TripID, map: &Map) -> Vec<TripPhase> {
         let mut phases: Vec<TripPhase> = Vec::new();
         for (t, id, maybe_req, md) in &self.trip_log {
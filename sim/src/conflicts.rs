@@ -0,0 +1,292 @@
+use crate::{AgentID, CarID, SpeedProfile};
+use abstutil::{deserialize_btreemap, serialize_btreemap};
+use geom::{Distance, Duration, Pt2D, Speed, Time};
+use map_model::{IntersectionID, Map, Traversable, Turn, TurnID, TurnType};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// Two agents passing through the same point this close together counts as a conflict.
+pub const MAX_PET: Duration = Duration::const_seconds(5.0);
+// Below this, it's a near-miss.
+pub const SEVERE_PET: Duration = Duration::const_seconds(1.5);
+// Only compare passages through an intersection that finished this recently. Should be longer than
+// anybody takes to cross a turn.
+const REMEMBER_PASSAGES_FOR: Duration = Duration::const_seconds(60.0);
+
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub enum ConflictType {
+    VehicleVehicle,
+    VehiclePedestrian,
+}
+
+// Two agents crossing the same point of an intersection close together in time
+#[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
+pub struct Conflict {
+    pub conflict_type: ConflictType,
+    // The agent that reached the conflict point first, and their turn
+    pub first: (AgentID, TurnID),
+    pub second: (AgentID, TurnID),
+    // When the second agent reached the conflict point
+    pub time: Time,
+    // Post-encroachment time: from the first agent clearing the conflict point to the second one
+    // reaching it
+    pub pet: Duration,
+    // Time-to-collision: when the first agent reached the conflict point, how long the second
+    // agent would take to get there at their speed then. Only known if the second agent was
+    // already moving through their turn.
+    pub ttc: Option<Duration>,
+}
+
+impl Conflict {
+    pub fn is_severe(&self) -> bool {
+        self.pet < SEVERE_PET || self.ttc.map(|dt| dt < SEVERE_PET).unwrap_or(false)
+    }
+}
+
+// Each time a vehicle started moving along a turn: when, how far along, and how it moved from
+// there. Only the last one reaches the end of the turn; the others were cut short by stopping or
+// changing speed.
+type Moves = Vec<(Time, Distance, SpeedProfile)>;
+
+// Someone finishing a turn
+#[derive(Clone, PartialEq, Debug)]
+struct Passage {
+    agent: AgentID,
+    turn: TurnID,
+    enter: Time,
+    exit: Time,
+    // Pedestrians take up no room
+    length: Distance,
+    // Empty for pedestrians, who are assumed to cross at a constant speed
+    moves: Moves,
+}
+
+impl Passage {
+    fn time_at(&self, len: Distance, dist: Distance) -> Time {
+        if self.moves.is_empty() {
+            if len == Distance::ZERO {
+                return self.enter;
+            }
+            return self.enter + (self.exit - self.enter) * (dist / len);
+        }
+        match self.moves.iter().rev().find(|(_, d, _)| *d <= dist) {
+            Some((start, d, profile)) => (*start + profile.time_to_reach(dist - *d)).min(self.exit),
+            None => self.enter,
+        }
+    }
+
+    // Where were they, and how fast were they going?
+    fn progress_at(&self, len: Distance, time: Time) -> (Distance, Speed) {
+        if self.moves.is_empty() {
+            if self.exit == self.enter {
+                return (len, Speed::ZERO);
+            }
+            let total = self.exit - self.enter;
+            let pct = ((time - self.enter) / total).max(0.0).min(1.0);
+            let speed = Speed::meters_per_second(len.inner_meters() / total.inner_seconds());
+            return (len * pct, speed);
+        }
+        match self.moves.iter().rev().find(|(start, _, _)| *start <= time) {
+            Some((start, d, profile)) => {
+                let dt = time - *start;
+                // Past the end of the profile, they're stopped and waiting to move again.
+                let speed = if dt > profile.total_time() {
+                    Speed::ZERO
+                } else {
+                    profile.speed_at(dt)
+                };
+                (*d + profile.dist_at(dt), speed)
+            }
+            None => (Distance::ZERO, Speed::ZERO),
+        }
+    }
+}
+
+// Looks for agents passing through conflicting turns close together in time, using surrogate
+// safety measures instead of actual crashes. Fed by AgentEntersTraversable events.
+#[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
+pub struct ConflictDetector {
+    #[serde(
+        serialize_with = "serialize_btreemap",
+        deserialize_with = "deserialize_btreemap"
+    )]
+    pub per_intersection: BTreeMap<IntersectionID, Vec<Conflict>>,
+
+    // Who's in the middle of a turn, when they started it, how long vehicles are, and how they've
+    // moved so far
+    #[serde(skip_serializing, skip_deserializing)]
+    on_turn: BTreeMap<AgentID, (TurnID, Time, Distance, Moves)>,
+    #[serde(skip_serializing, skip_deserializing)]
+    recent: BTreeMap<IntersectionID, Vec<Passage>>,
+}
+
+impl ConflictDetector {
+    pub fn new() -> ConflictDetector {
+        ConflictDetector {
+            per_intersection: BTreeMap::new(),
+            on_turn: BTreeMap::new(),
+            recent: BTreeMap::new(),
+        }
+    }
+
+    pub fn agent_entered(&mut self, agent: AgentID, on: Traversable, now: Time, map: &Map) {
+        let (turn, enter, length, moves) = match self.on_turn.remove(&agent) {
+            Some(x) => x,
+            None => {
+                if let Traversable::Turn(t) = on {
+                    self.on_turn
+                        .insert(agent, (t, now, Distance::ZERO, Vec::new()));
+                }
+                return;
+            }
+        };
+        match on {
+            Traversable::Lane(_) => {}
+            // They vanished partway through the last turn somehow. Just start over.
+            Traversable::Turn(t) => {
+                self.on_turn
+                    .insert(agent, (t, now, Distance::ZERO, Vec::new()));
+                return;
+            }
+        }
+
+        let passage = Passage {
+            agent,
+            turn,
+            enter,
+            exit: now,
+            length,
+            moves,
+        };
+        let recent = self.recent.entry(turn.parent).or_insert_with(Vec::new);
+        recent.retain(|p| now - p.exit <= REMEMBER_PASSAGES_FOR);
+        for other in recent.iter() {
+            if let Some(c) = find_conflict(other, &passage, map) {
+                self.per_intersection
+                    .entry(turn.parent)
+                    .or_insert_with(Vec::new)
+                    .push(c);
+            }
+        }
+        recent.push(passage);
+    }
+
+    pub fn vehicle_moved(
+        &mut self,
+        car: CarID,
+        length: Distance,
+        turn: TurnID,
+        start: Time,
+        dist: Distance,
+        profile: SpeedProfile,
+    ) {
+        if let Some((t, _, len, moves)) = self.on_turn.get_mut(&AgentID::Car(car)) {
+            if *t == turn {
+                *len = length;
+                moves.push((start, dist, profile));
+            }
+        }
+    }
+
+    pub fn all_conflicts(&self) -> impl Iterator<Item = &Conflict> {
+        self.per_intersection.values().flatten()
+    }
+
+    pub fn count(&self, i: IntersectionID) -> (usize, usize) {
+        match self.per_intersection.get(&i) {
+            Some(list) => (list.len(), list.iter().filter(|c| c.is_severe()).count()),
+            None => (0, 0),
+        }
+    }
+}
+
+fn find_conflict(p1: &Passage, p2: &Passage, map: &Map) -> Option<Conflict> {
+    if p1.agent == p2.agent {
+        return None;
+    }
+    let conflict_type = match (p1.agent, p2.agent) {
+        (AgentID::Pedestrian(_), AgentID::Pedestrian(_)) => {
+            return None;
+        }
+        (AgentID::Car(_), AgentID::Car(_)) => ConflictType::VehicleVehicle,
+        _ => ConflictType::VehiclePedestrian,
+    };
+    let t1 = map.get_t(p1.turn);
+    let t2 = map.get_t(p2.turn);
+    if !t1.conflicts_with(t2) {
+        return None;
+    }
+    // Pedestrians only tangle with vehicles on crosswalks, not at shared corners.
+    if conflict_type == ConflictType::VehiclePedestrian
+        && t1.turn_type != TurnType::Crosswalk
+        && t2.turn_type != TurnType::Crosswalk
+    {
+        return None;
+    }
+
+    // Merging into the same lane counts as conflicting at the end.
+    let pt = match t1.geom.intersection(&t2.geom) {
+        Some((pt, _)) => pt,
+        None => t1.geom.last_pt(),
+    };
+    let (len1, dist1) = dist_along(t1, pt);
+    let (len2, dist2) = dist_along(t2, pt);
+    let at1 = p1.time_at(len1, dist1);
+    let at2 = p2.time_at(len2, dist2);
+    let (first, second, first_at, second_at, first_len, second_len, second_dist) = if at1 <= at2 {
+        (p1, p2, at1, at2, len1, len2, dist2)
+    } else {
+        (p2, p1, at2, at1, len2, len1, dist1)
+    };
+    // The first agent's back has to clear the point too. If they're stopped right there, the best
+    // guess is when their front left the turn.
+    let first_clears = if first.length == Distance::ZERO {
+        first_at
+    } else {
+        let (_, speed) = first.progress_at(first_len, first_at);
+        if speed > Speed::ZERO {
+            first_at + first.length / speed
+        } else {
+            first.exit.max(first_at)
+        }
+    };
+    // They overlapped, which is as bad as it gets.
+    let pet = if second_at > first_clears {
+        second_at - first_clears
+    } else {
+        Duration::ZERO
+    };
+    if pet > MAX_PET {
+        return None;
+    }
+    let ttc = if second.enter <= first_at {
+        let (dist, speed) = second.progress_at(second_len, first_at);
+        if speed > Speed::ZERO && dist < second_dist {
+            Some((second_dist - dist) / speed)
+        } else {
+            None
+        }
+    } else {
+        None
+    };
+
+    Some(Conflict {
+        conflict_type,
+        first: (first.agent, first.turn),
+        second: (second.agent, second.turn),
+        time: second_at,
+        pet,
+        ttc,
+    })
+}
+
+// How long is the turn, and how far along it is some point?
+fn dist_along(turn: &Turn, pt: Pt2D) -> (Distance, Distance) {
+    let len = turn.geom.length();
+    let dist = turn
+        .geom
+        .dist_along_of_point(pt)
+        .map(|(d, _)| d)
+        .unwrap_or(len);
+    (len, dist)
+}
@@ -0,0 +1,342 @@
+use crate::{TripID, VehicleType};
+use abstutil::{deserialize_btreemap, serialize_btreemap};
+use geom::{Distance, Duration, Speed};
+use map_model::{IntersectionID, Map, RoadID, Traversable};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ops;
+
+// Roughly how much CO2 burning each fuel releases per unit of energy
+const GASOLINE_CO2_PER_MJ: f64 = 73.0;
+const DIESEL_CO2_PER_MJ: f64 = 74.0;
+
+// Grams of CO2 and NOx, and megajoules of energy used
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Debug)]
+pub struct Emissions {
+    pub co2: f64,
+    pub nox: f64,
+    pub energy: f64,
+}
+
+impl Emissions {
+    pub const ZERO: Emissions = Emissions {
+        co2: 0.0,
+        nox: 0.0,
+        energy: 0.0,
+    };
+
+    fn lerp(self, other: Emissions, pct: f64) -> Emissions {
+        self + (other + self * -1.0) * pct
+    }
+
+    pub fn describe(&self) -> String {
+        format!(
+            "{:.1} kg CO2, {:.1} g NOx, {:.1} MJ",
+            self.co2 / 1000.0,
+            self.nox,
+            self.energy
+        )
+    }
+}
+
+impl ops::Add for Emissions {
+    type Output = Emissions;
+
+    fn add(self, other: Emissions) -> Emissions {
+        Emissions {
+            co2: self.co2 + other.co2,
+            nox: self.nox + other.nox,
+            energy: self.energy + other.energy,
+        }
+    }
+}
+
+impl ops::AddAssign for Emissions {
+    fn add_assign(&mut self, other: Emissions) {
+        *self = *self + other;
+    }
+}
+
+impl ops::Mul<f64> for Emissions {
+    type Output = Emissions;
+
+    fn mul(self, scalar: f64) -> Emissions {
+        Emissions {
+            co2: self.co2 * scalar,
+            nox: self.nox * scalar,
+            energy: self.energy * scalar,
+        }
+    }
+}
+
+// How a vehicle spent some stretch of time. Each moving mode is the time spent and distance
+// covered.
+#[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
+pub struct DrivingModes {
+    // Stopped with the engine running
+    pub idle: Duration,
+    pub accelerating: (Duration, Distance),
+    pub cruising: (Duration, Distance),
+    pub braking: (Duration, Distance),
+}
+
+impl DrivingModes {
+    pub fn idle(dt: Duration) -> DrivingModes {
+        DrivingModes {
+            idle: dt,
+            accelerating: (Duration::ZERO, Distance::ZERO),
+            cruising: (Duration::ZERO, Distance::ZERO),
+            braking: (Duration::ZERO, Distance::ZERO),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.idle == Duration::ZERO
+            && self.accelerating.0 == Duration::ZERO
+            && self.cruising.0 == Duration::ZERO
+            && self.braking.0 == Duration::ZERO
+    }
+}
+
+// Emission factors for one type of vehicle
+#[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
+pub struct EmissionTable {
+    // Per hour spent idling
+    pub idle: Emissions,
+    // Per km driven at some average speed, sorted by speed. Rates in between are interpolated.
+    pub per_km: Vec<(Speed, Emissions)>,
+    // Accelerating costs this much more than cruising at the same average speed. Braking is
+    // mostly coasting, so it's some fraction of cruising.
+    pub accel_factor: f64,
+    pub brake_factor: f64,
+}
+
+impl EmissionTable {
+    // (km/h, g CO2/km, g NOx/km) for a vehicle burning fuel. Idling is (g CO2/h, g NOx/h).
+    fn combustion(
+        co2_per_mj: f64,
+        idle: (f64, f64),
+        per_km: Vec<(f64, f64, f64)>,
+        accel_factor: f64,
+    ) -> EmissionTable {
+        let emissions = |co2: f64, nox: f64| Emissions {
+            co2,
+            nox,
+            energy: co2 / co2_per_mj,
+        };
+        EmissionTable {
+            idle: emissions(idle.0, idle.1),
+            per_km: per_km
+                .into_iter()
+                .map(|(kmph, co2, nox)| (Speed::meters_per_second(kmph / 3.6), emissions(co2, nox)))
+                .collect(),
+            accel_factor,
+            brake_factor: 0.3,
+        }
+    }
+
+    fn rate_per_km(&self, speed: Speed) -> Emissions {
+        match self.per_km.iter().position(|(s, _)| *s > speed) {
+            Some(0) => self.per_km[0].1,
+            Some(idx) => {
+                let (s1, e1) = self.per_km[idx - 1];
+                let (s2, e2) = self.per_km[idx];
+                let pct = (speed.inner_meters_per_second() - s1.inner_meters_per_second())
+                    / (s2.inner_meters_per_second() - s1.inner_meters_per_second());
+                e1.lerp(e2, pct)
+            }
+            None => self
+                .per_km
+                .last()
+                .map(|(_, e)| *e)
+                .unwrap_or(Emissions::ZERO),
+        }
+    }
+}
+
+// Emission factors for every type of vehicle. Types that aren't listed, like bikes, don't emit
+// anything.
+#[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
+pub struct EmissionFactors {
+    #[serde(
+        serialize_with = "serialize_btreemap",
+        deserialize_with = "deserialize_btreemap"
+    )]
+    pub tables: BTreeMap<VehicleType, EmissionTable>,
+}
+
+impl EmissionFactors {
+    // Very rough average-speed factors for a mixed fleet. Load better ones for a particular place
+    // with SimOptions.
+    pub fn default_factors() -> EmissionFactors {
+        let mut tables = BTreeMap::new();
+        tables.insert(
+            VehicleType::Car,
+            EmissionTable::combustion(
+                GASOLINE_CO2_PER_MJ,
+                (2800.0, 2.0),
+                vec![
+                    (10.0, 300.0, 0.25),
+                    (30.0, 190.0, 0.12),
+                    (50.0, 160.0, 0.08),
+                    (80.0, 140.0, 0.07),
+                    (110.0, 160.0, 0.09),
+                ],
+                1.8,
+            ),
+        );
+        tables.insert(
+            VehicleType::Emergency,
+            EmissionTable::combustion(
+                GASOLINE_CO2_PER_MJ,
+                (4000.0, 3.0),
+                vec![
+                    (10.0, 450.0, 0.4),
+                    (30.0, 290.0, 0.2),
+                    (50.0, 240.0, 0.13),
+                    (80.0, 210.0, 0.11),
+                    (110.0, 240.0, 0.14),
+                ],
+                1.8,
+            ),
+        );
+        tables.insert(
+            VehicleType::Truck,
+            EmissionTable::combustion(
+                DIESEL_CO2_PER_MJ,
+                (4500.0, 40.0),
+                vec![
+                    (10.0, 1100.0, 8.0),
+                    (30.0, 700.0, 5.0),
+                    (50.0, 600.0, 4.0),
+                    (80.0, 550.0, 3.5),
+                    (110.0, 650.0, 4.0),
+                ],
+                2.2,
+            ),
+        );
+        tables.insert(
+            VehicleType::Bus,
+            EmissionTable::combustion(
+                DIESEL_CO2_PER_MJ,
+                (5000.0, 50.0),
+                vec![
+                    (10.0, 1600.0, 12.0),
+                    (30.0, 1100.0, 8.0),
+                    (50.0, 950.0, 6.0),
+                    (80.0, 900.0, 5.5),
+                ],
+                2.2,
+            ),
+        );
+        // Electric, so nothing from the tailpipe. Power for heating and lighting while stopped.
+        tables.insert(
+            VehicleType::Train,
+            EmissionTable {
+                idle: Emissions {
+                    co2: 0.0,
+                    nox: 0.0,
+                    energy: 20.0,
+                },
+                per_km: vec![(
+                    Speed::ZERO,
+                    Emissions {
+                        co2: 0.0,
+                        nox: 0.0,
+                        energy: 15.0,
+                    },
+                )],
+                accel_factor: 1.5,
+                brake_factor: 0.0,
+            },
+        );
+        EmissionFactors { tables }
+    }
+
+    pub fn estimate(&self, vt: VehicleType, modes: &DrivingModes) -> Emissions {
+        let table = match self.tables.get(&vt) {
+            Some(t) => t,
+            None => {
+                return Emissions::ZERO;
+            }
+        };
+        let mut total = table.idle * (modes.idle.inner_seconds() / 3600.0);
+        for ((dt, dist), factor) in vec![
+            (modes.accelerating, table.accel_factor),
+            (modes.cruising, 1.0),
+            (modes.braking, table.brake_factor),
+        ] {
+            if dt == Duration::ZERO {
+                continue;
+            }
+            let speed = Speed::meters_per_second(dist.inner_meters() / dt.inner_seconds());
+            total += table.rate_per_km(speed) * (factor * dist.inner_meters() / 1000.0);
+        }
+        total
+    }
+}
+
+// Emissions estimated from how each vehicle drove, totalled per road, intersection, trip, and for
+// the whole map. Collected by Analytics.
+#[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
+pub struct EmissionStats {
+    pub factors: EmissionFactors,
+    pub total: Emissions,
+    #[serde(
+        serialize_with = "serialize_btreemap",
+        deserialize_with = "deserialize_btreemap"
+    )]
+    pub per_road: BTreeMap<RoadID, Emissions>,
+    #[serde(
+        serialize_with = "serialize_btreemap",
+        deserialize_with = "deserialize_btreemap"
+    )]
+    pub per_intersection: BTreeMap<IntersectionID, Emissions>,
+    #[serde(
+        serialize_with = "serialize_btreemap",
+        deserialize_with = "deserialize_btreemap"
+    )]
+    pub per_trip: BTreeMap<TripID, Emissions>,
+}
+
+impl EmissionStats {
+    pub fn new(factors: EmissionFactors) -> EmissionStats {
+        EmissionStats {
+            factors,
+            total: Emissions::ZERO,
+            per_road: BTreeMap::new(),
+            per_intersection: BTreeMap::new(),
+            per_trip: BTreeMap::new(),
+        }
+    }
+
+    pub fn record(
+        &mut self,
+        vt: VehicleType,
+        trip: TripID,
+        on: Traversable,
+        modes: &DrivingModes,
+        map: &Map,
+    ) {
+        let amount = self.factors.estimate(vt, modes);
+        if amount == Emissions::ZERO {
+            return;
+        }
+        self.total += amount;
+        match on {
+            Traversable::Lane(l) => {
+                *self
+                    .per_road
+                    .entry(map.get_l(l).parent)
+                    .or_insert(Emissions::ZERO) += amount;
+            }
+            Traversable::Turn(t) => {
+                *self
+                    .per_intersection
+                    .entry(t.parent)
+                    .or_insert(Emissions::ZERO) += amount;
+            }
+        }
+        *self.per_trip.entry(trip).or_insert(Emissions::ZERO) += amount;
+    }
+}
@@ -1,7 +1,11 @@
-use crate::{AgentID, CarID, ParkingSpot, PedestrianID, TripID, TripMode};
-use geom::Duration;
+use crate::{
+    AgentID, CarID, DrivingModes, Incident, ParkingSpot, PedestrianID, SpeedProfile, TripID,
+    TripMode,
+};
+use geom::{Distance, Duration, Time};
 use map_model::{
     BuildingID, BusRouteID, BusStopID, IntersectionID, LaneID, Path, PathRequest, Traversable,
+    TurnID,
 };
 use serde_derive::{Deserialize, Serialize};
 
@@ -12,6 +16,10 @@ pub enum Event {
 
     BusArrivedAtStop(CarID, BusRouteID, BusStopID),
     BusDepartedFromStop(CarID, BusRouteID, BusStopID),
+    // Only for routes with a timetable. The time is when the bus was scheduled to arrive.
+    BusArrivalVsTimetable(CarID, BusRouteID, BusStopID, Time),
+    // How many passengers are on board heading to the next stop
+    BusLoadLeavingStop(CarID, BusRouteID, BusStopID, usize),
 
     PedReachedParkingSpot(PedestrianID, ParkingSpot),
     PedReachedBuilding(PedestrianID, BuildingID),
@@ -19,16 +27,34 @@ pub enum Event {
     PedReachedBusStop(PedestrianID, BusStopID),
     PedEntersBus(PedestrianID, CarID, BusRouteID),
     PedLeavesBus(PedestrianID, CarID, BusRouteID),
+    // The bus was full
+    PedPassedUpByBus(PedestrianID, CarID, BusRouteID, BusStopID),
 
     BikeStoppedAtSidewalk(CarID, LaneID),
+    // A delivery truck stopped in the lane: how long it stayed, and how long the vehicle behind it
+    // was stuck
+    DoubleParked(CarID, LaneID, Duration, Duration),
+    // An emergency vehicle reached a building, and how long it took since dispatch
+    EmergencyResponse(CarID, BuildingID, Duration),
 
     AgentEntersTraversable(AgentID, Traversable),
+    // How a vehicle drove while it was on something, for estimating emissions
+    VehicleDrove(CarID, TripID, Traversable, DrivingModes),
+    // A vehicle of some length started moving at some time and distance along a turn, following
+    // this profile
+    VehicleMovedOnTurn(CarID, Distance, TurnID, Time, Distance, SpeedProfile),
     IntersectionDelayMeasured(IntersectionID, Duration),
 
     TripFinished(TripID, TripMode, Duration),
     TripAborted(TripID),
     TripPhaseStarting(TripID, Option<PathRequest>, String),
 
+    IncidentStarted(Incident),
+    IncidentEnded(Incident),
+
+    // Vehicles waiting on each other in a cycle, and the lanes and intersections involved
+    Gridlock(Vec<CarID>, Vec<LaneID>, Vec<IntersectionID>),
+
     // Just use for parking replanning. Not happy about copying the full path in here, but the way
     // to plumb info into Analytics is Event.
     PathAmended(Path),
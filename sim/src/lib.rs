@@ -1,4 +1,6 @@
 mod analytics;
+mod conflicts;
+mod emissions;
 mod events;
 mod make;
 mod mechanics;
@@ -7,21 +9,28 @@ mod router;
 mod scheduler;
 mod sim;
 mod transit;
+mod travel_times;
 mod trips;
 
 pub use self::analytics::{Analytics, TripPhase};
+pub use self::conflicts::{Conflict, ConflictDetector, ConflictType, MAX_PET, SEVERE_PET};
+pub use self::emissions::{DrivingModes, EmissionFactors, EmissionStats, EmissionTable, Emissions};
 pub use self::events::Event;
 pub use self::make::{
-    ABTest, BorderSpawnOverTime, OriginDestination, Scenario, SeedParkedCars, SimFlags,
-    SpawnOverTime, SpawnTrip, TripSpawner, TripSpec,
+    ABTest, BorderSpawnOverTime, Incident, ModeChoiceModel, OriginDestination, PersonSpec,
+    Scenario, ScheduledIncident, SeedParkedCars, SimFlags, SpawnOverTime, SpawnTrip, TripSpawner,
+    TripSpec,
 };
+pub use self::mechanics::{crowded_speed, find_cycles, SpeedProfile};
 pub(crate) use self::mechanics::{
     DrivingSimState, IntersectionSimState, ParkingSimState, WalkingSimState,
 };
 pub(crate) use self::router::{ActionAtEnd, Router};
 pub(crate) use self::scheduler::{Command, Scheduler};
 pub use self::sim::{Sim, SimOptions};
+pub use self::transit::HoldingStrategy;
 pub(crate) use self::transit::TransitSimState;
+pub use self::travel_times::{TravelTimes, TripPaths};
 pub use self::trips::{TripCount, TripResult};
 pub use self::trips::{TripEnd, TripMode, TripStart};
 pub(crate) use self::trips::{TripLeg, TripManager};
@@ -46,6 +55,15 @@ pub const MIN_CAR_LENGTH: Distance = Distance::const_meters(4.5);
 pub const MAX_CAR_LENGTH: Distance = Distance::const_meters(6.5);
 // Note this is more than MAX_CAR_LENGTH
 pub const BUS_LENGTH: Distance = Distance::const_meters(12.5);
+pub const BUS_SEATED_CAPACITY: usize = 40;
+pub const BUS_STANDING_CAPACITY: usize = 20;
+// Two light rail cars coupled together
+pub const TRAIN_LENGTH: Distance = Distance::const_meters(58.0);
+pub const TRAIN_SEATED_CAPACITY: usize = 148;
+pub const TRAIN_STANDING_CAPACITY: usize = 240;
+// Box trucks and vans making deliveries. Longer than a parking spot.
+pub const MIN_TRUCK_LENGTH: Distance = Distance::const_meters(8.0);
+pub const MAX_TRUCK_LENGTH: Distance = Distance::const_meters(12.0);
 
 // At all speeds (including at rest), cars must be at least this far apart, measured from front of
 // one car to the back of the other.
@@ -67,6 +85,9 @@ impl fmt::Display for CarID {
                 VehicleType::Car => "car",
                 VehicleType::Bus => "bus",
                 VehicleType::Bike => "bike",
+                VehicleType::Train => "train",
+                VehicleType::Truck => "truck",
+                VehicleType::Emergency => "emergency vehicle",
             }
         )
     }
@@ -114,11 +135,23 @@ impl fmt::Display for TripID {
     }
 }
 
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct PersonID(pub usize);
+
+impl fmt::Display for PersonID {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PersonID({0})", self.0)
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Hash, PartialOrd, Ord)]
 pub enum VehicleType {
     Car,
     Bus,
     Bike,
+    Train,
+    Truck,
+    Emergency,
 }
 
 impl VehicleType {
@@ -127,10 +160,39 @@ impl VehicleType {
             VehicleType::Car => PathConstraints::Car,
             VehicleType::Bus => PathConstraints::Bus,
             VehicleType::Bike => PathConstraints::Bike,
+            VehicleType::Train => PathConstraints::Train,
+            VehicleType::Truck => PathConstraints::Car,
+            VehicleType::Emergency => PathConstraints::Car,
+        }
+    }
+
+    // Serves a bus route
+    pub fn is_transit(self) -> bool {
+        self == VehicleType::Bus || self == VehicleType::Train
+    }
+
+    // (max acceleration, comfortable braking) in meters per second^2. Roughly the usual IDM
+    // parameters.
+    pub fn default_accel_limits(self) -> (f64, f64) {
+        match self {
+            VehicleType::Car => (2.0, 2.5),
+            VehicleType::Bus => (1.0, 1.5),
+            VehicleType::Bike => (1.0, 2.0),
+            VehicleType::Train => (1.0, 1.3),
+            VehicleType::Truck => (1.2, 2.0),
+            VehicleType::Emergency => (2.5, 3.0),
         }
     }
 }
 
+// Older savestates and scenarios don't have acceleration limits; assume a car.
+fn default_max_accel() -> f64 {
+    VehicleType::Car.default_accel_limits().0
+}
+fn default_max_decel() -> f64 {
+    VehicleType::Car.default_accel_limits().1
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Vehicle {
     pub id: CarID,
@@ -138,6 +200,11 @@ pub struct Vehicle {
     pub vehicle_type: VehicleType,
     pub length: Distance,
     pub max_speed: Option<Speed>,
+    // In meters per second^2
+    #[serde(default = "default_max_accel")]
+    pub max_accel: f64,
+    #[serde(default = "default_max_decel")]
+    pub max_decel: f64,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
@@ -145,6 +212,11 @@ pub struct VehicleSpec {
     pub vehicle_type: VehicleType,
     pub length: Distance,
     pub max_speed: Option<Speed>,
+    // In meters per second^2
+    #[serde(default = "default_max_accel")]
+    pub max_accel: f64,
+    #[serde(default = "default_max_decel")]
+    pub max_decel: f64,
 }
 
 impl VehicleSpec {
@@ -155,6 +227,8 @@ impl VehicleSpec {
             vehicle_type: self.vehicle_type,
             length: self.length,
             max_speed: self.max_speed,
+            max_accel: self.max_accel,
+            max_decel: self.max_decel,
         }
     }
 }
@@ -216,7 +290,9 @@ impl DrivingGoal {
                     let l = map.find_biking_lane_near_building(*b);
                     Position::new(l, map.get_l(l).length() / 2.0)
                 }
-                PathConstraints::Bus | PathConstraints::Pedestrian => unreachable!(),
+                PathConstraints::Bus | PathConstraints::Train | PathConstraints::Pedestrian => {
+                    unreachable!()
+                }
             },
             DrivingGoal::Border(_, l) => Position::new(*l, map.get_l(*l).length()),
         }
@@ -518,6 +594,7 @@ impl TripPositions {
 // kind of an ezgui concept.
 impl Cloneable for ABTest {}
 impl Cloneable for CarID {}
+impl Cloneable for HoldingStrategy {}
 impl Cloneable for Scenario {}
 impl Cloneable for TripID {}
 impl Cloneable for TripMode {}
@@ -1,4 +1,4 @@
-use crate::{Scenario, Sim, SimOptions};
+use crate::{HoldingStrategy, Scenario, Sim, SimOptions};
 use abstutil::CmdArgs;
 use geom::Duration;
 use map_model::{Map, MapEdits};
@@ -29,6 +29,16 @@ impl SimFlags {
                 use_freeform_policy_everywhere: args.enabled("--freeform_policy"),
                 disable_block_the_box: args.enabled("--disable_block_the_box"),
                 recalc_lanechanging: !args.enabled("--dont_recalc_lc"),
+                reroute_blocked_after: args
+                    .optional_parse("--reroute_blocked_after", Duration::parse),
+                midblock_lanechanging: args.enabled("--midblock_lanechanging"),
+                check_gridlock_every: args
+                    .optional_parse("--check_gridlock_every", Duration::parse),
+                resolve_gridlock: args.enabled("--resolve_gridlock"),
+                travel_times_from: args.optional("--travel_times_from"),
+                paths_from: args.optional("--paths_from"),
+                bus_holding: args.optional_parse("--bus_holding", HoldingStrategy::parse),
+                emission_factors_from: args.optional("--emission_factors_from"),
             },
         }
     }
@@ -1,11 +1,14 @@
 mod a_b_test;
 mod load;
+mod mode_choice;
 mod scenario;
 mod spawner;
 
 pub use self::a_b_test::ABTest;
 pub use self::load::SimFlags;
+pub use self::mode_choice::ModeChoiceModel;
 pub use self::scenario::{
-    BorderSpawnOverTime, OriginDestination, Scenario, SeedParkedCars, SpawnOverTime, SpawnTrip,
+    BorderSpawnOverTime, Incident, OriginDestination, PersonSpec, Scenario, ScheduledIncident,
+    SeedParkedCars, SpawnOverTime, SpawnTrip,
 };
 pub use self::spawner::{TripSpawner, TripSpec};
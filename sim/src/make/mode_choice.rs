@@ -0,0 +1,126 @@
+use crate::{TravelTimes, TripMode};
+use geom::{Duration, Speed, Time};
+use map_model::{Map, PathConstraints, PathRequest, Position};
+use rand::Rng;
+use rand_xorshift::XorShiftRng;
+use serde_derive::{Deserialize, Serialize};
+
+// A multinomial logit model. The utility of each mode is a constant, minus the weighted travel
+// time and cost. Each agent draws a mode with probability proportional to exp(utility), so
+// changing the map to make some mode faster shifts the mode share.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ModeChoiceModel {
+    // Utility lost per minute of travel
+    pub per_minute: f64,
+    // Utility lost per dollar spent
+    pub per_dollar: f64,
+
+    // Preference for each mode, all else being equal
+    pub walk_constant: f64,
+    pub bike_constant: f64,
+    pub drive_constant: f64,
+    pub transit_constant: f64,
+
+    // Gas, parking, etc
+    pub drive_cost: f64,
+    pub transit_fare: f64,
+    // For routes without a timetable, we don't know when the bus will come, so assume this.
+    pub transit_wait: Duration,
+}
+
+impl ModeChoiceModel {
+    // TODO Calibrate these against real mode share.
+    pub fn new() -> ModeChoiceModel {
+        ModeChoiceModel {
+            per_minute: 0.1,
+            per_dollar: 0.2,
+            walk_constant: 0.0,
+            bike_constant: -1.0,
+            drive_constant: 1.0,
+            transit_constant: -0.5,
+            drive_cost: 3.0,
+            transit_fare: 2.75,
+            transit_wait: Duration::minutes(5),
+        }
+    }
+
+    fn utility(&self, mode: TripMode, time: Duration) -> f64 {
+        let (constant, cost) = match mode {
+            TripMode::Walk => (self.walk_constant, 0.0),
+            TripMode::Bike => (self.bike_constant, 0.0),
+            TripMode::Drive => (self.drive_constant, self.drive_cost),
+            TripMode::Transit => (self.transit_constant, self.transit_fare),
+        };
+        constant - self.per_minute * (time.inner_seconds() / 60.0) - self.per_dollar * cost
+    }
+
+    // Returns an index into the options.
+    pub fn choose(&self, options: &[(TripMode, Duration)], rng: &mut XorShiftRng) -> usize {
+        assert!(!options.is_empty());
+        let utilities: Vec<f64> = options
+            .iter()
+            .map(|(mode, time)| self.utility(*mode, *time))
+            .collect();
+        // Subtract the best utility first, so exp() doesn't overflow.
+        let best = utilities
+            .iter()
+            .cloned()
+            .fold(std::f64::NEG_INFINITY, f64::max);
+        let weights: Vec<f64> = utilities.into_iter().map(|u| (u - best).exp()).collect();
+
+        let mut x = rng.gen_range(0.0, weights.iter().sum::<f64>());
+        for (idx, w) in weights.iter().enumerate() {
+            if x < *w {
+                return idx;
+            }
+            x -= w;
+        }
+        options.len() - 1
+    }
+}
+
+pub(crate) fn walking_time(
+    start: Position,
+    end: Position,
+    speed: Speed,
+    map: &Map,
+) -> Option<Duration> {
+    let path = map.pathfind(PathRequest {
+        start,
+        end,
+        constraints: PathConstraints::Pedestrian,
+    })?;
+    Some(path.total_length() / speed)
+}
+
+// Uses how long vehicles took around the time they'd reach each step, if anything's been
+// observed. Otherwise assumes the vehicle goes the speed limit (or its own max speed).
+pub(crate) fn driving_time(
+    req: PathRequest,
+    max_speed: Option<Speed>,
+    depart: Time,
+    travel_times: Option<&TravelTimes>,
+    map: &Map,
+) -> Option<Duration> {
+    let path = if let Some(tt) = travel_times {
+        tt.pathfind(req, depart, map)?
+    } else {
+        map.pathfind(req)?
+    };
+    let mut total = Duration::ZERO;
+    for step in path.get_steps() {
+        let on = step.as_traversable();
+        let speed = if let Some(s) = max_speed {
+            on.speed_limit(map).min(s)
+        } else {
+            on.speed_limit(map)
+        };
+        let free_flow = on.length(map) / speed;
+        total += match travel_times.and_then(|tt| tt.average(on, depart + total)) {
+            // Slow vehicles can't go faster just because others did
+            Some(observed) => observed.max(free_flow),
+            None => free_flow,
+        };
+    }
+    Some(total)
+}
@@ -1,12 +1,14 @@
+use crate::make::mode_choice::{driving_time, walking_time};
 use crate::{
-    CarID, DrivingGoal, ParkingSpot, SidewalkSpot, Sim, TripSpec, VehicleSpec, VehicleType,
-    BIKE_LENGTH, MAX_CAR_LENGTH, MIN_CAR_LENGTH,
+    CarID, DrivingGoal, ModeChoiceModel, ParkingSpot, PersonID, SidewalkPOI, SidewalkSpot, Sim,
+    TravelTimes, TripMode, TripSpec, VehicleSpec, VehicleType, BIKE_LENGTH, MAX_CAR_LENGTH,
+    MAX_TRUCK_LENGTH, MIN_CAR_LENGTH, MIN_TRUCK_LENGTH,
 };
 use abstutil::{fork_rng, prettyprint_usize, Timer, WeightedUsizeChoice};
 use geom::{Distance, Duration, Speed, Time};
 use map_model::{
-    BuildingID, BusRouteID, BusStopID, DirectedRoadID, FullNeighborhoodInfo, Map, PathConstraints,
-    Position, RoadID,
+    BuildingID, BusRouteID, BusStopID, DirectedRoadID, FullNeighborhoodInfo, IntersectionID,
+    LaneID, Map, PathConstraints, PathRequest, Position, RoadID,
 };
 use rand::seq::SliceRandom;
 use rand::Rng;
@@ -28,6 +30,12 @@ pub struct Scenario {
     // Much more detailed
     pub individ_trips: Vec<SpawnTrip>,
     pub individ_parked_cars: BTreeMap<BuildingID, usize>,
+    #[serde(default)]
+    pub people: Vec<PersonSpec>,
+
+    // Changes to the supply side over time
+    #[serde(default)]
+    pub incidents: Vec<ScheduledIncident>,
 }
 
 // SpawnOverTime and BorderSpawnOverTime should be kept separate. Agents in SpawnOverTime pick
@@ -43,6 +51,10 @@ pub struct SpawnOverTime {
     pub goal: OriginDestination,
     pub percent_biking: f64,
     pub percent_use_transit: f64,
+    // If set, ignore the percentages above and pick each agent's mode by comparing how long it
+    // would take.
+    #[serde(default)]
+    pub mode_choice: Option<ModeChoiceModel>,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -58,6 +70,34 @@ pub struct BorderSpawnOverTime {
     pub goal: OriginDestination,
 }
 
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct ScheduledIncident {
+    pub start_time: Time,
+    pub end_time: Time,
+    pub incident: Incident,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub enum Incident {
+    // Nobody can enter the lane. Agents already on it can leave.
+    BlockLane(LaneID),
+    // Nobody can start any turn through the intersection.
+    CloseIntersection(IntersectionID),
+    // The traffic signal goes dark and everybody treats it like an all-way stop.
+    SignalFailure(IntersectionID),
+    // Vehicles can't go faster than this on the road.
+    ReduceSpeed(RoadID, Speed),
+}
+
+// Somebody making a sequence of trips through the day.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct PersonSpec {
+    pub id: PersonID,
+    // In order. Each trip starts at its departure time, or once the previous trip is done and
+    // the person has stayed there for the dwell time, whichever is later.
+    pub trips: Vec<(SpawnTrip, Duration)>,
+}
+
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct SeedParkedCars {
     pub neighborhood: String,
@@ -81,6 +121,12 @@ impl Scenario {
                 prettyprint_usize(self.border_spawn_over_time.len())
             ),
             format!("{} SpawnTrip", prettyprint_usize(self.individ_trips.len())),
+            format!(
+                "{} people, making {} trips",
+                prettyprint_usize(self.people.len()),
+                prettyprint_usize(self.people.iter().map(|p| p.trips.len()).sum())
+            ),
+            format!("{} incidents", prettyprint_usize(self.incidents.len())),
         ]
     }
 
@@ -92,7 +138,11 @@ impl Scenario {
 
         if self.seed_buses {
             for route in map.get_all_bus_routes() {
-                sim.seed_bus_route(route, map, timer);
+                if route.departures.is_empty() {
+                    sim.seed_bus_route(route, map, timer);
+                } else {
+                    sim.schedule_bus_route(route, map);
+                }
             }
         }
 
@@ -155,6 +205,26 @@ impl Scenario {
             sim.schedule_trip(depart, spec, map);
         }
 
+        timer.start_iter("PersonSpec", self.people.len());
+        for p in &self.people {
+            timer.next();
+            let trips = p
+                .trips
+                .iter()
+                .map(|(t, dwell)| {
+                    let (depart, spec) = t.clone().to_trip_spec(rng);
+                    (depart, *dwell, spec)
+                })
+                .collect();
+            sim.schedule_person(p.id, trips);
+        }
+
+        for i in &self.incidents {
+            if let Err(err) = sim.schedule_incident(i.clone(), map) {
+                timer.warn(format!("Skipping incident: {}", err));
+            }
+        }
+
         sim.spawn_all_trips(map, timer, true);
         timer.stop(format!("Instantiating {}", self.scenario_name));
     }
@@ -185,6 +255,7 @@ impl Scenario {
                 goal: OriginDestination::Neighborhood("_everywhere_".to_string()),
                 percent_biking: 0.5,
                 percent_use_transit: 0.5,
+                mode_choice: None,
             }],
             // If there are no sidewalks/driving lanes at a border, scenario instantiation will
             // just warn and skip them.
@@ -204,6 +275,8 @@ impl Scenario {
                 .collect(),
             individ_trips: Vec::new(),
             individ_parked_cars: BTreeMap::new(),
+            people: Vec::new(),
+            incidents: Vec::new(),
         };
         for i in map.all_outgoing_borders() {
             s.spawn_over_time.push(SpawnOverTime {
@@ -214,6 +287,7 @@ impl Scenario {
                 goal: OriginDestination::EndOfRoad(i.some_incoming_road(map)),
                 percent_biking: 0.5,
                 percent_use_transit: 0.5,
+                mode_choice: None,
             });
         }
         s
@@ -229,6 +303,8 @@ impl Scenario {
             border_spawn_over_time: Vec::new(),
             individ_trips: Vec::new(),
             individ_parked_cars: BTreeMap::new(),
+            people: Vec::new(),
+            incidents: Vec::new(),
         }
     }
 
@@ -252,19 +328,50 @@ impl Scenario {
                 goal: OriginDestination::Neighborhood("_everywhere_".to_string()),
                 percent_biking: 0.5,
                 percent_use_transit: 0.5,
+                mode_choice: None,
             }],
             border_spawn_over_time: Vec::new(),
             individ_trips: Vec::new(),
             individ_parked_cars: BTreeMap::new(),
+            people: Vec::new(),
+            incidents: Vec::new(),
         }
     }
 
     pub fn rand_car(rng: &mut XorShiftRng) -> VehicleSpec {
         let length = Scenario::rand_dist(rng, MIN_CAR_LENGTH, MAX_CAR_LENGTH);
+        let (max_accel, max_decel) = VehicleType::Car.default_accel_limits();
         VehicleSpec {
             vehicle_type: VehicleType::Car,
             length,
             max_speed: None,
+            max_accel,
+            max_decel,
+        }
+    }
+
+    pub fn rand_truck(rng: &mut XorShiftRng) -> VehicleSpec {
+        let length = Scenario::rand_dist(rng, MIN_TRUCK_LENGTH, MAX_TRUCK_LENGTH);
+        let (max_accel, max_decel) = VehicleType::Truck.default_accel_limits();
+        VehicleSpec {
+            vehicle_type: VehicleType::Truck,
+            length,
+            max_speed: None,
+            max_accel,
+            max_decel,
+        }
+    }
+
+    pub fn rand_emergency_vehicle(rng: &mut XorShiftRng) -> VehicleSpec {
+        // Somewhere between an SUV and an ambulance
+        let length = Scenario::rand_dist(rng, MAX_CAR_LENGTH, MIN_TRUCK_LENGTH);
+        let (max_accel, max_decel) = VehicleType::Emergency.default_accel_limits();
+        VehicleSpec {
+            vehicle_type: VehicleType::Emergency,
+            length,
+            max_speed: None,
+            max_accel,
+            max_decel,
         }
     }
 
@@ -274,10 +381,13 @@ impl Scenario {
             Speed::miles_per_hour(8.0),
             Speed::miles_per_hour(10.0),
         ));
+        let (max_accel, max_decel) = VehicleType::Bike.default_accel_limits();
         VehicleSpec {
             vehicle_type: VehicleType::Bike,
             length: BIKE_LENGTH,
             max_speed,
+            max_accel,
+            max_decel,
         }
     }
 
@@ -322,6 +432,21 @@ impl SpawnOverTime {
             .choose(rng)
             .unwrap();
 
+        if let Some(ref model) = self.mode_choice {
+            self.spawn_with_mode_choice(
+                model,
+                spawn_time,
+                from_bldg,
+                rng,
+                sim,
+                reserved_cars,
+                neighborhoods,
+                map,
+                timer,
+            );
+            return;
+        }
+
         // What mode?
         if let Some(parked_car) = sim
             .get_parked_cars_by_owner(from_bldg)
@@ -428,6 +553,248 @@ impl SpawnOverTime {
 
         timer.warn(format!("Couldn't fulfill {:?} at all", self));
     }
+
+    // Estimate how long each possible mode would take, then let the model pick.
+    fn spawn_with_mode_choice(
+        &self,
+        model: &ModeChoiceModel,
+        spawn_time: Time,
+        from_bldg: BuildingID,
+        rng: &mut XorShiftRng,
+        sim: &mut Sim,
+        reserved_cars: &mut HashSet<CarID>,
+        neighborhoods: &HashMap<String, FullNeighborhoodInfo>,
+        map: &Map,
+        timer: &mut Timer,
+    ) {
+        let start = SidewalkSpot::building(from_bldg, map);
+        let ped_speed = Scenario::rand_ped_speed(rng);
+        // The destination can't depend on the mode.
+        let (walk_goal, drive_goal, bike_goal) = match self.goal {
+            OriginDestination::Neighborhood(ref n) => {
+                let b = *neighborhoods[n].buildings.choose(rng).unwrap();
+                (
+                    Some(SidewalkSpot::building(b, map)),
+                    Some(DrivingGoal::ParkNear(b)),
+                    Some(DrivingGoal::ParkNear(b)),
+                )
+            }
+            OriginDestination::EndOfRoad(dr) => (
+                SidewalkSpot::end_at_border(dr.dst_i(map), map),
+                DrivingGoal::end_at_border(dr, PathConstraints::Car, map),
+                DrivingGoal::end_at_border(dr, PathConstraints::Bike, map),
+            ),
+        };
+
+        let mut options: Vec<(TripMode, Duration, TripSpec)> = Vec::new();
+
+        if let Some(goal) = walk_goal.filter(|g| *g != start) {
+            if let Some(time) = walking_time(start.sidewalk_pos, goal.sidewalk_pos, ped_speed, map)
+            {
+                if let Some((stop1, stop2, route)) =
+                    map.should_use_transit(start.sidewalk_pos, goal.sidewalk_pos)
+                {
+                    if let Some(t) = transit_time(
+                        model,
+                        route,
+                        stop1,
+                        stop2,
+                        &start,
+                        &goal,
+                        ped_speed,
+                        spawn_time,
+                        sim.get_travel_times(),
+                        map,
+                    ) {
+                        options.push((
+                            TripMode::Transit,
+                            t,
+                            TripSpec::UsingTransit {
+                                start: start.clone(),
+                                goal: goal.clone(),
+                                route,
+                                stop1,
+                                stop2,
+                                ped_speed,
+                            },
+                        ));
+                    }
+                }
+                options.push((
+                    TripMode::Walk,
+                    time,
+                    TripSpec::JustWalking {
+                        start: start.clone(),
+                        goal,
+                        ped_speed,
+                    },
+                ));
+            }
+        }
+
+        let mut parked_car: Option<CarID> = None;
+        if let Some(goal) = drive_goal {
+            if let Some(p) = sim
+                .get_parked_cars_by_owner(from_bldg)
+                .into_iter()
+                .find(|p| !reserved_cars.contains(&p.vehicle.id))
+            {
+                // Approximate; the car might be parked a bit away from the building.
+                let req = PathRequest {
+                    start: Position::new(
+                        map.find_driving_lane_near_building(from_bldg),
+                        Distance::ZERO,
+                    ),
+                    end: goal.goal_pos(PathConstraints::Car, map),
+                    constraints: PathConstraints::Car,
+                };
+                if let Some(time) = driving_time(
+                    req,
+                    p.vehicle.max_speed,
+                    spawn_time,
+                    sim.get_travel_times(),
+                    map,
+                ) {
+                    parked_car = Some(p.vehicle.id);
+                    options.push((
+                        TripMode::Drive,
+                        time,
+                        TripSpec::UsingParkedCar {
+                            start: start.clone(),
+                            spot: p.spot,
+                            goal,
+                            ped_speed,
+                        },
+                    ));
+                }
+            }
+        }
+
+        if let Some(goal) = bike_goal {
+            let start_at = map.get_b(from_bldg).sidewalk();
+            let end_ok = if let DrivingGoal::ParkNear(to_bldg) = goal {
+                let end_at = map.get_b(to_bldg).sidewalk();
+                map.get_parent(end_at).sidewalk_to_bike(end_at).is_some() && start_at != end_at
+            } else {
+                true
+            };
+            if let Some(rack) = SidewalkSpot::bike_from_bike_rack(start_at, map).filter(|_| end_ok)
+            {
+                let bike = Scenario::rand_bike(rng);
+                let bike_start = match rack.connection {
+                    SidewalkPOI::BikeRack(pos) => pos,
+                    _ => unreachable!(),
+                };
+                let req = PathRequest {
+                    start: bike_start,
+                    end: goal.goal_pos(PathConstraints::Bike, map),
+                    constraints: PathConstraints::Bike,
+                };
+                if let Some(time) =
+                    driving_time(req, bike.max_speed, spawn_time, sim.get_travel_times(), map)
+                {
+                    options.push((
+                        TripMode::Bike,
+                        time,
+                        TripSpec::UsingBike {
+                            start: start.clone(),
+                            vehicle: bike,
+                            goal,
+                            ped_speed,
+                        },
+                    ));
+                }
+            }
+        }
+
+        if options.is_empty() {
+            timer.warn(format!("Couldn't fulfill {:?} at all", self));
+            return;
+        }
+        let choices: Vec<(TripMode, Duration)> = options
+            .iter()
+            .map(|(mode, time, _)| (*mode, *time))
+            .collect();
+        let (mode, _, spec) = options.swap_remove(model.choose(&choices, rng));
+        if mode == TripMode::Drive {
+            reserved_cars.insert(parked_car.unwrap());
+        }
+        sim.schedule_trip(spawn_time, spec, map);
+    }
+}
+
+// Walk to the first stop, wait, ride, then walk from the second stop.
+fn transit_time(
+    model: &ModeChoiceModel,
+    route: BusRouteID,
+    stop1: BusStopID,
+    stop2: BusStopID,
+    start: &SidewalkSpot,
+    goal: &SidewalkSpot,
+    ped_speed: Speed,
+    depart: Time,
+    travel_times: Option<&TravelTimes>,
+    map: &Map,
+) -> Option<Duration> {
+    let walk1 = walking_time(
+        start.sidewalk_pos,
+        map.get_bs(stop1).sidewalk_pos,
+        ped_speed,
+        map,
+    )?;
+    let at_stop = depart + walk1;
+
+    let r = map.get_br(route);
+    let idx1 = r.stops.iter().position(|s| *s == stop1)?;
+    let idx2 = r.stops.iter().position(|s| *s == stop2)?;
+    let wait = if r.departures.is_empty() {
+        model.transit_wait
+    } else {
+        // Wait for the next timetabled bus to reach the stop. Stops after the return stop are
+        // served by runs starting there.
+        let (first, departures) = match r.return_stop {
+            Some(idx) if idx1 >= idx => (idx, &r.return_departures),
+            _ => (0, &r.departures),
+        };
+        let offset = r.stop_offsets[idx1] - r.stop_offsets[first];
+        departures
+            .iter()
+            .map(|t| *t + offset)
+            .filter(|t| *t >= at_stop)
+            .min()?
+            - at_stop
+    };
+    let ride = if idx1 < idx2 && idx2 < r.stop_offsets.len() {
+        r.stop_offsets[idx2] - r.stop_offsets[idx1]
+    } else {
+        // The bus visits every stop in between, wrapping around the end of the route.
+        let mut ride = Duration::ZERO;
+        let mut idx = idx1;
+        while idx != idx2 {
+            let next = (idx + 1) % r.stops.len();
+            ride += driving_time(
+                PathRequest {
+                    start: map.get_bs(r.stops[idx]).driving_pos,
+                    end: map.get_bs(r.stops[next]).driving_pos,
+                    constraints: r.route_type,
+                },
+                None,
+                at_stop + wait + ride,
+                travel_times,
+                map,
+            )?;
+            idx = next;
+        }
+        ride
+    };
+
+    let walk2 = walking_time(
+        map.get_bs(stop2).sidewalk_pos,
+        goal.sidewalk_pos,
+        ped_speed,
+        map,
+    )?;
+    Some(walk1 + wait + ride + walk2)
 }
 
 impl BorderSpawnOverTime {
@@ -826,6 +1193,68 @@ pub enum SpawnTrip {
         BusStopID,
         BusStopID,
     ),
+    // Park-and-ride: drive, park near a building, then walk to the first stop.
+    DriveThenTransit {
+        depart: Time,
+        start: BuildingID,
+        park_near: BuildingID,
+        goal: SidewalkSpot,
+        route: BusRouteID,
+        stop1: BusStopID,
+        stop2: BusStopID,
+    },
+    BikeThenTransit {
+        depart: Time,
+        start: SidewalkSpot,
+        park_near: BuildingID,
+        goal: SidewalkSpot,
+        route: BusRouteID,
+        stop1: BusStopID,
+        stop2: BusStopID,
+    },
+    // A truck stopping at each building for some time
+    Delivery {
+        depart: Time,
+        start: Position,
+        stops: Vec<(BuildingID, Duration)>,
+        goal: DrivingGoal,
+    },
+    // An emergency vehicle dispatched to a building
+    Emergency {
+        depart: Time,
+        start: Position,
+        goal: BuildingID,
+    },
+}
+
+impl ScheduledIncident {
+    // Scenarios are edited by hand and can outlive the map they were made for.
+    pub fn validate(&self, map: &Map) -> Result<(), String> {
+        if self.start_time >= self.end_time {
+            return Err(format!(
+                "{:?} ends at {}, before it starts at {}",
+                self.incident, self.end_time, self.start_time
+            ));
+        }
+        let exists = match self.incident {
+            Incident::BlockLane(l) => map.maybe_get_l(l).is_some(),
+            Incident::CloseIntersection(i) => map.maybe_get_i(i).is_some(),
+            Incident::SignalFailure(i) => map.maybe_get_traffic_signal(i).is_some(),
+            Incident::ReduceSpeed(r, speed) => {
+                if speed <= Speed::ZERO {
+                    return Err(format!("{:?} would stop all traffic", self.incident));
+                }
+                map.maybe_get_r(r).is_some()
+            }
+        };
+        if !exists {
+            return Err(format!(
+                "{:?} refers to something not in the map",
+                self.incident
+            ));
+        }
+        Ok(())
+    }
 }
 
 impl SpawnTrip {
@@ -887,6 +1316,73 @@ impl SpawnTrip {
                     ped_speed: Scenario::rand_ped_speed(rng),
                 },
             ),
+            SpawnTrip::DriveThenTransit {
+                depart,
+                start,
+                park_near,
+                goal,
+                route,
+                stop1,
+                stop2,
+            } => (
+                depart,
+                TripSpec::DriveThenTransit {
+                    start_bldg: start,
+                    park_near,
+                    goal,
+                    route,
+                    stop1,
+                    stop2,
+                    ped_speed: Scenario::rand_ped_speed(rng),
+                },
+            ),
+            SpawnTrip::BikeThenTransit {
+                depart,
+                start,
+                park_near,
+                goal,
+                route,
+                stop1,
+                stop2,
+            } => (
+                depart,
+                TripSpec::BikeThenTransit {
+                    start,
+                    park_near,
+                    goal,
+                    route,
+                    stop1,
+                    stop2,
+                    vehicle: Scenario::rand_bike(rng),
+                    ped_speed: Scenario::rand_ped_speed(rng),
+                },
+            ),
+            SpawnTrip::Delivery {
+                depart,
+                start,
+                stops,
+                goal,
+            } => (
+                depart,
+                TripSpec::Delivery {
+                    start_pos: start,
+                    stops,
+                    goal,
+                    vehicle_spec: Scenario::rand_truck(rng),
+                },
+            ),
+            SpawnTrip::Emergency {
+                depart,
+                start,
+                goal,
+            } => (
+                depart,
+                TripSpec::Emergency {
+                    start_pos: start,
+                    goal,
+                    vehicle_spec: Scenario::rand_emergency_vehicle(rng),
+                },
+            ),
         }
     }
 }
@@ -1,10 +1,10 @@
 use crate::{
     CarID, Command, CreateCar, CreatePedestrian, DrivingGoal, ParkingSimState, ParkingSpot,
-    PedestrianID, Scheduler, SidewalkPOI, SidewalkSpot, TripLeg, TripManager, TripStart,
-    VehicleSpec, MAX_CAR_LENGTH,
+    PedestrianID, PersonID, Router, Scheduler, SidewalkPOI, SidewalkSpot, TripLeg, TripManager,
+    TripStart, VehicleSpec, MAX_CAR_LENGTH,
 };
 use abstutil::Timer;
-use geom::{Speed, Time, EPSILON_DIST};
+use geom::{Duration, Speed, Time, EPSILON_DIST};
 use map_model::{BuildingID, BusRouteID, BusStopID, Map, PathConstraints, PathRequest, Position};
 use serde_derive::{Deserialize, Serialize};
 use std::collections::BTreeSet;
@@ -48,12 +48,51 @@ pub enum TripSpec {
         stop2: BusStopID,
         ped_speed: Speed,
     },
+    // Drive from a building to park near another, then walk to a stop and ride transit.
+    DriveThenTransit {
+        start_bldg: BuildingID,
+        park_near: BuildingID,
+        goal: SidewalkSpot,
+        route: BusRouteID,
+        stop1: BusStopID,
+        stop2: BusStopID,
+        ped_speed: Speed,
+    },
+    BikeThenTransit {
+        start: SidewalkSpot,
+        park_near: BuildingID,
+        goal: SidewalkSpot,
+        route: BusRouteID,
+        stop1: BusStopID,
+        stop2: BusStopID,
+        vehicle: VehicleSpec,
+        ped_speed: Speed,
+    },
+    // A truck appears, stops near each building for some time, then leaves the map.
+    Delivery {
+        start_pos: Position,
+        stops: Vec<(BuildingID, Duration)>,
+        goal: DrivingGoal,
+        vehicle_spec: VehicleSpec,
+    },
+    // An emergency vehicle is dispatched and drives to a building, preempting signals on the way.
+    Emergency {
+        start_pos: Position,
+        goal: BuildingID,
+        vehicle_spec: VehicleSpec,
+    },
 }
 
 #[derive(Serialize, Deserialize, PartialEq)]
 pub struct TripSpawner {
     parked_cars_claimed: BTreeSet<CarID>,
-    trips: Vec<(Time, Option<PedestrianID>, Option<CarID>, TripSpec)>,
+    trips: Vec<(
+        Time,
+        Option<PedestrianID>,
+        Option<CarID>,
+        Option<PersonID>,
+        TripSpec,
+    )>,
 }
 
 impl TripSpawner {
@@ -69,9 +108,11 @@ impl TripSpawner {
         start_time: Time,
         ped_id: Option<PedestrianID>,
         car_id: Option<CarID>,
+        person: Option<PersonID>,
         spec: TripSpec,
         map: &Map,
         parking: &ParkingSimState,
+        trips: &mut TripManager,
     ) {
         // TODO We'll want to repeat this validation when we spawn stuff later for a second leg...
         match &spec {
@@ -126,20 +167,17 @@ impl TripSpawner {
             }
             TripSpec::UsingBike {
                 start,
+                vehicle,
                 goal,
                 ped_speed,
-                ..
             } => {
-                // TODO These trips are just silently erased; they don't even show up as aborted
-                // trips! Really need to fix the underlying problem.
+                let mut problem = None;
                 if SidewalkSpot::bike_from_bike_rack(start.sidewalk_pos.lane(), map).is_none() {
-                    println!(
+                    problem = Some(format!(
                         "Can't start biking from {}; no biking or driving lane nearby?",
                         start.sidewalk_pos.lane()
-                    );
-                    return;
-                }
-                if let DrivingGoal::ParkNear(b) = goal {
+                    ));
+                } else if let DrivingGoal::ParkNear(b) = goal {
                     let last_lane = goal.goal_pos(PathConstraints::Bike, map).lane();
                     // If bike_to_sidewalk works, then SidewalkSpot::bike_rack should too.
                     if map
@@ -147,14 +185,12 @@ impl TripSpawner {
                         .bike_to_sidewalk(last_lane)
                         .is_none()
                     {
-                        println!(
+                        problem = Some(format!(
                             "Can't fulfill {:?} for a bike trip; no sidewalk near {}",
                             goal, last_lane
-                        );
-                        return;
-                    }
-                    // A bike trip going from one lane to the same lane should... just walk.
-                    if start.sidewalk_pos.lane() == map.get_b(*b).sidewalk() {
+                        ));
+                    } else if start.sidewalk_pos.lane() == map.get_b(*b).sidewalk() {
+                        // A bike trip going from one lane to the same lane should... just walk.
                         println!(
                             "Bike trip from {:?} to {:?} will just walk; it's the same sidewalk!",
                             start, goal
@@ -163,6 +199,7 @@ impl TripSpawner {
                             start_time,
                             ped_id,
                             None,
+                            person,
                             TripSpec::JustWalking {
                                 start: start.clone(),
                                 goal: SidewalkSpot::building(*b, map),
@@ -172,11 +209,106 @@ impl TripSpawner {
                         return;
                     }
                 }
+                if let Some(problem) = problem {
+                    println!("{}", problem);
+                    // Still record the trip as aborted, so it shows up and the person moves on
+                    // to their next trip.
+                    let trip = trips.new_trip(
+                        start_time,
+                        match start.connection {
+                            SidewalkPOI::Building(b) => TripStart::Bldg(b),
+                            SidewalkPOI::SuddenlyAppear => {
+                                TripStart::Border(map.get_l(start.sidewalk_pos.lane()).src_i)
+                            }
+                            SidewalkPOI::Border(i) => TripStart::Border(i),
+                            _ => unreachable!(),
+                        },
+                        vec![TripLeg::Drive(
+                            vehicle.make(car_id.unwrap(), None),
+                            goal.clone(),
+                        )],
+                        person,
+                    );
+                    trips.abort_trip_failed_start(trip);
+                    return;
+                }
+            }
+            TripSpec::UsingTransit { .. } | TripSpec::DriveThenTransit { .. } => {}
+            TripSpec::Delivery {
+                start_pos,
+                stops,
+                goal,
+                vehicle_spec,
+            } => {
+                if start_pos.dist_along() < vehicle_spec.length {
+                    panic!(
+                        "Can't spawn a truck at {}; too close to the start",
+                        start_pos.dist_along()
+                    );
+                }
+                if stops.is_empty() {
+                    panic!("A delivery trip without any stops doesn't make sense");
+                }
+                // TODO Let trucks end their day at a depot
+                if let DrivingGoal::ParkNear(_) = goal {
+                    panic!("Delivery trips have to end at a border, not {:?}", goal);
+                }
+            }
+            TripSpec::Emergency {
+                start_pos,
+                vehicle_spec,
+                ..
+            } => {
+                if start_pos.dist_along() < vehicle_spec.length {
+                    panic!(
+                        "Can't spawn an emergency vehicle at {}; too close to the start",
+                        start_pos.dist_along()
+                    );
+                }
+            }
+            TripSpec::BikeThenTransit {
+                start,
+                park_near,
+                goal,
+                route,
+                stop1,
+                stop2,
+                ped_speed,
+                ..
+            } => {
+                let last_lane = DrivingGoal::ParkNear(*park_near)
+                    .goal_pos(PathConstraints::Bike, map)
+                    .lane();
+                if SidewalkSpot::bike_from_bike_rack(start.sidewalk_pos.lane(), map).is_none()
+                    || map
+                        .get_parent(last_lane)
+                        .bike_to_sidewalk(last_lane)
+                        .is_none()
+                {
+                    println!(
+                        "Can't bike from {:?} to near {}; just walking to the bus instead",
+                        start, park_near
+                    );
+                    self.trips.push((
+                        start_time,
+                        ped_id,
+                        None,
+                        person,
+                        TripSpec::UsingTransit {
+                            start: start.clone(),
+                            goal: goal.clone(),
+                            route: *route,
+                            stop1: *stop1,
+                            stop2: *stop2,
+                            ped_speed: *ped_speed,
+                        },
+                    ));
+                    return;
+                }
             }
-            TripSpec::UsingTransit { .. } => {}
         };
 
-        self.trips.push((start_time, ped_id, car_id, spec));
+        self.trips.push((start_time, ped_id, car_id, person, spec));
     }
 
     pub fn spawn_all(
@@ -188,17 +320,23 @@ impl TripSpawner {
         timer: &mut Timer,
         retry_if_no_room: bool,
     ) {
-        let paths = timer.parallelize(
-            "calculate paths",
-            std::mem::replace(&mut self.trips, Vec::new()),
-            |tuple| {
-                let req = tuple.3.get_pathfinding_request(map, parking);
-                (tuple, req.clone(), map.pathfind(req))
-            },
-        );
+        let to_spawn = std::mem::replace(&mut self.trips, Vec::new());
+        let requests: Vec<(Time, PathRequest)> = to_spawn
+            .iter()
+            .map(|tuple| (tuple.0, tuple.4.get_pathfinding_request(map, parking)))
+            .collect();
+        let paths = if let Some(tt) = trips.get_travel_times_mut() {
+            tt.pathfind_many(requests.clone(), map, timer)
+        } else {
+            timer.parallelize("calculate paths", requests.clone(), |(_, req)| {
+                map.pathfind(req)
+            })
+        };
 
         timer.start_iter("spawn trips", paths.len());
-        for ((start_time, ped_id, car_id, spec), req, maybe_path) in paths {
+        for (((start_time, ped_id, car_id, person, spec), (_, req)), maybe_path) in
+            to_spawn.into_iter().zip(requests).zip(paths)
+        {
             timer.next();
             match spec {
                 TripSpec::CarAppearing {
@@ -225,7 +363,9 @@ impl TripSpawner {
                         ));
                     }
                     let trip_start = TripStart::Border(map.get_l(start_pos.lane()).src_i);
-                    let trip = trips.new_trip(start_time, trip_start, legs);
+                    let trip = trips.new_trip(start_time, trip_start, legs, person);
+                    let maybe_path =
+                        trips.vehicle_path_for_new_trip(trip, &req, start_time, maybe_path);
                     if let Some(path) = maybe_path {
                         let router = goal.make_router(path, map, vehicle.vehicle_type);
                         scheduler.quick_push(
@@ -251,10 +391,17 @@ impl TripSpawner {
                     ped_speed,
                 } => {
                     let vehicle = &parking.get_car_at_spot(spot).unwrap().vehicle;
-                    match start.connection {
-                        SidewalkPOI::Building(b) => assert_eq!(vehicle.owner, Some(b)),
+                    let start_bldg = match start.connection {
+                        SidewalkPOI::Building(b) => b,
                         _ => unreachable!(),
                     };
+                    if person.is_some() {
+                        // People use their car wherever they last parked it, and may use it
+                        // again later in the day.
+                        self.parked_cars_claimed.remove(&vehicle.id);
+                    } else {
+                        assert_eq!(vehicle.owner, Some(start_bldg));
+                    }
 
                     let parking_spot = SidewalkSpot::parking_spot(spot, map, parking);
 
@@ -273,7 +420,7 @@ impl TripSpawner {
                         DrivingGoal::Border(_, _) => {}
                     }
                     let trip =
-                        trips.new_trip(start_time, TripStart::Bldg(vehicle.owner.unwrap()), legs);
+                        trips.new_trip(start_time, TripStart::Bldg(start_bldg), legs, person);
 
                     if let Some(path) = maybe_path {
                         scheduler.quick_push(
@@ -303,11 +450,20 @@ impl TripSpawner {
                     goal,
                     ped_speed,
                 } => {
-                    let walk_to = SidewalkSpot::deferred_parking_spot(start_bldg, goal, map);
+                    let walk_to =
+                        SidewalkSpot::deferred_parking_spot(start_bldg, goal.clone(), map);
                     // Can't add TripLeg::Drive, because we don't know the vehicle yet! Plumb along
                     // the DrivingGoal, so we can expand the trip later.
-                    let legs = vec![TripLeg::Walk(ped_id.unwrap(), ped_speed, walk_to.clone())];
-                    let trip = trips.new_trip(start_time, TripStart::Bldg(start_bldg), legs);
+                    let mut legs = vec![TripLeg::Walk(ped_id.unwrap(), ped_speed, walk_to.clone())];
+                    if let DrivingGoal::ParkNear(b) = goal {
+                        legs.push(TripLeg::Walk(
+                            ped_id.unwrap(),
+                            ped_speed,
+                            SidewalkSpot::building(b, map),
+                        ));
+                    }
+                    let trip =
+                        trips.new_trip(start_time, TripStart::Bldg(start_bldg), legs, person);
 
                     scheduler.quick_push(
                         start_time,
@@ -325,6 +481,48 @@ impl TripSpawner {
                         ),
                     );
                 }
+                TripSpec::DriveThenTransit {
+                    start_bldg,
+                    park_near,
+                    goal,
+                    route,
+                    stop1,
+                    stop2,
+                    ped_speed,
+                } => {
+                    let ped = ped_id.unwrap();
+                    let walk_to = SidewalkSpot::deferred_parking_spot(
+                        start_bldg,
+                        DrivingGoal::ParkNear(park_near),
+                        map,
+                    );
+                    // Like MaybeUsingParkedCar, the Drive leg gets filled in once the car is
+                    // found.
+                    let legs = vec![
+                        TripLeg::Walk(ped, ped_speed, walk_to.clone()),
+                        TripLeg::Walk(ped, ped_speed, SidewalkSpot::bus_stop(stop1, map)),
+                        TripLeg::RideBus(ped, route, stop2),
+                        TripLeg::Walk(ped, ped_speed, goal),
+                    ];
+                    let trip =
+                        trips.new_trip(start_time, TripStart::Bldg(start_bldg), legs, person);
+
+                    scheduler.quick_push(
+                        start_time,
+                        Command::SpawnPed(
+                            CreatePedestrian {
+                                id: ped,
+                                speed: ped_speed,
+                                start: SidewalkSpot::building(start_bldg, map),
+                                goal: walk_to,
+                                // This is guaranteed to work, and is junk anyway.
+                                path: maybe_path.unwrap(),
+                                trip,
+                            },
+                            req,
+                        ),
+                    );
+                }
                 TripSpec::JustWalking {
                     start,
                     goal,
@@ -341,6 +539,7 @@ impl TripSpawner {
                             _ => unreachable!(),
                         },
                         vec![TripLeg::Walk(ped_id.unwrap(), ped_speed, goal.clone())],
+                        person,
                     );
 
                     if let Some(path) = maybe_path {
@@ -399,6 +598,7 @@ impl TripSpawner {
                             _ => unreachable!(),
                         },
                         legs,
+                        person,
                     );
 
                     if let Some(path) = maybe_path {
@@ -448,6 +648,7 @@ impl TripSpawner {
                             TripLeg::RideBus(ped_id.unwrap(), route, stop2),
                             TripLeg::Walk(ped_id.unwrap(), ped_speed, goal),
                         ],
+                        person,
                     );
 
                     if let Some(path) = maybe_path {
@@ -473,6 +674,133 @@ impl TripSpawner {
                         trips.abort_trip_failed_start(trip);
                     }
                 }
+                TripSpec::BikeThenTransit {
+                    start,
+                    park_near,
+                    goal,
+                    route,
+                    stop1,
+                    stop2,
+                    vehicle,
+                    ped_speed,
+                } => {
+                    let ped = ped_id.unwrap();
+                    let walk_to =
+                        SidewalkSpot::bike_from_bike_rack(start.sidewalk_pos.lane(), map).unwrap();
+                    let legs = vec![
+                        TripLeg::Walk(ped, ped_speed, walk_to.clone()),
+                        TripLeg::Drive(
+                            vehicle.make(car_id.unwrap(), None),
+                            DrivingGoal::ParkNear(park_near),
+                        ),
+                        TripLeg::Walk(ped, ped_speed, SidewalkSpot::bus_stop(stop1, map)),
+                        TripLeg::RideBus(ped, route, stop2),
+                        TripLeg::Walk(ped, ped_speed, goal),
+                    ];
+                    let trip = trips.new_trip(
+                        start_time,
+                        match start.connection {
+                            SidewalkPOI::Building(b) => TripStart::Bldg(b),
+                            SidewalkPOI::SuddenlyAppear => {
+                                TripStart::Border(map.get_l(start.sidewalk_pos.lane()).src_i)
+                            }
+                            SidewalkPOI::Border(i) => TripStart::Border(i),
+                            _ => unreachable!(),
+                        },
+                        legs,
+                        person,
+                    );
+
+                    if let Some(path) = maybe_path {
+                        scheduler.quick_push(
+                            start_time,
+                            Command::SpawnPed(
+                                CreatePedestrian {
+                                    id: ped,
+                                    speed: ped_speed,
+                                    start,
+                                    goal: walk_to,
+                                    path,
+                                    trip,
+                                },
+                                req,
+                            ),
+                        );
+                    } else {
+                        timer.warn(format!(
+                            "BikeThenTransit trip couldn't find the first path {}",
+                            req
+                        ));
+                        trips.abort_trip_failed_start(trip);
+                    }
+                }
+                TripSpec::Delivery {
+                    start_pos,
+                    stops,
+                    goal,
+                    vehicle_spec,
+                } => {
+                    let vehicle = vehicle_spec.make(car_id.unwrap(), None);
+                    let first_stop = stops[0].0;
+                    let mut legs: Vec<TripLeg> = stops
+                        .into_iter()
+                        .map(|(b, dwell)| TripLeg::Deliver(vehicle.clone(), b, dwell))
+                        .collect();
+                    legs.push(TripLeg::Drive(vehicle.clone(), goal));
+                    let trip_start = TripStart::Border(map.get_l(start_pos.lane()).src_i);
+                    let trip = trips.new_trip(start_time, trip_start, legs, person);
+                    let maybe_path =
+                        trips.vehicle_path_for_new_trip(trip, &req, start_time, maybe_path);
+                    if let Some(path) = maybe_path {
+                        let router = Router::deliver_to(path, first_stop, &vehicle, map);
+                        scheduler.quick_push(
+                            start_time,
+                            Command::SpawnCar(
+                                CreateCar::for_appearing(vehicle, start_pos, router, trip),
+                                req,
+                                retry_if_no_room,
+                            ),
+                        );
+                    } else {
+                        timer.warn(format!(
+                            "Delivery trip couldn't find the first path {}",
+                            req
+                        ));
+                        trips.abort_trip_failed_start(trip);
+                    }
+                }
+                TripSpec::Emergency {
+                    start_pos,
+                    goal,
+                    vehicle_spec,
+                } => {
+                    let vehicle = vehicle_spec.make(car_id.unwrap(), None);
+                    let trip = trips.new_trip(
+                        start_time,
+                        TripStart::Border(map.get_l(start_pos.lane()).src_i),
+                        vec![TripLeg::Drive(vehicle.clone(), DrivingGoal::ParkNear(goal))],
+                        person,
+                    );
+                    let maybe_path =
+                        trips.vehicle_path_for_new_trip(trip, &req, start_time, maybe_path);
+                    if let Some(path) = maybe_path {
+                        let router = Router::respond_to(path, goal, &vehicle, map);
+                        scheduler.quick_push(
+                            start_time,
+                            Command::SpawnCar(
+                                CreateCar::for_appearing(vehicle, start_pos, router, trip),
+                                req,
+                                retry_if_no_room,
+                            ),
+                        );
+                    } else {
+                        timer.warn(format!(
+                            "Emergency trip couldn't find the first path {}",
+                            req
+                        ));
+                        trips.abort_trip_failed_start(trip);
+                    }
+                }
             }
         }
 
@@ -530,7 +858,8 @@ impl TripSpec {
             },
             // Don't know where the parked car will be, so just make a dummy path that'll never
             // fail.
-            TripSpec::MaybeUsingParkedCar { start_bldg, .. } => {
+            TripSpec::MaybeUsingParkedCar { start_bldg, .. }
+            | TripSpec::DriveThenTransit { start_bldg, .. } => {
                 let pos = map.get_b(*start_bldg).front_path.sidewalk;
                 PathRequest {
                     start: pos,
@@ -543,12 +872,28 @@ impl TripSpec {
                 end: goal.sidewalk_pos,
                 constraints: PathConstraints::Pedestrian,
             },
-            TripSpec::UsingBike { start, .. } => PathRequest {
-                start: start.sidewalk_pos,
-                end: SidewalkSpot::bike_from_bike_rack(start.sidewalk_pos.lane(), map)
-                    .unwrap()
-                    .sidewalk_pos,
-                constraints: PathConstraints::Pedestrian,
+            TripSpec::UsingBike { start, .. } | TripSpec::BikeThenTransit { start, .. } => {
+                PathRequest {
+                    start: start.sidewalk_pos,
+                    end: SidewalkSpot::bike_from_bike_rack(start.sidewalk_pos.lane(), map)
+                        .unwrap()
+                        .sidewalk_pos,
+                    constraints: PathConstraints::Pedestrian,
+                }
+            }
+            TripSpec::Delivery {
+                start_pos, stops, ..
+            } => PathRequest {
+                start: *start_pos,
+                end: DrivingGoal::ParkNear(stops[0].0).goal_pos(PathConstraints::Car, map),
+                constraints: PathConstraints::Car,
+            },
+            TripSpec::Emergency {
+                start_pos, goal, ..
+            } => PathRequest {
+                start: *start_pos,
+                end: DrivingGoal::ParkNear(*goal).goal_pos(PathConstraints::Car, map),
+                constraints: PathConstraints::Car,
             },
             TripSpec::UsingTransit { start, stop1, .. } => PathRequest {
                 start: start.sidewalk_pos,
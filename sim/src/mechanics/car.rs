@@ -1,11 +1,12 @@
+use crate::mechanics::kinematics::SpeedProfile;
 use crate::{
-    AgentMetadata, CarStatus, DistanceInterval, DrawCarInput, ParkingSpot, Router, TimeInterval,
-    TransitSimState, TripID, Vehicle, VehicleType,
+    AgentMetadata, CarStatus, DistanceInterval, DrawCarInput, DrivingModes, ParkingSpot, Router,
+    TimeInterval, TransitSimState, TripID, Vehicle, VehicleType,
 };
-use geom::{Distance, Duration, PolyLine, Time};
-use map_model::{Map, Traversable, LANE_THICKNESS};
+use geom::{Distance, Duration, PolyLine, Speed, Time};
+use map_model::{Map, RoadID, Traversable, TurnPriority, LANE_THICKNESS};
 use serde_derive::{Deserialize, Serialize};
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, VecDeque};
 
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub struct Car {
@@ -15,6 +16,9 @@ pub struct Car {
     pub trip: TripID,
     pub blocked_since: Option<Time>,
     pub started_at: Time,
+    // How fast the car was going when it finished its last Crossing state. Only meaningful if it
+    // hasn't been blocked since.
+    pub last_speed: Speed,
 
     // In reverse order -- most recently left is first. The sum length of these must be >=
     // vehicle.length.
@@ -23,16 +27,15 @@ pub struct Car {
 
 impl Car {
     // Assumes the current head of the path is the thing to cross.
-    pub fn crossing_state(&self, start_dist: Distance, start_time: Time, map: &Map) -> CarState {
-        let dist_int = DistanceInterval::new_driving(
-            start_dist,
-            if self.router.last_step() {
-                self.router.get_end_dist()
-            } else {
-                self.router.head().length(map)
-            },
-        );
-        self.crossing_state_with_end_dist(dist_int, start_time, map)
+    pub fn crossing_state(
+        &self,
+        start_dist: Distance,
+        start_time: Time,
+        map: &Map,
+        speed_caps: &BTreeMap<RoadID, Speed>,
+    ) -> CarState {
+        let dist_int = DistanceInterval::new_driving(start_dist, self.end_of_head(map));
+        self.crossing_state_with_end_dist(dist_int, start_time, map, speed_caps)
     }
 
     pub fn crossing_state_with_end_dist(
@@ -40,14 +43,133 @@ impl Car {
         dist_int: DistanceInterval,
         start_time: Time,
         map: &Map,
+        speed_caps: &BTreeMap<RoadID, Speed>,
     ) -> CarState {
-        let on = self.router.head();
+        let speed = self.speed_limit_on(self.router.head(), map, speed_caps);
+        // Only brake for what's next if we're actually going to reach the end of this step.
+        let end_speed = if dist_int.end >= self.end_of_head(map) {
+            self.speed_at_end_of_head(speed, map, speed_caps)
+        } else {
+            speed
+        };
+        let profile = SpeedProfile::new(
+            dist_int.end - dist_int.start,
+            self.current_speed(start_time),
+            speed,
+            end_speed,
+            self.vehicle.max_accel,
+            self.vehicle.max_decel,
+        );
+        CarState::Crossing(
+            TimeInterval::new(start_time, start_time + profile.total_time()),
+            dist_int,
+            profile,
+        )
+    }
+
+    // How fast is the car going right now? Only meaningful at the instant of a state transition;
+    // a Crossing car might really be stuck behind its leader.
+    pub fn current_speed(&self, now: Time) -> Speed {
+        match self.state {
+            CarState::Crossing(ref time_int, _, ref profile) => {
+                profile.speed_at(now.min(time_int.end) - time_int.start)
+            }
+            // Just reached the end of the previous step and didn't have to wait.
+            CarState::Queued | CarState::WaitingToAdvance => {
+                if self.blocked_since == Some(now) {
+                    self.last_speed
+                } else {
+                    Speed::ZERO
+                }
+            }
+            CarState::Unparking(_, _, _) | CarState::Parking(_, _, _) | CarState::Idling(_, _) => {
+                Speed::ZERO
+            }
+        }
+    }
+
+    // How the car spent its current state, from the start until now. Queued cars only count the
+    // time since they were blocked.
+    pub fn driving_modes(&self, now: Time) -> DrivingModes {
+        match self.state {
+            CarState::Crossing(ref time_int, _, ref profile) => {
+                profile.driving_modes(now.min(time_int.end) - time_int.start)
+            }
+            CarState::Queued | CarState::WaitingToAdvance => DrivingModes::idle(
+                self.blocked_since
+                    .map(|t| now - t)
+                    .unwrap_or(Duration::ZERO),
+            ),
+            CarState::Unparking(_, _, ref time_int)
+            | CarState::Parking(_, _, ref time_int)
+            | CarState::Idling(_, ref time_int) => {
+                DrivingModes::idle(now.min(time_int.end) - time_int.start)
+            }
+        }
+    }
+
+    fn speed_limit_on(
+        &self,
+        on: Traversable,
+        map: &Map,
+        speed_caps: &BTreeMap<RoadID, Speed>,
+    ) -> Speed {
         let mut speed = on.speed_limit(map);
+        // Incidents can temporarily slow down a road.
+        if let Traversable::Lane(l) = on {
+            if let Some(cap) = speed_caps.get(&map.get_l(l).parent) {
+                speed = speed.min(*cap);
+            }
+        }
         if let Some(s) = self.vehicle.max_speed {
             speed = speed.min(s);
         }
-        let dt = (dist_int.end - dist_int.start) / speed;
-        CarState::Crossing(TimeInterval::new(start_time, start_time + dt), dist_int)
+        speed
+    }
+
+    fn end_of_head(&self, map: &Map) -> Distance {
+        if self.router.last_step() {
+            self.router.get_end_dist()
+        } else {
+            self.router.head().length(map)
+        }
+    }
+
+    // How fast should the car be going when it reaches the end of the current step?
+    fn speed_at_end_of_head(
+        &self,
+        cruise_speed: Speed,
+        map: &Map,
+        speed_caps: &BTreeMap<RoadID, Speed>,
+    ) -> Speed {
+        match self.router.maybe_next() {
+            None => {
+                if self.router.vanishes_at_end() {
+                    cruise_speed
+                } else {
+                    Speed::ZERO
+                }
+            }
+            Some(Traversable::Turn(t)) => {
+                if let Some(ss) = map.maybe_get_stop_sign(t.parent) {
+                    if ss.get_priority(t, map) != TurnPriority::Protected {
+                        return Speed::ZERO;
+                    }
+                }
+                // Slow down to check for gaps when entering a roundabout, but don't stop.
+                if map.get_i(t.parent).is_roundabout()
+                    && map.get_roundabout_priority(t) == TurnPriority::Yield
+                {
+                    return cruise_speed
+                        .min(self.speed_limit_on(Traversable::Turn(t), map, speed_caps))
+                        .min(Speed::miles_per_hour(15.0));
+                }
+                // TODO We don't know yet if a traffic signal will be red when we arrive, so
+                // optimistically don't brake. Cars waiting at a red light still stop instantly.
+                cruise_speed.min(self.speed_limit_on(Traversable::Turn(t), map, speed_caps))
+            }
+            Some(next) => cruise_speed.min(self.speed_limit_on(next, map, speed_caps)),
+        }
     }
 
     pub fn get_draw_car(
@@ -151,7 +273,7 @@ impl Car {
             status: match self.state {
                 CarState::Queued => CarStatus::Moving,
                 CarState::WaitingToAdvance => CarStatus::Moving,
-                CarState::Crossing(_, _) => CarStatus::Moving,
+                CarState::Crossing(_, _, _) => CarStatus::Moving,
                 // Eh they're technically moving, but this is a bit easier to spot
                 CarState::Unparking(_, _, _) => CarStatus::Parked,
                 CarState::Parking(_, _, _) => CarStatus::Parked,
@@ -159,7 +281,7 @@ impl Car {
                 CarState::Idling(_, _) => CarStatus::Parked,
             },
             on: self.router.head(),
-            label: if self.vehicle.vehicle_type == VehicleType::Bus {
+            label: if self.vehicle.vehicle_type.is_transit() {
                 Some(
                     map.get_br(transit.bus_route(self.vehicle.id))
                         .name
@@ -189,7 +311,7 @@ impl Car {
 
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub enum CarState {
-    Crossing(TimeInterval, DistanceInterval),
+    Crossing(TimeInterval, DistanceInterval, SpeedProfile),
     Queued,
     WaitingToAdvance,
     // Where's the front of the car while this is happening?
@@ -201,7 +323,7 @@ pub enum CarState {
 impl CarState {
     pub fn get_end_time(&self) -> Time {
         match self {
-            CarState::Crossing(ref time_int, _) => time_int.end,
+            CarState::Crossing(ref time_int, _, _) => time_int.end,
             CarState::Queued => unreachable!(),
             CarState::WaitingToAdvance => unreachable!(),
             CarState::Unparking(_, _, ref time_int) => time_int.end,
@@ -3,18 +3,31 @@ use crate::mechanics::Queue;
 use crate::{
     ActionAtEnd, AgentID, AgentMetadata, CarID, Command, CreateCar, DistanceInterval, DrawCarInput,
     Event, IntersectionSimState, ParkedCar, ParkingSimState, Scheduler, TimeInterval,
-    TransitSimState, TripManager, TripPositions, UnzoomedAgent, WalkingSimState,
+    TransitSimState, TripManager, TripPositions, UnzoomedAgent, VehicleType, WalkingSimState,
     FOLLOWING_DISTANCE,
 };
 use abstutil::{deserialize_btreemap, serialize_btreemap};
-use geom::{Distance, Duration, PolyLine, Time};
-use map_model::{BuildingID, LaneID, Map, Path, PathStep, Traversable};
+use geom::{Distance, Duration, PolyLine, Speed, Time};
+use map_model::{
+    BuildingID, IntersectionID, LaneID, Map, Path, PathStep, Position, RoadID, Traversable,
+    TurnPriority,
+};
+use petgraph::graphmap::DiGraphMap;
 use serde_derive::{Deserialize, Serialize};
-use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::collections::{BTreeMap, BTreeSet, HashSet, VecDeque};
 
 const TIME_TO_UNPARK: Duration = Duration::const_seconds(10.0);
 const TIME_TO_PARK: Duration = Duration::const_seconds(15.0);
-const TIME_TO_WAIT_AT_STOP: Duration = Duration::const_seconds(10.0);
+// Don't enter a roundabout if somebody circulating will arrive sooner than this.
+const ROUNDABOUT_CRITICAL_GAP: Duration = Duration::const_seconds(4.0);
+
+// When rerouting, assume each vehicle queued on a lane adds this much to the cost of using it.
+// TODO Bike costs are distances, not times, so this undercounts for them.
+const QUEUED_VEHICLE_PENALTY: usize = 3;
+
+// How often a vehicle stuck behind somebody partway along a lane looks for a gap in the next lane
+// over.
+const MIDBLOCK_LANECHANGE_RETRY: Duration = Duration::const_seconds(5.0);
 
 // TODO Do something else.
 pub(crate) const BLIND_RETRY_TO_CREEP_FORWARDS: Duration = Duration::const_seconds(0.1);
@@ -33,17 +46,33 @@ pub struct DrivingSimState {
     )]
     queues: BTreeMap<Traversable, Queue>,
     events: Vec<Event>,
+    // Temporary speed limits from incidents
+    #[serde(
+        serialize_with = "serialize_btreemap",
+        deserialize_with = "deserialize_btreemap"
+    )]
+    speed_caps: BTreeMap<RoadID, Speed>,
 
     recalc_lanechanging: bool,
+    reroute_blocked_after: Option<Duration>,
+    midblock_lanechanging: bool,
 }
 
 impl DrivingSimState {
-    pub fn new(map: &Map, recalc_lanechanging: bool) -> DrivingSimState {
+    pub fn new(
+        map: &Map,
+        recalc_lanechanging: bool,
+        reroute_blocked_after: Option<Duration>,
+        midblock_lanechanging: bool,
+    ) -> DrivingSimState {
         let mut sim = DrivingSimState {
             cars: BTreeMap::new(),
             queues: BTreeMap::new(),
             events: Vec::new(),
+            speed_caps: BTreeMap::new(),
             recalc_lanechanging,
+            reroute_blocked_after,
+            midblock_lanechanging,
         };
 
         for l in map.all_lanes() {
@@ -68,7 +97,7 @@ impl DrivingSimState {
         now: Time,
         params: CreateCar,
         map: &Map,
-        intersections: &IntersectionSimState,
+        intersections: &mut IntersectionSimState,
         parking: &ParkingSimState,
         scheduler: &mut Scheduler,
     ) -> bool {
@@ -92,6 +121,7 @@ impl DrivingSimState {
                 last_steps: VecDeque::new(),
                 blocked_since: None,
                 started_at: now,
+                last_speed: Speed::ZERO,
                 trip: params.trip,
             };
             if let Some(p) = params.maybe_parked_car {
@@ -125,7 +155,7 @@ impl DrivingSimState {
                     }
                 }
 
-                car.state = car.crossing_state(params.start_dist, now, map);
+                car.state = car.crossing_state(params.start_dist, now, map, &self.speed_caps);
             }
             scheduler.push(car.state.get_end_time(), Command::UpdateCar(car.vehicle.id));
             {
@@ -135,6 +165,9 @@ impl DrivingSimState {
                 // get_idx_to_insert_car does a more detailed check of the current space usage.
                 queue.reserved_length += car.vehicle.length + FOLLOWING_DISTANCE;
             }
+            if car.vehicle.vehicle_type == VehicleType::Emergency {
+                vehicle_approaching(&car, params.start_dist, now, map, intersections, scheduler);
+            }
             self.cars.insert(car.vehicle.id, car);
             return true;
         }
@@ -242,21 +275,42 @@ impl DrivingSimState {
         scheduler: &mut Scheduler,
     ) -> bool {
         match car.state {
-            CarState::Crossing(_, _) => {
+            CarState::Crossing(_, _, _) => {
+                let end_speed = match car.state {
+                    CarState::Crossing(_, _, ref profile) => profile.end_speed,
+                    _ => unreachable!(),
+                };
+                record_driving(&mut self.events, car, now);
                 car.state = CarState::Queued;
                 car.blocked_since = Some(now);
+                car.last_speed = Speed::ZERO;
                 if car.router.last_step() {
                     // Immediately run update_car_with_distances.
                     return true;
                 }
                 let queue = &self.queues[&car.router.head()];
                 if queue.cars[0] == car.vehicle.id && queue.laggy_head.is_none() {
-                    // Want to re-run, but no urgency about it happening immediately.
+                    // Want to re-run, but no urgency about it happening immediately. If the turn
+                    // is accepted right away, the car keeps its momentum.
+                    car.last_speed = end_speed;
                     car.state = CarState::WaitingToAdvance;
                     if self.recalc_lanechanging {
                         car.router.opportunistically_lanechange(&self.queues, map);
                     }
+                    if let Some(dt) = self.reroute_blocked_after {
+                        // Don't wait around for a closed lane or intersection to reopen.
+                        if intersections.is_turn_blocked(car.router.next().as_turn()) {
+                            self.reroute_car(car, now, map, intersections);
+                        }
+                        scheduler.update(now + dt, Command::RerouteIfBlocked(car.vehicle.id));
+                    }
                     scheduler.push(now, Command::UpdateCar(car.vehicle.id));
+                } else if self.midblock_lanechanging
+                    || car.vehicle.vehicle_type == VehicleType::Emergency
+                {
+                    // Stuck behind somebody. Can't look at positions until this car is back in
+                    // self.cars, so try a bit later.
+                    scheduler.update(now, Command::ChangeLanesIfBlocked(car.vehicle.id));
                 }
             }
             CarState::Unparking(front, _, _) => {
@@ -274,17 +328,25 @@ impl DrivingSimState {
                         &mut self.events,
                     );
                 }
-                car.state = car.crossing_state(front, now, map);
+                record_driving(&mut self.events, car, now);
+                car.state = car.crossing_state(front, now, map, &self.speed_caps);
                 scheduler.push(car.state.get_end_time(), Command::UpdateCar(car.vehicle.id));
             }
-            CarState::Idling(dist, _) => {
-                car.router = transit.bus_departed_from_stop(car.vehicle.id);
-                self.events
-                    .push(Event::PathAmended(car.router.get_path().clone()));
-                car.state = car.crossing_state(dist, now, map);
+            CarState::Idling(dist, ref time_int) => {
+                let idle_start = time_int.start;
+                record_driving(&mut self.events, car, now);
+                // Delivery trucks already know where they're going next.
+                if car.vehicle.vehicle_type.is_transit() {
+                    car.router = transit.bus_departed_from_stop(now, car.vehicle.id);
+                    self.events
+                        .push(Event::PathAmended(car.router.get_path().clone()));
+                    vehicle_approaching(car, dist, now, map, intersections, scheduler);
+                }
+                car.state = car.crossing_state(dist, now, map, &self.speed_caps);
                 scheduler.push(car.state.get_end_time(), Command::UpdateCar(car.vehicle.id));
 
                 // Update our follower, so they know we stopped idling.
+                let mut follower_delay = Duration::ZERO;
                 let queue = &self.queues[&car.router.head()];
                 let idx = queue
                     .cars
@@ -298,12 +360,17 @@ impl DrivingSimState {
                             // If they're on their last step, they might be ending early and not
                             // right behind us.
                             if !follower.router.last_step() {
+                                record_driving(&mut self.events, follower, now);
                                 follower.state = follower.crossing_state(
                                     // Since the follower was Queued, this must be where they are.
                                     dist - car.vehicle.length - FOLLOWING_DISTANCE,
                                     now,
                                     map,
+                                    &self.speed_caps,
                                 );
+                                if let Some(t) = follower.blocked_since {
+                                    follower_delay = now - t;
+                                }
                                 follower.blocked_since = None;
                                 scheduler.update(
                                     follower.state.get_end_time(),
@@ -314,12 +381,20 @@ impl DrivingSimState {
                         CarState::WaitingToAdvance => unreachable!(),
                         // They weren't blocked. Note that there's no way the Crossing state could jump
                         // forwards here; the leader is still in front of them.
-                        CarState::Crossing(_, _)
+                        CarState::Crossing(_, _, _)
                         | CarState::Unparking(_, _, _)
                         | CarState::Parking(_, _, _)
                         | CarState::Idling(_, _) => {}
                     }
                 }
+                if !car.vehicle.vehicle_type.is_transit() {
+                    self.events.push(Event::DoubleParked(
+                        car.vehicle.id,
+                        car.router.head().as_lane(),
+                        now - idle_start,
+                        follower_delay,
+                    ));
+                }
             }
             CarState::Queued => unreachable!(),
             CarState::WaitingToAdvance => {
@@ -333,6 +408,18 @@ impl DrivingSimState {
                     if let Some(s) = car.vehicle.max_speed {
                         speed = speed.min(s);
                     }
+                    if map.get_i(t.parent).is_roundabout()
+                        && map.get_roundabout_priority(t) == TurnPriority::Yield
+                    {
+                        if let Some(arrival) = self.circulating_car_arriving(t.parent, now, map) {
+                            // Nobody else will wake us up, since we never asked the intersection.
+                            scheduler.update(
+                                arrival + BLIND_RETRY_TO_CREEP_FORWARDS,
+                                Command::UpdateCar(car.vehicle.id),
+                            );
+                            return false;
+                        }
+                    }
                     if !intersections.maybe_start_turn(
                         AgentID::Car(car.vehicle.id),
                         t,
@@ -355,6 +442,7 @@ impl DrivingSimState {
                     assert_eq!(queue.cars.pop_front().unwrap(), car.vehicle.id);
                     queue.laggy_head = Some(car.vehicle.id);
                 }
+                record_driving(&mut self.events, car, now);
 
                 // We do NOT need to update the follower. If they were Queued, they'll remain that
                 // way, until laggy_head is None.
@@ -362,13 +450,22 @@ impl DrivingSimState {
                 let last_step =
                     car.router
                         .advance(&car.vehicle, parking, map, car.trip, &mut self.events);
-                car.state = car.crossing_state(Distance::ZERO, now, map);
+                car.state = car.crossing_state(Distance::ZERO, now, map, &self.speed_caps);
                 car.blocked_since = None;
                 scheduler.push(car.state.get_end_time(), Command::UpdateCar(car.vehicle.id));
+                if self.reroute_blocked_after.is_some() {
+                    scheduler.cancel(Command::RerouteIfBlocked(car.vehicle.id));
+                }
                 self.events.push(Event::AgentEntersTraversable(
                     AgentID::Car(car.vehicle.id),
                     goto,
                 ));
+                if goto.maybe_lane().is_some()
+                    && (transit.serves_route(car.vehicle.id)
+                        || car.vehicle.vehicle_type == VehicleType::Emergency)
+                {
+                    vehicle_approaching(car, Distance::ZERO, now, map, intersections, scheduler);
+                }
 
                 car.last_steps.push_front(last_step);
                 if goto.length(map) >= car.vehicle.length + FOLLOWING_DISTANCE {
@@ -384,6 +481,7 @@ impl DrivingSimState {
                             ),
                             now,
                             map,
+                            &self.speed_caps,
                         )
                         .get_end_time(),
                         Command::UpdateLaggyHead(car.vehicle.id),
@@ -405,6 +503,32 @@ impl DrivingSimState {
         false
     }
 
+    // If a car already on a roundabout will reach this intersection within the critical gap,
+    // returns when.
+    fn circulating_car_arriving(&self, i: IntersectionID, now: Time, map: &Map) -> Option<Time> {
+        let mut earliest: Option<Time> = None;
+        for l in &map.get_i(i).incoming_lanes {
+            if !map.get_parent(*l).is_roundabout() {
+                continue;
+            }
+            let queue = match self.queues.get(&Traversable::Lane(*l)) {
+                Some(q) => q,
+                None => continue,
+            };
+            if let Some(id) = queue.cars.front() {
+                if let CarState::Crossing(ref time_int, ref dist_int, _) = self.cars[id].state {
+                    if dist_int.end == queue.geom_len
+                        && time_int.end - now < ROUNDABOUT_CRITICAL_GAP
+                        && earliest.map(|t| time_int.end < t).unwrap_or(true)
+                    {
+                        earliest = Some(time_int.end);
+                    }
+                }
+            }
+        }
+        earliest
+    }
+
     // Returns true if the car survives.
     fn update_car_with_distances(
         &mut self,
@@ -426,9 +550,10 @@ impl DrivingSimState {
         let our_dist = dists[idx].1;
 
         // Just two cases here. In all cases, we leave the Queued state.
+        record_driving(&mut self.events, car, now);
         car.blocked_since = None;
         match car.state {
-            CarState::Crossing(_, _)
+            CarState::Crossing(_, _, _)
             | CarState::Unparking(_, _, _)
             | CarState::Idling(_, _)
             | CarState::WaitingToAdvance => unreachable!(),
@@ -462,7 +587,7 @@ impl DrivingSimState {
                         return true;
                     }
                     Some(ActionAtEnd::GotoLaneEnd) => {
-                        car.state = car.crossing_state(our_dist, now, map);
+                        car.state = car.crossing_state(our_dist, now, map, &self.speed_caps);
                         scheduler
                             .push(car.state.get_end_time(), Command::UpdateCar(car.vehicle.id));
                         return true;
@@ -471,21 +596,40 @@ impl DrivingSimState {
                         trips.bike_reached_end(now, car.vehicle.id, bike_rack, map, scheduler);
                     }
                     Some(ActionAtEnd::BusAtStop) => {
-                        transit.bus_arrived_at_stop(
+                        // Otherwise the bus is out of service and vanishes.
+                        if let Some(depart) = transit.bus_arrived_at_stop(
                             now,
                             car.vehicle.id,
                             trips,
                             walking,
                             scheduler,
                             map,
-                        );
-                        car.state = CarState::Idling(
-                            our_dist,
-                            TimeInterval::new(now, now + TIME_TO_WAIT_AT_STOP),
-                        );
-                        scheduler
-                            .push(car.state.get_end_time(), Command::UpdateCar(car.vehicle.id));
-                        return true;
+                        ) {
+                            car.state = CarState::Idling(our_dist, TimeInterval::new(now, depart));
+                            scheduler
+                                .push(car.state.get_end_time(), Command::UpdateCar(car.vehicle.id));
+                            return true;
+                        }
+                    }
+                    Some(ActionAtEnd::DoubleParkForDelivery) => {
+                        if let Some((router, dwell)) = trips.truck_double_parked(
+                            now,
+                            car.vehicle.id,
+                            Position::new(car.router.head().as_lane(), our_dist),
+                            map,
+                        ) {
+                            car.router = router;
+                            self.events
+                                .push(Event::PathAmended(car.router.get_path().clone()));
+                            car.state =
+                                CarState::Idling(our_dist, TimeInterval::new(now, now + dwell));
+                            scheduler
+                                .push(car.state.get_end_time(), Command::UpdateCar(car.vehicle.id));
+                            return true;
+                        }
+                    }
+                    Some(ActionAtEnd::EmergencyVehicleArrived) => {
+                        trips.emergency_vehicle_arrived(now, car.vehicle.id);
                     }
                     None => {
                         scheduler.push(
@@ -498,7 +642,7 @@ impl DrivingSimState {
                         /*
                         // If this car wasn't blocked at all, when would it reach its goal?
                         let ideal_end_time = match car.crossing_state(our_dist, now, map) {
-                            CarState::Crossing(time_int, _) => time_int.end,
+                            CarState::Crossing(time_int, _, _) => time_int.end,
                             _ => unreachable!(),
                         };
                         if ideal_end_time == now {
@@ -560,6 +704,383 @@ impl DrivingSimState {
         scheduler.cancel(Command::UpdateCar(c));
     }
 
+    pub fn reroute_if_blocked(
+        &mut self,
+        id: CarID,
+        now: Time,
+        map: &Map,
+        intersections: &mut IntersectionSimState,
+        scheduler: &mut Scheduler,
+    ) {
+        // The car might've been deleted since this was scheduled.
+        let mut car = match self.cars.remove(&id) {
+            Some(car) => car,
+            None => {
+                return;
+            }
+        };
+        if car.state == CarState::WaitingToAdvance {
+            let dt = self.reroute_blocked_after.unwrap();
+            if now - car.blocked_since.unwrap() >= dt
+                && self.reroute_car(&mut car, now, map, intersections)
+            {
+                scheduler.update(now, Command::UpdateCar(id));
+            }
+            // Keep trying until the car moves.
+            scheduler.push(now + dt, Command::RerouteIfBlocked(id));
+        }
+        self.cars.insert(id, car);
+    }
+
+    // Only for cars WaitingToAdvance. Returns true if the car has a new path. The caller is
+    // responsible for waking the car up.
+    fn reroute_car(
+        &mut self,
+        car: &mut Car,
+        now: Time,
+        map: &Map,
+        intersections: &mut IntersectionSimState,
+    ) -> bool {
+        let stuck_turn = car.router.next().as_turn();
+        let waited = now - car.blocked_since.unwrap();
+        let queues = &self.queues;
+        let blocked = &*intersections;
+        let rerouted = car
+            .router
+            .reroute_with_costs(&car.vehicle, map, |lane, turn, cost| {
+                if blocked.is_turn_blocked(turn.id) {
+                    return None;
+                }
+                let num_queued = queues
+                    .get(&Traversable::Lane(lane.id))
+                    .map(|q| q.cars.len())
+                    .unwrap_or(0);
+                let mut cost = cost + num_queued * QUEUED_VEHICLE_PENALTY;
+                // The turn we're stuck at costs at least as much as we've waited so far.
+                if turn.id == stuck_turn {
+                    cost += waited.inner_seconds().round() as usize;
+                }
+                Some(cost)
+            });
+        if !rerouted {
+            return false;
+        }
+        // We might've already asked to do the old turn.
+        intersections.cancel_request(AgentID::Car(car.vehicle.id), stuck_turn);
+        self.events
+            .push(Event::PathAmended(car.router.get_path().clone()));
+        true
+    }
+
+    // Vehicles can't pull over mid-block in the queue model. Instead, an emergency vehicle stuck
+    // behind stopped traffic trades places with everybody stopped ahead of it, as if they pulled
+    // over to let it by. Whoever's already waiting at the intersection still goes first. Returns
+    // true if the emergency vehicle moved up.
+    fn pass_stopped_vehicles(&mut self, id: CarID, now: Time, scheduler: &mut Scheduler) -> bool {
+        let head = match self.cars.get(&id) {
+            Some(car)
+                if car.state == CarState::Queued
+                    && car.vehicle.vehicle_type == VehicleType::Emergency
+                    && !car.router.last_step()
+                    && car.last_steps.is_empty() =>
+            {
+                car.router.head()
+            }
+            _ => {
+                return false;
+            }
+        };
+        let queue = &self.queues[&head];
+        let start_idx = queue.cars.iter().position(|c| *c == id).unwrap();
+        let mut idx = start_idx;
+        while idx > 0 {
+            let leader = &self.cars[&queue.cars[idx - 1]];
+            if leader.state != CarState::Queued
+                || leader.router.last_step()
+                || leader.vehicle.vehicle_type == VehicleType::Emergency
+            {
+                break;
+            }
+            idx -= 1;
+        }
+        if idx == start_idx {
+            return false;
+        }
+
+        // Queued cars sit right behind their leader, so reordering them moves everybody in the
+        // block: the emergency vehicle jumps to where the first passed car's front was, and the
+        // passed cars slide back by its length plus the following distance. The block as a whole
+        // covers the same stretch, so nobody behind it moves. Recompute the positions and make
+        // sure everybody still fits on the lane; the passed cars can't back up into the previous
+        // lane.
+        let queue = self.queues.get_mut(&head).unwrap();
+        for i in (idx..start_idx).rev() {
+            queue.cars.swap(i, i + 1);
+        }
+        let positions = self.queues[&head].get_car_positions(now, &self.cars, &self.queues);
+        let fits = positions[idx..=start_idx]
+            .iter()
+            .all(|(car, front)| *front - self.cars[car].vehicle.length >= Distance::ZERO);
+        if !fits {
+            let queue = self.queues.get_mut(&head).unwrap();
+            for i in idx..start_idx {
+                queue.cars.swap(i, i + 1);
+            }
+            return false;
+        }
+        if idx == 0 && queue.laggy_head.is_none() {
+            let car = self.cars.get_mut(&id).unwrap();
+            car.state = CarState::WaitingToAdvance;
+            scheduler.push(now, Command::UpdateCar(id));
+        }
+        true
+    }
+
+    // Emergency vehicles stuck behind somebody get waved through first. Otherwise, only used when
+    // SimOptions enables mid-block lane-changing. A vehicle stuck behind somebody partway along a
+    // lane moves over to an adjacent lane going the same way, if there's a gap there and more room
+    // ahead. This is how cars overtake slow bikes.
+    // TODO The vehicle jumps sideways instantly.
+    pub fn change_lanes_if_blocked(
+        &mut self,
+        id: CarID,
+        now: Time,
+        map: &Map,
+        intersections: &mut IntersectionSimState,
+        scheduler: &mut Scheduler,
+    ) {
+        if self.pass_stopped_vehicles(id, now, scheduler) || !self.midblock_lanechanging {
+            return;
+        }
+        // The car might've been deleted or started moving again since this was scheduled.
+        let from = match self.cars.get(&id) {
+            Some(car)
+                if car.state == CarState::Queued
+                    && !car.router.last_step()
+                    && car.last_steps.is_empty()
+                    && !car.vehicle.vehicle_type.is_transit() =>
+            {
+                car.router.head()
+            }
+            _ => {
+                return;
+            }
+        };
+        let l = match from {
+            Traversable::Lane(l) => l,
+            Traversable::Turn(_) => {
+                return;
+            }
+        };
+        let dists = self.queues[&from].get_car_positions(now, &self.cars, &self.queues);
+        let idx = dists.iter().position(|(c, _)| *c == id).unwrap();
+        // Waiting at the front of the queue is a different problem.
+        if idx == 0 {
+            return;
+        }
+        let our_dist = dists[idx].1;
+        let (leader, leader_dist) = dists[idx - 1];
+        let room_ahead = leader_dist - self.cars[&leader].vehicle.length - our_dist;
+
+        let mut car = self.cars.remove(&id).unwrap();
+        let our_len = car.vehicle.length;
+        let parent = map.get_parent(l);
+        let (fwds, offset) = parent.dir_and_offset(l);
+        let siblings = if fwds {
+            &parent.children_forwards
+        } else {
+            &parent.children_backwards
+        };
+        let constraints = car.vehicle.vehicle_type.to_constraints();
+
+        let mut best: Option<(Distance, LaneID, Distance, usize, Vec<PathStep>)> = None;
+        for (sibling_idx, (to, _)) in siblings.iter().enumerate() {
+            if sibling_idx + 1 != offset && sibling_idx != offset + 1 {
+                continue;
+            }
+            if !constraints.can_use(map.get_l(*to), map) {
+                continue;
+            }
+            let queue = match self.queues.get(&Traversable::Lane(*to)) {
+                Some(q) => q,
+                None => continue,
+            };
+            let new_dist = Position::new(l, our_dist)
+                .equiv_pos(*to, our_len, map)
+                .dist_along();
+            // Leave room for somebody entering the lane behind us.
+            if new_dist < our_len + FOLLOWING_DISTANCE
+                || new_dist >= queue.geom_len
+                || !queue.room_for_car(&car)
+            {
+                continue;
+            }
+            let new_idx =
+                match queue.get_idx_to_insert_car(new_dist, our_len, now, &self.cars, &self.queues)
+                {
+                    Some(i) => i,
+                    None => continue,
+                };
+            let new_room_ahead = if new_idx == 0 {
+                queue.geom_len - new_dist
+            } else {
+                let (new_leader, new_leader_dist) =
+                    queue.get_car_positions(now, &self.cars, &self.queues)[new_idx - 1];
+                new_leader_dist - self.cars[&new_leader].vehicle.length - new_dist
+            };
+            // Only worth it if we can actually get somewhere.
+            if new_room_ahead < room_ahead + our_len + FOLLOWING_DISTANCE {
+                continue;
+            }
+            if best
+                .as_ref()
+                .map(|(room, _, _, _, _)| new_room_ahead <= *room)
+                .unwrap_or(false)
+            {
+                continue;
+            }
+            if let Some(steps) = car.router.midblock_lanechange(*to, map) {
+                best = Some((new_room_ahead, *to, new_dist, new_idx, steps));
+            }
+        }
+
+        let (to, new_dist, new_idx, steps) = match best {
+            Some((_, to, new_dist, new_idx, steps)) => (to, new_dist, new_idx, steps),
+            None => {
+                scheduler.update(
+                    now + MIDBLOCK_LANECHANGE_RETRY,
+                    Command::ChangeLanesIfBlocked(id),
+                );
+                self.cars.insert(id, car);
+                return;
+            }
+        };
+
+        {
+            let queue = self.queues.get_mut(&from).unwrap();
+            assert_eq!(queue.cars.remove(idx).unwrap(), id);
+            queue.free_reserved_space(&car);
+            intersections.space_freed(now, map.get_l(l).src_i, scheduler, map);
+        }
+        self.update_follower(&dists, idx, now, map, scheduler);
+        {
+            let queue = self.queues.get_mut(&Traversable::Lane(to)).unwrap();
+            queue.cars.insert(new_idx, id);
+            // room_for_car and get_idx_to_insert_car already checked for room.
+            queue.reserved_length += our_len + FOLLOWING_DISTANCE;
+        }
+
+        record_driving(&mut self.events, &car, now);
+        car.router.change_lanes(steps, map);
+        self.events
+            .push(Event::PathAmended(car.router.get_path().clone()));
+        car.state = car.crossing_state(new_dist, now, map, &self.speed_caps);
+        car.blocked_since = None;
+        scheduler.update(car.state.get_end_time(), Command::UpdateCar(id));
+        if car.vehicle.vehicle_type == VehicleType::Emergency {
+            // The intersection is expecting a different turn now.
+            vehicle_approaching(&car, new_dist, now, map, intersections, scheduler);
+        }
+        self.cars.insert(id, car);
+    }
+
+    // TODO Cars already crossing the road keep their old speed until they start their next step.
+    pub fn cap_speed(&mut self, r: RoadID, speed: Speed) {
+        self.speed_caps.insert(r, speed);
+    }
+
+    pub fn uncap_speed(&mut self, r: RoadID) {
+        self.speed_caps.remove(&r);
+    }
+
+    // Map edits were just applied without resetting the simulation. Reroutes cars whose path
+    // doesn't work anymore. Returns the cars that had to be deleted, because they were partly on a
+    // turn that's gone or can't reach their goal anymore.
+    // TODO Cars on a lane that isn't for vehicles anymore are left alone if it's their last step.
+    pub fn handle_live_edits(
+        &mut self,
+        now: Time,
+        map: &Map,
+        scheduler: &mut Scheduler,
+        intersections: &mut IntersectionSimState,
+    ) -> Vec<CarID> {
+        // Start from the back of each queue, so deleting a car never updates a follower on a turn
+        // that's gone.
+        let mut doomed: Vec<CarID> = Vec::new();
+        for q in self.queues.values() {
+            if let Traversable::Turn(t) = q.id {
+                if map.maybe_get_t(t).is_none() {
+                    doomed.extend(q.cars.iter().rev().cloned());
+                }
+            }
+        }
+        for car in self.cars.values() {
+            if !doomed.contains(&car.vehicle.id)
+                && car.last_steps.iter().any(|on| match on {
+                    Traversable::Turn(t) => map.maybe_get_t(*t).is_none(),
+                    Traversable::Lane(_) => false,
+                })
+            {
+                doomed.push(car.vehicle.id);
+            }
+        }
+        for id in &doomed {
+            self.kill_stuck_car(*id, now, map, scheduler, intersections);
+        }
+
+        let mut stranded: Vec<CarID> = Vec::new();
+        for car in self.cars.values_mut() {
+            if !car.router.path_broken_by_edits(&car.vehicle, map) {
+                continue;
+            }
+            let old_next = car.router.maybe_next();
+            if !car.router.reroute_after_edits(&car.vehicle, map) {
+                println!(
+                    "{} can't reach its goal after map edits; deleting it",
+                    car.vehicle.id
+                );
+                stranded.push(car.vehicle.id);
+                continue;
+            }
+            self.events
+                .push(Event::PathAmended(car.router.get_path().clone()));
+            // Whatever turn the car was waiting to make might not be its next step anymore.
+            if let CarState::WaitingToAdvance = car.state {
+                if let Some(Traversable::Turn(t)) = old_next {
+                    intersections.cancel_request(AgentID::Car(car.vehicle.id), t);
+                }
+                scheduler.update(now, Command::UpdateCar(car.vehicle.id));
+            }
+        }
+        for id in &stranded {
+            self.kill_stuck_car(*id, now, map, scheduler, intersections);
+        }
+
+        // Old queues stick around until they're empty.
+        self.queues.retain(|id, q| {
+            let exists = match id {
+                Traversable::Lane(l) => map.get_l(*l).lane_type.is_for_moving_vehicles(),
+                Traversable::Turn(t) => map.maybe_get_t(*t).is_some(),
+            };
+            exists || !q.cars.is_empty() || q.laggy_head.is_some()
+        });
+        for l in map.all_lanes() {
+            let id = Traversable::Lane(l.id);
+            if l.lane_type.is_for_moving_vehicles() && !self.queues.contains_key(&id) {
+                self.queues.insert(id, Queue::new(id, map));
+            }
+        }
+        for t in map.all_turns().values() {
+            let id = Traversable::Turn(t.id);
+            if !t.between_sidewalks() && !self.queues.contains_key(&id) {
+                self.queues.insert(id, Queue::new(id, map));
+            }
+        }
+
+        doomed.extend(stranded);
+        doomed
+    }
+
     fn delete_car(
         &mut self,
         car: &mut Car,
@@ -570,6 +1091,7 @@ impl DrivingSimState {
         scheduler: &mut Scheduler,
         intersections: &mut IntersectionSimState,
     ) {
+        record_driving(&mut self.events, car, now);
         {
             let queue = self.queues.get_mut(&car.router.head()).unwrap();
             assert_eq!(queue.cars.remove(idx).unwrap(), car.vehicle.id);
@@ -589,19 +1111,34 @@ impl DrivingSimState {
         scheduler.cancel(Command::UpdateLaggyHead(car.vehicle.id));
 
         // Update the follower so that they don't suddenly jump forwards.
+        // TODO If the leader vanished at a border node, this still jumps a bit -- the lead car's
+        // back is still sticking out. Need to still be bound by them, even though they don't
+        // exist! If the leader just parked, then we're fine.
+        self.update_follower(&dists, idx, now, map, scheduler);
+    }
+
+    // The car at idx just left the queue. Recalculate the follower's state so they don't suddenly
+    // jump forwards.
+    fn update_follower(
+        &mut self,
+        dists: &Vec<(CarID, Distance)>,
+        idx: usize,
+        now: Time,
+        map: &Map,
+        scheduler: &mut Scheduler,
+    ) {
         if idx != dists.len() - 1 {
             let (follower_id, follower_dist) = dists[idx + 1];
             let mut follower = self.cars.get_mut(&follower_id).unwrap();
-            // TODO If the leader vanished at a border node, this still jumps a bit -- the
-            // lead car's back is still sticking out. Need to still be bound by them, even
-            // though they don't exist! If the leader just parked, then we're fine.
             match follower.state {
-                CarState::Queued | CarState::Crossing(_, _) => {
+                CarState::Queued | CarState::Crossing(_, _, _) => {
                     // If the follower was still Crossing, they might not've been blocked
                     // by leader yet. In that case, recalculating their Crossing state is a
                     // no-op. But if they were blocked, then this will prevent them from
                     // jumping forwards.
-                    follower.state = follower.crossing_state(follower_dist, now, map);
+                    record_driving(&mut self.events, follower, now);
+                    follower.state =
+                        follower.crossing_state(follower_dist, now, map, &self.speed_caps);
                     follower.blocked_since = None;
                     scheduler.update(
                         follower.state.get_end_time(),
@@ -645,6 +1182,7 @@ impl DrivingSimState {
                         DistanceInterval::new_driving(our_dist, our_len),
                         now,
                         map,
+                        &self.speed_caps,
                     )
                     .get_end_time();
                 // Sometimes due to rounding, retry_at will be exactly time, but we really need to
@@ -728,7 +1266,7 @@ impl DrivingSimState {
                         CarState::WaitingToAdvance => unreachable!(),
                         // They weren't blocked. Note that there's no way the Crossing state could jump
                         // forwards here; the leader vanished from the end of the traversable.
-                        CarState::Crossing(_, _)
+                        CarState::Crossing(_, _, _)
                         | CarState::Unparking(_, _, _)
                         | CarState::Parking(_, _, _)
                         | CarState::Idling(_, _) => {}
@@ -957,7 +1495,165 @@ impl DrivingSimState {
         }
     }
 
+    // Build a graph of which vehicles are waiting on which others, and find groups that're all
+    // waiting on each other in a cycle. None of them will ever move again on their own. Returns
+    // the vehicles (the one blocked the longest first), and the lanes and intersections involved.
+    pub fn find_gridlock(
+        &self,
+        map: &Map,
+        intersections: &IntersectionSimState,
+    ) -> Vec<(Vec<CarID>, Vec<LaneID>, Vec<IntersectionID>)> {
+        let mut waits_for: Vec<(CarID, CarID)> = Vec::new();
+        for queue in self.queues.values() {
+            let mut leader = queue.laggy_head;
+            for id in &queue.cars {
+                let car = &self.cars[id];
+                match car.state {
+                    CarState::Queued => {
+                        if let Some(leader) = leader {
+                            waits_for.push((*id, leader));
+                        }
+                    }
+                    CarState::WaitingToAdvance => {
+                        if let Traversable::Turn(t) = car.router.next() {
+                            // Anybody already doing the same or a conflicting turn here might be
+                            // stuck inside, in the way.
+                            let turn = map.get_t(t);
+                            for (agent, other_turn) in intersections.get_accepted_turns(t.parent) {
+                                if let AgentID::Car(other) = agent {
+                                    if other_turn == t || turn.conflicts_with(map.get_t(other_turn))
+                                    {
+                                        waits_for.push((*id, other));
+                                    }
+                                }
+                            }
+                            // Is there room to go where we want?
+                            let target = &self.queues[&Traversable::Lane(t.dst)];
+                            if !target.room_for_car(car) {
+                                let tail = target.cars.back().or(target.laggy_head.as_ref());
+                                if let Some(tail) = tail {
+                                    waits_for.push((*id, *tail));
+                                }
+                            }
+                        }
+                    }
+                    // Everybody else is making progress.
+                    _ => {}
+                }
+                leader = Some(*id);
+            }
+        }
+
+        let mut results = Vec::new();
+        for mut cars in find_cycles(&waits_for) {
+            cars.sort_by_key(|id| (self.cars[id].blocked_since, *id));
+            let mut lanes = BTreeSet::new();
+            let mut intersections = BTreeSet::new();
+            for id in &cars {
+                match self.cars[id].router.head() {
+                    Traversable::Lane(l) => {
+                        lanes.insert(l);
+                        intersections.insert(map.get_l(l).dst_i);
+                    }
+                    Traversable::Turn(t) => {
+                        intersections.insert(t.parent);
+                    }
+                }
+            }
+            results.push((
+                cars,
+                lanes.into_iter().collect(),
+                intersections.into_iter().collect(),
+            ));
+        }
+        // kosaraju_scc doesn't promise any order
+        results.sort();
+        results
+    }
+
     pub fn collect_events(&mut self) -> Vec<Event> {
         std::mem::replace(&mut self.events, Vec::new())
     }
 }
+
+// Groups of things that are all waiting on each other, directly or not, in some cycle. Each
+// `waits_for` edge is (waiting, on). Each group is sorted, and so is the list of groups.
+pub fn find_cycles<T: Copy + Ord + std::hash::Hash>(waits_for: &Vec<(T, T)>) -> Vec<Vec<T>> {
+    let mut graph: DiGraphMap<T, ()> = DiGraphMap::new();
+    for (from, to) in waits_for {
+        graph.add_edge(*from, *to, ());
+    }
+    let mut results: Vec<Vec<T>> = petgraph::algo::kosaraju_scc(&graph)
+        .into_iter()
+        .filter(|group| group.len() >= 2)
+        .map(|mut group| {
+            group.sort();
+            group
+        })
+        .collect();
+    // kosaraju_scc doesn't promise any order
+    results.sort();
+    results
+}
+
+// Report how the car drove in its current state, right before that state changes. On turns, also
+// report where it was when, for finding conflicts.
+fn record_driving(events: &mut Vec<Event>, car: &Car, now: Time) {
+    let modes = car.driving_modes(now);
+    if !modes.is_empty() {
+        events.push(Event::VehicleDrove(
+            car.vehicle.id,
+            car.trip,
+            car.router.head(),
+            modes,
+        ));
+    }
+    if let (CarState::Crossing(ref time_int, ref dist_int, ref profile), Traversable::Turn(t)) =
+        (&car.state, car.router.head())
+    {
+        events.push(Event::VehicleMovedOnTurn(
+            car.vehicle.id,
+            car.vehicle.length,
+            t,
+            time_int.start,
+            dist_int.start,
+            profile.clone(),
+        ));
+    }
+}
+
+// Let the next intersection know a bus is coming, in case it's a signal with transit priority, or
+// that an emergency vehicle is coming, so it can preempt signals and hold other traffic.
+// TODO Buses only announce themselves when they reach a new lane, not when they first spawn.
+fn vehicle_approaching(
+    car: &Car,
+    front: Distance,
+    now: Time,
+    map: &Map,
+    intersections: &mut IntersectionSimState,
+    scheduler: &mut Scheduler,
+) {
+    let head = car.router.head();
+    if head.maybe_lane().is_none() {
+        return;
+    }
+    if let Some(Traversable::Turn(t)) = car.router.maybe_next() {
+        let mut speed = head.speed_limit(map);
+        if let Some(s) = car.vehicle.max_speed {
+            speed = speed.min(s);
+        }
+        let eta = now + (head.length(map) - front) / speed;
+        if car.vehicle.vehicle_type == VehicleType::Emergency {
+            intersections.emergency_vehicle_approaching(
+                now,
+                car.vehicle.id,
+                t,
+                eta,
+                map,
+                scheduler,
+            );
+        } else {
+            intersections.bus_approaching(now, car.vehicle.id, t, eta, map, scheduler);
+        }
+    }
+}
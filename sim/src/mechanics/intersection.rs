@@ -1,6 +1,6 @@
 use crate::mechanics::car::Car;
 use crate::mechanics::Queue;
-use crate::{AgentID, Command, Event, Scheduler, Speed};
+use crate::{AgentID, CarID, Command, Event, Scheduler, Speed};
 use abstutil::{deserialize_btreemap, serialize_btreemap};
 use derivative::Derivative;
 use geom::{Duration, Time};
@@ -13,12 +13,16 @@ use std::collections::{BTreeMap, BTreeSet, HashSet};
 
 const WAIT_AT_STOP_SIGN: Duration = Duration::const_seconds(0.5);
 const WAIT_BEFORE_YIELD_AT_TRAFFIC_SIGNAL: Duration = Duration::const_seconds(0.2);
+// Stop holding traffic for an emergency vehicle that's this late; it probably went elsewhere.
+const EMERGENCY_VEHICLE_GRACE: Duration = Duration::const_seconds(30.0);
 
 #[derive(Serialize, Deserialize, PartialEq)]
 pub struct IntersectionSimState {
     state: BTreeMap<IntersectionID, State>,
     use_freeform_policy_everywhere: bool,
     force_queue_entry: bool,
+    // From incidents. Nobody can start a turn into these.
+    blocked_lanes: BTreeSet<LaneID>,
     events: Vec<Event>,
 }
 
@@ -33,6 +37,40 @@ struct State {
         deserialize_with = "deserialize_btreemap"
     )]
     waiting: BTreeMap<Request, Time>,
+    // Only for traffic signals
+    signal: Option<SignalState>,
+    // From incidents
+    closed: bool,
+    // A traffic signal that's failed acts like an all-way stop.
+    failed_signal: Option<ControlStopSign>,
+    // Only for traffic signals with transit priority. Buses on their way, the turn they want to
+    // do, and roughly when they'll arrive.
+    #[serde(
+        serialize_with = "serialize_btreemap",
+        deserialize_with = "deserialize_btreemap"
+    )]
+    approaching_buses: BTreeMap<CarID, (TurnID, Time)>,
+    // Emergency vehicles on their way, the turn they want to do, and roughly when they'll arrive.
+    // Conflicting traffic holds for them, and traffic signals switch to serve them.
+    #[serde(
+        serialize_with = "serialize_btreemap",
+        deserialize_with = "deserialize_btreemap"
+    )]
+    approaching_emergency_vehicles: BTreeMap<CarID, (TurnID, Time)>,
+}
+
+// Fixed-time phases just cycle through in order. Actuated phases can be extended or skipped based
+// on demand.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+struct SignalState {
+    current_phase: usize,
+    phase_started: Time,
+    // When UpdateIntersection is next scheduled
+    next_check: Time,
+    // The last time somebody asked to do a turn allowed by the current phase
+    last_demand: Option<Time>,
+    // Transit priority already ended this phase early for a bus
+    cut_short: bool,
 }
 
 impl IntersectionSimState {
@@ -46,20 +84,36 @@ impl IntersectionSimState {
             state: BTreeMap::new(),
             use_freeform_policy_everywhere,
             force_queue_entry: disable_block_the_box,
+            blocked_lanes: BTreeSet::new(),
             events: Vec::new(),
         };
         for i in map.all_intersections() {
-            sim.state.insert(
-                i.id,
-                State {
-                    id: i.id,
-                    accepted: BTreeSet::new(),
-                    waiting: BTreeMap::new(),
-                },
-            );
+            let mut state = State {
+                id: i.id,
+                accepted: BTreeSet::new(),
+                waiting: BTreeMap::new(),
+                signal: None,
+                closed: false,
+                failed_signal: None,
+                approaching_buses: BTreeMap::new(),
+                approaching_emergency_vehicles: BTreeMap::new(),
+            };
             if i.is_traffic_signal() && !use_freeform_policy_everywhere {
-                sim.update_intersection(Time::START_OF_DAY, i.id, map, scheduler);
+                // The offset determines what phase we start in.
+                let (idx, _, remaining) = map
+                    .get_traffic_signal(i.id)
+                    .current_phase_and_remaining_time(Time::START_OF_DAY);
+                let next_check = Time::START_OF_DAY + remaining;
+                state.signal = Some(SignalState {
+                    current_phase: idx,
+                    phase_started: Time::START_OF_DAY,
+                    next_check,
+                    last_demand: None,
+                    cut_short: false,
+                });
+                scheduler.push(next_check, Command::UpdateIntersection(i.id));
             }
+            sim.state.insert(i.id, state);
         }
         sim
     }
@@ -71,6 +125,11 @@ impl IntersectionSimState {
             .any(|req| req.turn.dst == lane)
     }
 
+    // Is an incident currently preventing anybody from starting this turn?
+    pub fn is_turn_blocked(&self, t: TurnID) -> bool {
+        self.state[&t.parent].closed || self.blocked_lanes.contains(&t.dst)
+    }
+
     pub fn turn_finished(
         &mut self,
         now: Time,
@@ -80,9 +139,19 @@ impl IntersectionSimState {
         map: &Map,
     ) {
         let state = self.state.get_mut(&turn.parent).unwrap();
-        assert!(state.accepted.remove(&Request { agent, turn }));
-        if map.get_t(turn).turn_type != TurnType::SharedSidewalkCorner {
-            self.wakeup_waiting(now, turn.parent, scheduler, map);
+        let was_accepted = state.accepted.remove(&Request { agent, turn });
+        match map.maybe_get_t(turn) {
+            Some(t) => {
+                assert!(was_accepted);
+                if t.turn_type != TurnType::SharedSidewalkCorner {
+                    self.wakeup_waiting(now, turn.parent, scheduler, map);
+                }
+            }
+            // Live map edits deleted the turn while the agent was finishing it, and the request
+            // was already forgotten.
+            None => {
+                self.wakeup_waiting(now, turn.parent, scheduler, map);
+            }
         }
     }
 
@@ -90,6 +159,97 @@ impl IntersectionSimState {
     pub fn cancel_request(&mut self, agent: AgentID, turn: TurnID) {
         let state = self.state.get_mut(&turn.parent).unwrap();
         state.waiting.remove(&Request { agent, turn });
+        if let AgentID::Car(c) = agent {
+            state.approaching_buses.remove(&c);
+            state.approaching_emergency_vehicles.remove(&c);
+        }
+    }
+
+    // A bus just started driving towards this turn, and should reach it around eta. If the signal
+    // has transit priority and the bus has to wait for another phase, end the current phase early.
+    // If the current phase serves the bus, update_intersection might hold it.
+    // TODO Jump straight to the phase serving the bus, instead of just the next one.
+    pub fn bus_approaching(
+        &mut self,
+        now: Time,
+        bus: CarID,
+        turn: TurnID,
+        eta: Time,
+        map: &Map,
+        scheduler: &mut Scheduler,
+    ) {
+        let signal = match map.maybe_get_traffic_signal(turn.parent) {
+            Some(signal) => signal,
+            None => {
+                return;
+            }
+        };
+        let tsp = match signal.transit_priority {
+            Some(ref tsp) => tsp,
+            None => {
+                return;
+            }
+        };
+        let state = self.state.get_mut(&turn.parent).unwrap();
+        if state.failed_signal.is_some() {
+            return;
+        }
+        let ss = if let Some(ref mut ss) = state.signal {
+            ss
+        } else {
+            return;
+        };
+        state.approaching_buses.insert(bus, (turn, eta));
+
+        if !ss.cut_short
+            && signal.phases[ss.current_phase].get_priority_of_turn(turn, signal)
+                == TurnPriority::Banned
+        {
+            let end = (ss.next_check - tsp.max_red_truncation).max(now);
+            if end < ss.next_check {
+                ss.next_check = end;
+                ss.cut_short = true;
+                // Don't let an actuated phase extend itself.
+                ss.last_demand = None;
+                scheduler.update(end, Command::UpdateIntersection(turn.parent));
+            }
+        }
+    }
+
+    // An emergency vehicle just started driving towards this turn, and should reach it around eta.
+    // Conflicting traffic will hold for it. A traffic signal immediately ends any phase that
+    // doesn't serve the turn.
+    pub fn emergency_vehicle_approaching(
+        &mut self,
+        now: Time,
+        car: CarID,
+        turn: TurnID,
+        eta: Time,
+        map: &Map,
+        scheduler: &mut Scheduler,
+    ) {
+        let state = self.state.get_mut(&turn.parent).unwrap();
+        state
+            .approaching_emergency_vehicles
+            .insert(car, (turn, eta));
+        if state.failed_signal.is_some() {
+            return;
+        }
+        let signal = match map.maybe_get_traffic_signal(turn.parent) {
+            Some(signal) => signal,
+            None => {
+                return;
+            }
+        };
+        if let Some(ref mut ss) = state.signal {
+            if signal.phases[ss.current_phase].get_priority_of_turn(turn, signal)
+                == TurnPriority::Banned
+            {
+                ss.next_check = now;
+                ss.last_demand = None;
+                scheduler.update(now, Command::UpdateIntersection(turn.parent));
+            }
+        }
     }
 
     pub fn space_freed(
@@ -106,9 +266,15 @@ impl IntersectionSimState {
         /*if i == IntersectionID(64) {
             println!("at {}: wakeup_waiting -----------------", now);
         }*/
-        let mut all: Vec<(Request, Time)> = self.state[&i]
+        let state = &self.state[&i];
+        // Whoever ends the incident will wake everybody up.
+        if state.closed {
+            return;
+        }
+        let mut all: Vec<(Request, Time)> = state
             .waiting
             .iter()
+            .filter(|(r, _)| !self.blocked_lanes.contains(&r.turn.dst))
             .map(|(r, t)| (r.clone(), *t))
             .collect();
         // Sort by waiting time, so things like stop signs actually are first-come, first-served.
@@ -124,8 +290,21 @@ impl IntersectionSimState {
             for (req, _) in all {
                 protected.push(req);
             }
+        } else if let Some(sign) = state
+            .failed_signal
+            .as_ref()
+            .or_else(|| map.maybe_get_stop_sign(i))
+        {
+            for (req, _) in all {
+                // Banned is impossible
+                if sign.get_priority(req.turn, map) == TurnPriority::Protected {
+                    protected.push(req);
+                } else {
+                    yielding.push(req);
+                }
+            }
         } else if let Some(ref signal) = map.maybe_get_traffic_signal(i) {
-            let (_, phase, _) = signal.current_phase_and_remaining_time(now);
+            let phase = &signal.phases[state.signal.as_ref().unwrap().current_phase];
             for (req, _) in all {
                 match phase.get_priority_of_turn(req.turn, signal) {
                     TurnPriority::Protected => {
@@ -138,17 +317,17 @@ impl IntersectionSimState {
                     TurnPriority::Banned => {}
                 }
             }
-        } else if let Some(ref sign) = map.maybe_get_stop_sign(i) {
+        } else if map.get_i(i).is_roundabout() {
             for (req, _) in all {
-                // Banned is impossible
-                if sign.get_priority(req.turn, map) == TurnPriority::Protected {
+                if map.get_roundabout_priority(req.turn) == TurnPriority::Protected {
                     protected.push(req);
                 } else {
                     yielding.push(req);
                 }
             }
         } else {
-            assert!(map.get_i(i).is_border());
+            // Nothing waits at borders or closed intersections.
+            assert!(map.get_i(i).is_border() || map.get_i(i).is_closed());
         };
 
         for req in protected {
@@ -166,19 +345,265 @@ impl IntersectionSimState {
         }
     }
 
+    // Map edits were just applied without resetting the simulation. Forget requests for turns
+    // that don't exist anymore, and restart the state of signals that were added or edited since
+    // the simulation last ran.
+    pub fn handle_live_edits(
+        &mut self,
+        now: Time,
+        changed: &BTreeSet<IntersectionID>,
+        map: &Map,
+        scheduler: &mut Scheduler,
+    ) {
+        for state in self.state.values_mut() {
+            state.accepted = state
+                .accepted
+                .iter()
+                .filter(|req| map.maybe_get_t(req.turn).is_some())
+                .cloned()
+                .collect();
+            state.waiting = std::mem::replace(&mut state.waiting, BTreeMap::new())
+                .into_iter()
+                .filter(|(req, _)| map.maybe_get_t(req.turn).is_some())
+                .collect();
+            state.approaching_buses =
+                std::mem::replace(&mut state.approaching_buses, BTreeMap::new())
+                    .into_iter()
+                    .filter(|(_, (turn, _))| map.maybe_get_t(*turn).is_some())
+                    .collect();
+            state.approaching_emergency_vehicles =
+                std::mem::replace(&mut state.approaching_emergency_vehicles, BTreeMap::new())
+                    .into_iter()
+                    .filter(|(_, (turn, _))| map.maybe_get_t(*turn).is_some())
+                    .collect();
+
+            if map.maybe_get_traffic_signal(state.id).is_none()
+                || self.use_freeform_policy_everywhere
+            {
+                state.failed_signal = None;
+                if state.signal.take().is_some() {
+                    scheduler.cancel(Command::UpdateIntersection(state.id));
+                }
+                continue;
+            }
+            let num_phases = map.get_traffic_signal(state.id).phases.len();
+            if changed.contains(&state.id)
+                || state
+                    .signal
+                    .as_ref()
+                    .map(|ss| ss.current_phase >= num_phases)
+                    .unwrap_or(true)
+            {
+                // The phases might be totally different, so just jump to wherever the new timing
+                // says we should be.
+                let (idx, _, remaining) = map
+                    .get_traffic_signal(state.id)
+                    .current_phase_and_remaining_time(now);
+                let next_check = now + remaining;
+                state.signal = Some(SignalState {
+                    current_phase: idx,
+                    phase_started: now,
+                    next_check,
+                    last_demand: None,
+                    cut_short: false,
+                });
+                scheduler.update(next_check, Command::UpdateIntersection(state.id));
+            }
+        }
+
+        // The rules might've changed for anybody waiting.
+        for i in changed {
+            self.wakeup_waiting(now, *i, scheduler, map);
+        }
+    }
+
+    pub fn set_lane_blocked(
+        &mut self,
+        now: Time,
+        l: LaneID,
+        blocked: bool,
+        map: &Map,
+        scheduler: &mut Scheduler,
+    ) {
+        if blocked {
+            self.blocked_lanes.insert(l);
+        } else {
+            self.blocked_lanes.remove(&l);
+            self.wakeup_waiting(now, map.get_l(l).src_i, scheduler, map);
+        }
+    }
+
+    pub fn set_intersection_closed(
+        &mut self,
+        now: Time,
+        i: IntersectionID,
+        closed: bool,
+        map: &Map,
+        scheduler: &mut Scheduler,
+    ) {
+        self.state.get_mut(&i).unwrap().closed = closed;
+        if !closed {
+            self.wakeup_waiting(now, i, scheduler, map);
+        }
+    }
+
+    // The signal keeps cycling through its phases in the background, so it picks up wherever it
+    // should be once it's fixed.
+    pub fn set_signal_failed(
+        &mut self,
+        now: Time,
+        i: IntersectionID,
+        failed: bool,
+        map: &Map,
+        scheduler: &mut Scheduler,
+    ) {
+        if map.maybe_get_traffic_signal(i).is_none() {
+            println!("WARNING: {} isn't a traffic signal, so it can't fail", i);
+            return;
+        }
+        self.state.get_mut(&i).unwrap().failed_signal = if failed {
+            let mut sign = ControlStopSign::new(map, i);
+            for cfg in sign.roads.values_mut() {
+                cfg.must_stop = true;
+            }
+            Some(sign)
+        } else {
+            None
+        };
+        // Either way, the rules just changed for anybody waiting.
+        self.wakeup_waiting(now, i, scheduler, map);
+    }
+
     // This is only triggered for traffic signals.
     pub fn update_intersection(
-        &self,
+        &mut self,
         now: Time,
         id: IntersectionID,
         map: &Map,
         scheduler: &mut Scheduler,
     ) {
+        let signal = map.get_traffic_signal(id);
+        let state = self.state.get_mut(&id).unwrap();
+        let current_phase = state.signal.as_ref().unwrap().current_phase;
+
+        // Preempt the normal cycle for an emergency vehicle: hold a phase that serves it, or jump
+        // straight to one.
+        state.approaching_emergency_vehicles =
+            std::mem::replace(&mut state.approaching_emergency_vehicles, BTreeMap::new())
+                .into_iter()
+                .filter(|(_, (_, eta))| *eta + EMERGENCY_VEHICLE_GRACE > now)
+                .collect();
+        let mut preempt_phase = None;
+        if let Some((turn, eta)) = state.approaching_emergency_vehicles.values().next() {
+            let ss = state.signal.as_mut().unwrap();
+            if signal.phases[current_phase].get_priority_of_turn(*turn, signal)
+                != TurnPriority::Banned
+            {
+                // Keep checking until they're through.
+                ss.next_check = (*eta).max(now + Duration::seconds(1.0));
+                scheduler.push(ss.next_check, Command::UpdateIntersection(id));
+                return;
+            }
+            preempt_phase = signal
+                .phases
+                .iter()
+                .position(|p| p.get_priority_of_turn(*turn, signal) != TurnPriority::Banned);
+        }
+
+        // Should an actuated phase keep going?
+        if preempt_phase.is_none() {
+            let ss = state.signal.as_mut().unwrap();
+            if let Some(ref actuated) = signal.phases[current_phase].actuated {
+                let max_end = ss.phase_started + actuated.max_green;
+                if let Some(t) = ss.last_demand {
+                    let extend_until = (t + actuated.extension).min(max_end);
+                    if extend_until > now {
+                        ss.next_check = extend_until;
+                        scheduler.push(extend_until, Command::UpdateIntersection(id));
+                        return;
+                    }
+                }
+            }
+
+            // Hold the green for a bus that's almost here.
+            if let Some(ref tsp) = signal.transit_priority {
+                let phase = &signal.phases[current_phase];
+                let max_end = ss.phase_started + phase.max_duration() + tsp.max_green_extension;
+                if let Some(eta) = state
+                    .approaching_buses
+                    .values()
+                    .filter(|(turn, _)| {
+                        phase.get_priority_of_turn(*turn, signal) != TurnPriority::Banned
+                    })
+                    .map(|(_, eta)| *eta)
+                    .max()
+                {
+                    let extend_until = eta.min(max_end);
+                    if !ss.cut_short && extend_until > now {
+                        ss.next_check = extend_until;
+                        scheduler.push(extend_until, Command::UpdateIntersection(id));
+                        return;
+                    }
+                }
+            }
+        }
+
+        // Move to the next phase, skipping actuated phases that nobody's waiting for. If every
+        // other phase is skipped, just restart the current one.
+        let mut next_phase = preempt_phase.unwrap_or((current_phase + 1) % signal.phases.len());
+        for _ in 1..signal.phases.len() {
+            let phase = &signal.phases[next_phase];
+            let skippable = preempt_phase.is_none()
+                && phase
+                    .actuated
+                    .as_ref()
+                    .map(|a| a.skip_if_no_demand)
+                    .unwrap_or(false);
+            if skippable
+                && !state
+                    .waiting
+                    .keys()
+                    .any(|req| phase.get_priority_of_turn(req.turn, signal) != TurnPriority::Banned)
+            {
+                next_phase = (next_phase + 1) % signal.phases.len();
+            } else {
+                break;
+            }
+        }
+
+        let phase = &signal.phases[next_phase];
+        let ss = state.signal.as_mut().unwrap();
+        ss.current_phase = next_phase;
+        ss.phase_started = now;
+        ss.last_demand = None;
+        ss.cut_short = false;
+        ss.next_check = now
+            + match phase.actuated {
+                Some(ref actuated) => actuated.min_green,
+                None => phase.duration,
+            };
+        scheduler.push(ss.next_check, Command::UpdateIntersection(id));
+
         self.wakeup_waiting(now, id, scheduler, map);
-        let (_, _, remaining) = map
-            .get_traffic_signal(id)
-            .current_phase_and_remaining_time(now);
-        scheduler.push(now + remaining, Command::UpdateIntersection(id));
+    }
+
+    // Only for traffic signals. Returns the index of the current phase and how long until the
+    // signal next reconsiders it. Actuated phases might be extended past that.
+    pub fn current_phase_and_remaining_time(
+        &self,
+        now: Time,
+        id: IntersectionID,
+        map: &Map,
+    ) -> (usize, Duration) {
+        if let Some(ref ss) = self.state[&id].signal {
+            (ss.current_phase, ss.next_check - now)
+        } else {
+            // With the freeform policy, nothing is tracked; just show the fixed-time schedule.
+            let (idx, _, remaining) = map
+                .get_traffic_signal(id)
+                .current_phase_and_remaining_time(now);
+            (idx, remaining)
+        }
     }
 
     // For cars: The head car calls this when they're at the end of the lane WaitingToAdvance. If
@@ -201,14 +626,65 @@ impl IntersectionSimState {
         //let debug = turn.parent == IntersectionID(64);
         let req = Request { agent, turn };
         let state = self.state.get_mut(&turn.parent).unwrap();
+        let new_arrival = !state.waiting.contains_key(&req);
         state.waiting.entry(req.clone()).or_insert(now);
 
+        // Whoever ends the incident will wake us up.
+        if state.closed || self.blocked_lanes.contains(&turn.dst) {
+            return false;
+        }
+
+        // Hold for an emergency vehicle about to do a conflicting turn. Normally it wakes us up
+        // once it's through, but in case it never shows up, retry when we'd give up waiting.
+        // Emergency vehicles don't hold for each other, so they can't deadlock. Anybody ahead of
+        // the emergency vehicle in its lane is in the way, so they clear the intersection.
+        let is_emergency = match agent {
+            AgentID::Car(c) => state.approaching_emergency_vehicles.contains_key(&c),
+            _ => false,
+        };
+        if let Some(until) = state
+            .approaching_emergency_vehicles
+            .values()
+            .filter(|(t, eta)| {
+                !is_emergency
+                    && t.src != turn.src
+                    && *eta + EMERGENCY_VEHICLE_GRACE > now
+                    && map.get_t(*t).conflicts_with(map.get_t(turn))
+            })
+            .map(|(_, eta)| *eta + EMERGENCY_VEHICLE_GRACE)
+            .max()
+        {
+            scheduler.update(until, Command::update_agent(agent));
+            return false;
+        }
+
+        // Keep actuated phases going while new agents arrive for them, unless transit priority is
+        // ending the phase early. Agents already waiting asking again don't count, like a
+        // detector at the stop line that only fires once per vehicle.
+        if let Some(ref mut ss) = state.signal {
+            let signal = map.get_traffic_signal(state.id);
+            if new_arrival
+                && !ss.cut_short
+                && map.get_t(turn).turn_type != TurnType::SharedSidewalkCorner
+                && signal.phases[ss.current_phase].get_priority_of_turn(turn, signal)
+                    != TurnPriority::Banned
+            {
+                ss.last_demand = Some(now);
+            }
+        }
+
         let allowed = if self.use_freeform_policy_everywhere {
             state.freeform_policy(&req, map)
+        } else if let Some(sign) = state
+            .failed_signal
+            .as_ref()
+            .or_else(|| map.maybe_get_stop_sign(state.id))
+        {
+            state.stop_sign_policy(sign, &req, now, map, scheduler)
         } else if let Some(ref signal) = map.maybe_get_traffic_signal(state.id) {
             state.traffic_signal_policy(signal, &req, speed, now, map, scheduler)
-        } else if let Some(ref sign) = map.maybe_get_stop_sign(state.id) {
-            state.stop_sign_policy(sign, &req, now, map, scheduler)
+        } else if map.get_i(state.id).is_roundabout() {
+            state.roundabout_policy(&req, map)
         } else {
             unreachable!()
         };
@@ -238,6 +714,10 @@ impl IntersectionSimState {
             self.events
                 .push(Event::IntersectionDelayMeasured(turn.parent, delay));
         }
+        if let AgentID::Car(c) = req.agent {
+            state.approaching_buses.remove(&c);
+            state.approaching_emergency_vehicles.remove(&c);
+        }
         state.accepted.insert(req);
         /*if debug {
             println!("{}: {} going!", now, agent)
@@ -251,6 +731,8 @@ impl IntersectionSimState {
             println!("{}", abstutil::to_json(sign));
         } else if let Some(ref signal) = map.maybe_get_traffic_signal(id) {
             println!("{}", abstutil::to_json(signal));
+        } else if map.get_i(id).is_roundabout() {
+            println!("Roundabout");
         } else {
             println!("Border");
         }
@@ -264,6 +746,14 @@ impl IntersectionSimState {
             .collect()
     }
 
+    pub fn get_accepted_turns(&self, id: IntersectionID) -> Vec<(AgentID, TurnID)> {
+        self.state[&id]
+            .accepted
+            .iter()
+            .map(|req| (req.agent, req.turn))
+            .collect()
+    }
+
     pub fn collect_events(&mut self) -> Vec<Event> {
         std::mem::replace(&mut self.events, Vec::new())
     }
@@ -331,6 +821,25 @@ impl State {
         true
     }
 
+    fn roundabout_policy(&self, req: &Request, map: &Map) -> bool {
+        if self.any_accepted_conflict_with(req.turn, map) {
+            return false;
+        }
+        if map.get_roundabout_priority(req.turn) == TurnPriority::Protected {
+            return true;
+        }
+
+        // Entering the ring doesn't require stopping, but give way to anybody circulating who's
+        // already waiting to make a conflicting turn. They'll wake us up when they're through.
+        // DrivingSimState checks for circulating vehicles that haven't arrived yet.
+        let turn = map.get_t(req.turn);
+        !self.waiting.keys().any(|other| {
+            other != req
+                && map.get_roundabout_priority(other.turn) == TurnPriority::Protected
+                && map.get_t(other.turn).conflicts_with(turn)
+        })
+    }
+
     fn traffic_signal_policy(
         &self,
         signal: &ControlTrafficSignal,
@@ -347,7 +856,13 @@ impl State {
             return true;
         }
 
-        let (_, phase, remaining_phase_time) = signal.current_phase_and_remaining_time(now);
+        let ss = self.signal.as_ref().unwrap();
+        let phase = &signal.phases[ss.current_phase];
+        // Optimistically assume an actuated phase will keep going as long as possible.
+        let remaining_phase_time = match phase.actuated {
+            Some(ref actuated) => ss.phase_started + actuated.max_green - now,
+            None => ss.next_check - now,
+        };
 
         // Can't go at all this phase.
         let our_priority = phase.get_priority_of_turn(req.turn, signal);
@@ -381,14 +896,20 @@ impl State {
         // TODO Make sure we can optimistically finish this turn before an approaching
         // higher-priority vehicle wants to begin.
 
+        // The signal holds the phase for emergency vehicles until they're through.
+        let emergency = match req.agent {
+            AgentID::Car(c) => self.approaching_emergency_vehicles.contains_key(&c),
+            _ => false,
+        };
+
         // Optimistically if nobody else is in the way, this is how long it'll take to finish the
         // turn. Don't start the turn if we won't finish by the time the light changes. If we get
         // it wrong, that's fine -- block the box a bit.
         let time_to_cross = turn.geom.length() / speed;
-        if time_to_cross > remaining_phase_time {
+        if !emergency && time_to_cross > remaining_phase_time {
             // Actually, we might have bigger problems...
-            if time_to_cross > phase.duration {
-                println!("OYYY! {:?} is impossible to fit into phase duration of {}. Allowing, but fix the policy!", req, phase.duration);
+            if time_to_cross > phase.max_duration() {
+                println!("OYYY! {:?} is impossible to fit into phase duration of {}. Allowing, but fix the policy!", req, phase.max_duration());
             } else {
                 return false;
             }
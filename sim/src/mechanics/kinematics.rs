@@ -0,0 +1,200 @@
+use crate::{DistanceInterval, DrivingModes, TimeInterval};
+use geom::{Distance, Duration, Speed, Time};
+use serde_derive::{Deserialize, Serialize};
+
+// A zero speed limit, max_speed, or acceleration would mean never finishing. Crawl instead.
+const MIN_SPEED: f64 = 0.1;
+const MIN_ACCEL: f64 = 0.1;
+
+// Vehicles don't instantly jump to their cruising speed or stop on a dime. While crossing
+// something, a vehicle accelerates (at most at max_accel) from its current speed up to a cruising
+// speed, holds that, and then brakes (at max_decel) to whatever speed it needs at the end. This is
+// a simplified IDM -- the minimum gap to the leader is still enforced by Queue, not by smoothly
+// modulating acceleration.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct SpeedProfile {
+    pub start_speed: Speed,
+    pub peak_speed: Speed,
+    pub end_speed: Speed,
+    // In meters per second^2. Both are non-negative.
+    accel: f64,
+    decel: f64,
+    accel_time: Duration,
+    cruise_time: Duration,
+    brake_time: Duration,
+}
+
+impl SpeedProfile {
+    pub fn new(
+        length: Distance,
+        start_speed: Speed,
+        cruise_speed: Speed,
+        end_speed: Speed,
+        max_accel: f64,
+        max_decel: f64,
+    ) -> SpeedProfile {
+        let len = length.inner_meters();
+        let vc = cruise_speed.inner_meters_per_second().max(MIN_SPEED);
+        let max_accel = max_accel.max(MIN_ACCEL);
+        let max_decel = max_decel.max(MIN_ACCEL);
+        // Entering something slower than we're going? Just clamp.
+        let v0 = start_speed.inner_meters_per_second().min(vc);
+
+        if len <= 0.0 {
+            return SpeedProfile {
+                start_speed: Speed::meters_per_second(v0),
+                peak_speed: Speed::meters_per_second(v0),
+                end_speed: Speed::meters_per_second(v0),
+                accel: max_accel,
+                decel: max_decel,
+                accel_time: Duration::ZERO,
+                cruise_time: Duration::ZERO,
+                brake_time: Duration::ZERO,
+            };
+        }
+
+        // Short things might not give us enough room to reach the desired end speed.
+        let v1 = end_speed
+            .inner_meters_per_second()
+            .min(vc)
+            .min((v0 * v0 + 2.0 * max_accel * len).sqrt());
+
+        // Not enough room to brake comfortably. Brake harder, at a constant rate, the whole way.
+        if v0 * v0 - 2.0 * max_decel * len > v1 * v1 {
+            let decel = (v0 * v0 - v1 * v1) / (2.0 * len);
+            return SpeedProfile {
+                start_speed: Speed::meters_per_second(v0),
+                peak_speed: Speed::meters_per_second(v0),
+                end_speed: Speed::meters_per_second(v1),
+                accel: max_accel,
+                decel,
+                accel_time: Duration::ZERO,
+                cruise_time: Duration::ZERO,
+                brake_time: Duration::seconds((v0 - v1) / decel),
+            };
+        }
+
+        let accel_dist = (vc * vc - v0 * v0) / (2.0 * max_accel);
+        let brake_dist = (vc * vc - v1 * v1) / (2.0 * max_decel);
+        let (peak, cruise_dist) = if accel_dist + brake_dist <= len {
+            (vc, len - accel_dist - brake_dist)
+        } else {
+            // Never reach the cruising speed; start braking partway through accelerating.
+            let peak =
+                ((2.0 * len * max_accel * max_decel + v0 * v0 * max_decel + v1 * v1 * max_accel)
+                    / (max_accel + max_decel))
+                    .sqrt();
+            (peak, 0.0)
+        };
+
+        SpeedProfile {
+            start_speed: Speed::meters_per_second(v0),
+            peak_speed: Speed::meters_per_second(peak),
+            end_speed: Speed::meters_per_second(v1),
+            accel: max_accel,
+            decel: max_decel,
+            accel_time: Duration::seconds(((peak - v0) / max_accel).max(0.0)),
+            cruise_time: if cruise_dist > 0.0 {
+                Duration::seconds(cruise_dist / peak)
+            } else {
+                Duration::ZERO
+            },
+            brake_time: Duration::seconds(((peak - v1) / max_decel).max(0.0)),
+        }
+    }
+
+    pub fn total_time(&self) -> Duration {
+        self.accel_time + self.cruise_time + self.brake_time
+    }
+
+    // How far along after some time has passed since the start?
+    pub fn dist_at(&self, t: Duration) -> Distance {
+        let t = t.inner_seconds().max(0.0);
+        let v0 = self.start_speed.inner_meters_per_second();
+        let vp = self.peak_speed.inner_meters_per_second();
+        let t1 = self.accel_time.inner_seconds();
+        let t2 = self.cruise_time.inner_seconds();
+        let t3 = self.brake_time.inner_seconds();
+
+        if t <= t1 {
+            return Distance::meters(v0 * t + 0.5 * self.accel * t * t);
+        }
+        let accel_dist = v0 * t1 + 0.5 * self.accel * t1 * t1;
+        if t <= t1 + t2 {
+            return Distance::meters(accel_dist + vp * (t - t1));
+        }
+        let cruise_dist = vp * t2;
+        let tau = (t - t1 - t2).min(t3);
+        Distance::meters(accel_dist + cruise_dist + vp * tau - 0.5 * self.decel * tau * tau)
+    }
+
+    // How long after the start until the vehicle is some distance along? dist_at never decreases,
+    // so just bisect.
+    pub fn time_to_reach(&self, dist: Distance) -> Duration {
+        let mut low = Duration::ZERO;
+        let mut high = self.total_time();
+        if self.dist_at(high) <= dist {
+            return high;
+        }
+        for _ in 0..50 {
+            let mid = (low + high) / 2.0;
+            if self.dist_at(mid) < dist {
+                low = mid;
+            } else {
+                high = mid;
+            }
+        }
+        high
+    }
+
+    pub fn speed_at(&self, t: Duration) -> Speed {
+        let t = t.inner_seconds().max(0.0);
+        let v0 = self.start_speed.inner_meters_per_second();
+        let vp = self.peak_speed.inner_meters_per_second();
+        let t1 = self.accel_time.inner_seconds();
+        let t2 = self.cruise_time.inner_seconds();
+        let t3 = self.brake_time.inner_seconds();
+
+        if t <= t1 {
+            Speed::meters_per_second(v0 + self.accel * t)
+        } else if t <= t1 + t2 {
+            self.peak_speed
+        } else if t < t1 + t2 + t3 {
+            Speed::meters_per_second((vp - self.decel * (t - t1 - t2)).max(0.0))
+        } else {
+            self.end_speed
+        }
+    }
+
+    // How the vehicle drove from the start until some time has passed, for estimating emissions
+    pub fn driving_modes(&self, t: Duration) -> DrivingModes {
+        let t = t.max(Duration::ZERO);
+        let t1 = self.accel_time.min(t);
+        let t2 = (self.accel_time + self.cruise_time).min(t);
+        let t3 = self.total_time().min(t);
+        let d1 = self.dist_at(t1);
+        let d2 = self.dist_at(t2);
+        let d3 = self.dist_at(t3);
+        DrivingModes {
+            idle: Duration::ZERO,
+            accelerating: (t1, d1),
+            cruising: (t2 - t1, d2 - d1),
+            braking: (t3 - t2, d3 - d2),
+        }
+    }
+
+    // Where is the vehicle at some point during the crossing? Never overshoots the interval.
+    pub fn dist_along(
+        &self,
+        time_int: &TimeInterval,
+        dist_int: &DistanceInterval,
+        now: Time,
+    ) -> Distance {
+        // We process car updates in any order, so we might calculate this before moving this car
+        // from Crossing to another state.
+        if now >= time_int.end {
+            return dist_int.end;
+        }
+        (dist_int.start + self.dist_at(now - time_int.start)).min(dist_int.end)
+    }
+}
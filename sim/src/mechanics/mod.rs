@@ -1,12 +1,14 @@
 mod car;
 mod driving;
 mod intersection;
+mod kinematics;
 mod parking;
 mod queue;
 mod walking;
 
-pub use self::driving::DrivingSimState;
+pub use self::driving::{find_cycles, DrivingSimState};
 pub use self::intersection::IntersectionSimState;
+pub use self::kinematics::SpeedProfile;
 pub use self::parking::ParkingSimState;
 pub use self::queue::Queue;
-pub use self::walking::WalkingSimState;
+pub use self::walking::{crowded_speed, WalkingSimState};
@@ -1,4 +1,7 @@
-use crate::{AgentMetadata, CarID, CarStatus, DrawCarInput, ParkedCar, ParkingSpot, Vehicle};
+use crate::router::path_to_free_parking_spot;
+use crate::{
+    AgentMetadata, CarID, CarStatus, DrawCarInput, ParkedCar, ParkingSpot, Vehicle, VehicleType,
+};
 use abstutil::{
     deserialize_btreemap, deserialize_multimap, serialize_btreemap, serialize_multimap, MultiMap,
     Timer,
@@ -206,6 +209,12 @@ impl ParkingSimState {
     }
 
     pub fn is_free(&self, spot: ParkingSpot) -> bool {
+        if let ParkingSpot::Onstreet(l, _) = spot {
+            // Live map edits might've removed the lane.
+            if !self.onstreet_lanes.contains_key(&l) {
+                return false;
+            }
+        }
         !self.occupants.contains_key(&spot) && !self.reserved_spots.contains(&spot)
     }
 
@@ -237,6 +246,11 @@ impl ParkingSimState {
         }
 
         for b in self.driving_to_offstreet.get(driving_pos.lane()) {
+            // Trucks don't fit in garages; they just use onstreet spots as loading zones.
+            // TODO Model dedicated loading zones, and trucks taking up more than one spot.
+            if vehicle.vehicle_type == VehicleType::Truck {
+                break;
+            }
             let bldg_dist = map
                 .get_b(*b)
                 .parking
@@ -323,6 +337,10 @@ impl ParkingSimState {
         results
     }
 
+    pub fn lookup_parked_car(&self, id: CarID) -> Option<&ParkedCar> {
+        self.parked_cars.get(&id)
+    }
+
     pub fn get_owner_of_car(&self, id: CarID) -> Option<BuildingID> {
         self.parked_cars.get(&id).and_then(|p| p.vehicle.owner)
     }
@@ -352,6 +370,92 @@ impl ParkingSimState {
 
         (filled, available)
     }
+
+    // Map edits were just applied without resetting the simulation. Parking lanes might've been
+    // added or removed. Cars parked on a lane that's gone get moved to the nearest free spot.
+    // Returns (old spot, new spot) for every car that moved.
+    // TODO If a car is in the middle of parking on a lane that's gone, or there's nowhere to move
+    // a car to, the lane sticks around until the next round of edits.
+    pub fn handle_live_edits(
+        &mut self,
+        map: &Map,
+        timer: &mut Timer,
+    ) -> Vec<(ParkingSpot, ParkingSpot)> {
+        let mut new_lanes: BTreeMap<LaneID, ParkingLane> = BTreeMap::new();
+        for l in map.all_lanes() {
+            if let Some(lane) = ParkingLane::new(l, map, timer) {
+                new_lanes.insert(lane.parking_lane, lane);
+            }
+        }
+
+        let mut moved = Vec::new();
+        let old_lanes = std::mem::replace(&mut self.onstreet_lanes, new_lanes);
+        self.driving_to_parking_lanes = MultiMap::new();
+        for lane in self.onstreet_lanes.values() {
+            self.driving_to_parking_lanes
+                .insert(lane.driving_lane, lane.parking_lane);
+        }
+        for (l, old_lane) in old_lanes {
+            if self.onstreet_lanes.contains_key(&l) {
+                continue;
+            }
+            if self.reserved_spots.iter().any(|spot| match spot {
+                ParkingSpot::Onstreet(l2, _) => *l2 == l,
+                ParkingSpot::Offstreet(_, _) => false,
+            }) {
+                self.driving_to_parking_lanes
+                    .insert(old_lane.driving_lane, l);
+                self.onstreet_lanes.insert(l, old_lane);
+                continue;
+            }
+
+            let mut stuck = false;
+            for spot in old_lane.spots() {
+                let p = match self.get_car_at_spot(spot) {
+                    Some(p) => p.clone(),
+                    None => {
+                        continue;
+                    }
+                };
+                let new_spot = self
+                    .get_first_free_spot(
+                        Position::new(old_lane.driving_lane, Distance::ZERO),
+                        &p.vehicle,
+                        map,
+                    )
+                    .or_else(|| {
+                        path_to_free_parking_spot(old_lane.driving_lane, &p.vehicle, map, self)
+                            .map(|(_, spot, pos)| (spot, pos))
+                    });
+                if let Some((new_spot, _)) = new_spot {
+                    let reserved = self.dynamically_reserved_cars.contains(&p.vehicle.id);
+                    self.remove_parked_car(p.clone());
+                    self.reserve_spot(new_spot);
+                    self.add_parked_car(ParkedCar {
+                        vehicle: p.vehicle.clone(),
+                        spot: new_spot,
+                    });
+                    if reserved {
+                        self.dynamically_reserved_cars.insert(p.vehicle.id);
+                    }
+                    moved.push((spot, new_spot));
+                } else {
+                    timer.warn(format!(
+                        "Nowhere to move {} after parking lane {} was removed",
+                        p.vehicle.id, l
+                    ));
+                    stuck = true;
+                }
+            }
+            if stuck {
+                self.driving_to_parking_lanes
+                    .insert(old_lane.driving_lane, l);
+                self.onstreet_lanes.insert(l, old_lane);
+            }
+        }
+
+        moved
+    }
 }
 
 #[derive(Serialize, Deserialize, PartialEq)]
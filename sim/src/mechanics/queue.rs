@@ -107,10 +107,8 @@ impl Queue {
                     assert_eq!(bound, self.geom_len);
                     self.geom_len
                 }
-                CarState::Crossing(ref time_int, ref dist_int) => {
-                    // TODO Why percent_clamp_end? We process car updates in any order, so we might
-                    // calculate this before moving this car from Crossing to another state.
-                    dist_int.lerp(time_int.percent_clamp_end(now)).min(bound)
+                CarState::Crossing(ref time_int, ref dist_int, ref profile) => {
+                    profile.dist_along(time_int, dist_int, now).min(bound)
                 }
                 CarState::Unparking(front, _, _) => front,
                 CarState::Parking(front, _, _) => front,
@@ -220,10 +218,16 @@ fn dump_cars(
         let car = &cars[id];
         println!("- {} @ {} (length {})", id, dist, car.vehicle.length);
         match car.state {
-            CarState::Crossing(ref time_int, ref dist_int) => {
+            CarState::Crossing(ref time_int, ref dist_int, ref profile) => {
                 println!(
-                    "  Going {} .. {} during {} .. {}",
-                    dist_int.start, dist_int.end, time_int.start, time_int.end
+                    "  Going {} .. {} during {} .. {}, {} -> {} -> {}",
+                    dist_int.start,
+                    dist_int.end,
+                    time_int.start,
+                    time_int.end,
+                    profile.start_speed,
+                    profile.peak_speed,
+                    profile.end_speed
                 );
             }
             CarState::Queued => {
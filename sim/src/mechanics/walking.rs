@@ -6,13 +6,24 @@ use crate::{
 };
 use abstutil::{deserialize_multimap, serialize_multimap, MultiMap};
 use geom::{Distance, Duration, Line, PolyLine, Speed, Time};
-use map_model::{BuildingID, BusRouteID, Map, Path, PathStep, Traversable, LANE_THICKNESS};
+use map_model::{
+    BuildingID, BusRouteID, Map, Path, PathConstraints, PathRequest, PathStep, Traversable,
+    LANE_THICKNESS,
+};
 use serde_derive::{Deserialize, Serialize};
 use std::collections::BTreeMap;
 
 const TIME_TO_START_BIKING: Duration = Duration::const_seconds(30.0);
 const TIME_TO_FINISH_BIKING: Duration = Duration::const_seconds(45.0);
 
+// Pedestrians per square meter. Below this, everybody walks at their own speed (roughly level of
+// service C).
+const FREE_FLOW_DENSITY: f64 = 0.3;
+// Nobody can move at all this packed together.
+const JAM_DENSITY: f64 = 5.4;
+// Even in a crush, people shuffle along slowly.
+const MIN_SPEED_FACTOR: f64 = 0.1;
+
 #[derive(Serialize, Deserialize, PartialEq)]
 pub struct WalkingSimState {
     // BTreeMap not for deterministic simulation, but to make serialized things easier to compare.
@@ -77,15 +88,20 @@ impl WalkingSimState {
                 Line::new(driving_pos.pt(map), params.start.sidewalk_pos.pt(map)),
                 TimeInterval::new(now, now + TIME_TO_FINISH_BIKING),
             ),
-            _ => ped.crossing_state(params.start.sidewalk_pos.dist_along(), now, map),
+            _ => ped.crossing_state(
+                params.start.sidewalk_pos.dist_along(),
+                now,
+                map,
+                &self.peds_per_traversable,
+            ),
         };
 
         scheduler.push(ped.state.get_end_time(), Command::UpdatePed(ped.id));
         self.peds.insert(ped.id, ped);
-        self.peds_per_traversable.insert(
-            Traversable::Lane(params.start.sidewalk_pos.lane()),
-            params.id,
-        );
+        let on = Traversable::Lane(params.start.sidewalk_pos.lane());
+        let before = self.peds_per_traversable.get(on).len();
+        self.peds_per_traversable.insert(on, params.id);
+        self.crowd_changed(on, before, now, map, scheduler);
     }
 
     pub fn get_draw_ped(
@@ -116,6 +132,8 @@ impl WalkingSimState {
         transit: &mut TransitSimState,
     ) {
         let mut ped = self.peds.get_mut(&id).unwrap();
+        let was_on = ped.path.current_step().as_traversable();
+        let was_on_before = self.peds_per_traversable.get(was_on).len();
         match ped.state {
             PedState::Crossing(ref dist_int, _) => {
                 if ped.path.is_last_step() {
@@ -211,8 +229,12 @@ impl WalkingSimState {
                 }
             }
             PedState::LeavingBuilding(b, _) => {
-                ped.state =
-                    ped.crossing_state(map.get_b(b).front_path.sidewalk.dist_along(), now, map);
+                ped.state = ped.crossing_state(
+                    map.get_b(b).front_path.sidewalk.dist_along(),
+                    now,
+                    map,
+                    &self.peds_per_traversable,
+                );
                 scheduler.push(ped.state.get_end_time(), Command::UpdatePed(ped.id));
             }
             PedState::EnteringBuilding(bldg, _) => {
@@ -228,11 +250,168 @@ impl WalkingSimState {
                 self.peds.remove(&id);
             }
             PedState::FinishingBiking(ref spot, _, _) => {
-                ped.state = ped.crossing_state(spot.sidewalk_pos.dist_along(), now, map);
+                ped.state = ped.crossing_state(
+                    spot.sidewalk_pos.dist_along(),
+                    now,
+                    map,
+                    &self.peds_per_traversable,
+                );
                 scheduler.push(ped.state.get_end_time(), Command::UpdatePed(ped.id));
             }
             PedState::WaitingForBus(_) => unreachable!(),
         }
+
+        // Anybody arriving or leaving changes how fast everybody else there can walk.
+        let now_on = self
+            .peds
+            .get(&id)
+            .map(|p| p.path.current_step().as_traversable());
+        if now_on != Some(was_on) {
+            self.crowd_changed(was_on, was_on_before, now, map, scheduler);
+            if let Some(on) = now_on {
+                // We just arrived
+                let before = self.peds_per_traversable.get(on).len() - 1;
+                self.crowd_changed(on, before, now, map, scheduler);
+            }
+        }
+    }
+
+    // Everybody walking along something speeds up or slows down from where they are now, if the
+    // crowd there used to be a different size. Most of the time, there's nobody else around, or
+    // the crowd stays sparse enough that nobody's speed changes.
+    fn crowd_changed(
+        &mut self,
+        on: Traversable,
+        before: usize,
+        now: Time,
+        map: &Map,
+        scheduler: &mut Scheduler,
+    ) {
+        let peds = self.peds_per_traversable.get(on);
+        let length = on.length(map);
+        for id in peds {
+            let ped = self.peds.get_mut(id).unwrap();
+            if let PedState::Crossing(_, _) = ped.state {
+                if crowded_speed(ped.speed, before, length)
+                    == crowded_speed(ped.speed, peds.len(), length)
+                {
+                    continue;
+                }
+                ped.state = ped.crossing_state(
+                    ped.get_dist_along(now, map),
+                    now,
+                    map,
+                    &self.peds_per_traversable,
+                );
+                scheduler.update(ped.state.get_end_time(), Command::UpdatePed(ped.id));
+            }
+        }
+    }
+
+    // Map edits were just applied without resetting the simulation. Pedestrians walking to a
+    // parked car that moved head to the new spot instead, and anybody whose path doesn't work
+    // anymore is rerouted. Returns the pedestrians that had to be deleted, because they were on a
+    // turn that's gone or can't reach their goal anymore.
+    pub fn handle_live_edits(
+        &mut self,
+        now: Time,
+        moved_cars: &Vec<(ParkingSpot, SidewalkSpot)>,
+        map: &Map,
+        intersections: &mut IntersectionSimState,
+        scheduler: &mut Scheduler,
+    ) -> Vec<PedestrianID> {
+        let mut deleted = Vec::new();
+        for ped in self.peds.values_mut() {
+            if let PathStep::Turn(t) = ped.path.current_step() {
+                if map.maybe_get_t(t).is_none() {
+                    deleted.push(ped.id);
+                    continue;
+                }
+            }
+
+            let mut goal_changed = false;
+            if let SidewalkPOI::ParkingSpot(spot) = ped.goal.connection {
+                if let Some((_, new_goal)) = moved_cars.iter().find(|(old, _)| *old == spot) {
+                    ped.goal = new_goal.clone();
+                    goal_changed = true;
+                }
+            }
+            if !goal_changed
+                && ped
+                    .path
+                    .is_valid_after_current_step(PathConstraints::Pedestrian, map)
+            {
+                continue;
+            }
+
+            let old_next = if ped.path.is_last_step() {
+                None
+            } else {
+                Some(ped.path.next_step())
+            };
+            match map.pathfind(PathRequest {
+                start: ped.path.current_step_end(map),
+                end: ped.goal.sidewalk_pos,
+                constraints: PathConstraints::Pedestrian,
+            }) {
+                Some(path) => {
+                    ped.path.replace_after_current_step(path, map);
+                    self.events.push(Event::PathAmended(ped.path.clone()));
+                }
+                None => {
+                    println!(
+                        "{} can't reach {:?} after map edits; deleting them",
+                        ped.id, ped.goal.connection
+                    );
+                    deleted.push(ped.id);
+                    continue;
+                }
+            }
+
+            match ped.state {
+                // They might've been headed for the old goal partway along this step.
+                PedState::Crossing(_, _) => {
+                    ped.state = ped.crossing_state(
+                        ped.get_dist_along(now, map),
+                        now,
+                        map,
+                        &self.peds_per_traversable,
+                    );
+                    scheduler.update(ped.state.get_end_time(), Command::UpdatePed(ped.id));
+                }
+                PedState::WaitingToTurn(_) => {
+                    if let Some(PathStep::Turn(t)) = old_next {
+                        intersections.cancel_request(AgentID::Pedestrian(ped.id), t);
+                    }
+                    scheduler.update(now, Command::UpdatePed(ped.id));
+                }
+                _ => {}
+            }
+        }
+
+        for id in &deleted {
+            let ped = self.peds.remove(id).unwrap();
+            self.peds_per_traversable
+                .remove(ped.path.current_step().as_traversable(), ped.id);
+            scheduler.cancel(Command::UpdatePed(ped.id));
+            if let PathStep::Turn(t) = ped.path.current_step() {
+                if map.maybe_get_t(t).is_some() {
+                    intersections.turn_finished(
+                        now,
+                        AgentID::Pedestrian(ped.id),
+                        t,
+                        scheduler,
+                        map,
+                    );
+                }
+            }
+            if let PedState::WaitingToTurn(_) = ped.state {
+                if let PathStep::Turn(t) = ped.path.next_step() {
+                    intersections.cancel_request(AgentID::Pedestrian(ped.id), t);
+                }
+            }
+        }
+        deleted
     }
 
     pub fn ped_boarded_bus(&mut self, id: PedestrianID) {
@@ -246,6 +425,11 @@ impl WalkingSimState {
         };
     }
 
+    // Nothing's coming to pick them up anymore.
+    pub fn delete_ped_waiting_for_bus(&mut self, id: PedestrianID) {
+        self.ped_boarded_bus(id);
+    }
+
     pub fn debug_ped(&self, id: PedestrianID) {
         if let Some(ped) = self.peds.get(&id) {
             println!("{}", abstutil::to_json(ped));
@@ -270,6 +454,17 @@ impl WalkingSimState {
             ),
             format!("Trip time so far: {}", now - p.started_at),
         ];
+        if let PedState::Crossing(ref dist_int, ref time_int) = p.state {
+            let dt = time_int.end - time_int.start;
+            if dt > Duration::ZERO {
+                let speed =
+                    Speed::meters_per_second(dist_int.length().inner_meters() / dt.inner_seconds());
+                // Ignore rounding
+                if speed < p.speed * 0.99 {
+                    lines.push(format!("Slowed down to {} by the crowd", speed));
+                }
+            }
+        }
         if let PedState::WaitingForBus(r) = p.state {
             lines.push(format!("Waiting for bus {}", map.get_br(r).name));
         }
@@ -435,7 +630,13 @@ struct Pedestrian {
 }
 
 impl Pedestrian {
-    fn crossing_state(&self, start_dist: Distance, start_time: Time, map: &Map) -> PedState {
+    fn crossing_state(
+        &self,
+        start_dist: Distance,
+        start_time: Time,
+        map: &Map,
+        peds_per_traversable: &MultiMap<Traversable, PedestrianID>,
+    ) -> PedState {
         let end_dist = if self.path.is_last_step() {
             self.goal.sidewalk_pos.dist_along()
         } else {
@@ -447,10 +648,32 @@ impl Pedestrian {
             }
         };
         let dist_int = DistanceInterval::new_walking(start_dist, end_dist);
-        let time_int = TimeInterval::new(start_time, start_time + dist_int.length() / self.speed);
+        let speed = self.speed_on(
+            self.path.current_step().as_traversable(),
+            map,
+            peds_per_traversable,
+        );
+        let time_int = TimeInterval::new(start_time, start_time + dist_int.length() / speed);
         PedState::Crossing(dist_int, time_int)
     }
 
+    // Walking speed on something, slowed down by everybody else there
+    fn speed_on(
+        &self,
+        on: Traversable,
+        map: &Map,
+        peds_per_traversable: &MultiMap<Traversable, PedestrianID>,
+    ) -> Speed {
+        let peds = peds_per_traversable.get(on);
+        // Count ourselves too
+        let crowd = if peds.contains(&self.id) {
+            peds.len()
+        } else {
+            peds.len() + 1
+        };
+        crowded_speed(self.speed, crowd, on.length(map))
+    }
+
     fn get_dist_along(&self, now: Time, map: &Map) -> Distance {
         match self.state {
             PedState::Crossing(ref dist_int, ref time_int) => dist_int.lerp(time_int.percent(now)),
@@ -570,10 +793,11 @@ impl Pedestrian {
         scheduler: &mut Scheduler,
     ) -> bool {
         if let PathStep::Turn(t) = self.path.next_step() {
+            let speed = self.speed_on(Traversable::Turn(t), map, peds_per_traversable);
             if !intersections.maybe_start_turn(
                 AgentID::Pedestrian(self.id),
                 t,
-                self.speed,
+                speed,
                 now,
                 map,
                 scheduler,
@@ -590,7 +814,7 @@ impl Pedestrian {
             PathStep::ContraflowLane(l) => map.get_l(l).length(),
             PathStep::Turn(_) => Distance::ZERO,
         };
-        self.state = self.crossing_state(start_dist, now, map);
+        self.state = self.crossing_state(start_dist, now, map, peds_per_traversable);
         peds_per_traversable.insert(self.path.current_step().as_traversable(), self.id);
         events.push(Event::AgentEntersTraversable(
             AgentID::Pedestrian(self.id),
@@ -626,6 +850,18 @@ impl PedState {
     }
 }
 
+// How fast somebody walks along a sidewalk or crosswalk of some length with this many people on it,
+// counting them. Uses Weidmann's speed-density relationship.
+pub fn crowded_speed(free_speed: Speed, crowd: usize, length: Distance) -> Speed {
+    let area = length.inner_meters() * LANE_THICKNESS.inner_meters();
+    let density = (crowd as f64) / area.max(1.0);
+    if density <= FREE_FLOW_DENSITY {
+        return free_speed;
+    }
+    let factor = 1.0 - (-1.913 * (1.0 / density - 1.0 / JAM_DENSITY)).exp();
+    free_speed * factor.max(MIN_SPEED_FACTOR)
+}
+
 // The crowds returned here may have low/high values extending up to radius past the real geometry.
 fn find_crowds(
     input: Vec<(PedestrianID, Distance)>,
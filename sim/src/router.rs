@@ -2,8 +2,8 @@ use crate::mechanics::Queue;
 use crate::{Event, ParkingSimState, ParkingSpot, SidewalkSpot, TripID, Vehicle};
 use geom::Distance;
 use map_model::{
-    BuildingID, IntersectionID, LaneID, Map, Path, PathConstraints, PathRequest, PathStep,
-    Position, Traversable, TurnID,
+    BuildingID, IntersectionID, Lane, LaneID, Map, Path, PathConstraints, PathRequest, PathStep,
+    Position, Traversable, Turn, TurnID,
 };
 use serde_derive::{Deserialize, Serialize};
 use std::collections::{BTreeMap, HashMap, VecDeque};
@@ -22,6 +22,8 @@ pub enum ActionAtEnd {
     GotoLaneEnd,
     StopBiking(SidewalkSpot),
     BusAtStop,
+    DoubleParkForDelivery,
+    EmergencyVehicleArrived,
     AbortTrip,
 }
 
@@ -45,6 +47,17 @@ enum Goal {
     FollowBusRoute {
         end_dist: Distance,
     },
+    // Use a free spot on the last lane as a loading zone, or double-park in front of the building
+    DeliverTo {
+        target: BuildingID,
+        spot: Option<(ParkingSpot, Distance)>,
+        double_park_dist: Distance,
+    },
+    // Emergency vehicles just stop in front of the building
+    RespondTo {
+        target: BuildingID,
+        end_dist: Distance,
+    },
 }
 
 impl Router {
@@ -80,6 +93,29 @@ impl Router {
         }
     }
 
+    pub fn deliver_to(path: Path, bldg: BuildingID, vehicle: &Vehicle, map: &Map) -> Router {
+        let double_park_dist = dist_in_front_of(&path, bldg, vehicle, map);
+        Router {
+            path,
+            goal: Goal::DeliverTo {
+                target: bldg,
+                spot: None,
+                double_park_dist,
+            },
+        }
+    }
+
+    pub fn respond_to(path: Path, bldg: BuildingID, vehicle: &Vehicle, map: &Map) -> Router {
+        let end_dist = dist_in_front_of(&path, bldg, vehicle, map);
+        Router {
+            path,
+            goal: Goal::RespondTo {
+                target: bldg,
+                end_dist,
+            },
+        }
+    }
+
     pub fn head(&self) -> Traversable {
         self.path.current_step().as_traversable()
     }
@@ -112,6 +148,20 @@ impl Router {
             } => stuck_end_dist.unwrap_or_else(|| spot.unwrap().1),
             Goal::BikeThenStop { end_dist } => end_dist,
             Goal::FollowBusRoute { end_dist } => end_dist,
+            Goal::DeliverTo {
+                spot,
+                double_park_dist,
+                ..
+            } => spot.map(|(_, dist)| dist).unwrap_or(double_park_dist),
+            Goal::RespondTo { end_dist, .. } => end_dist,
+        }
+    }
+
+    // Does the vehicle just vanish at the end of the path, without needing to stop?
+    pub fn vanishes_at_end(&self) -> bool {
+        match self.goal {
+            Goal::EndAtBorder { .. } => true,
+            _ => false,
         }
     }
 
@@ -119,6 +169,71 @@ impl Router {
         &self.path
     }
 
+    pub fn path_broken_by_edits(&self, vehicle: &Vehicle, map: &Map) -> bool {
+        !self
+            .path
+            .is_valid_after_current_step(vehicle.vehicle_type.to_constraints(), map)
+    }
+
+    // After live map edits, find a new way to the same destination, starting from the end of the
+    // current step. False if there's no way there anymore.
+    pub fn reroute_after_edits(&mut self, vehicle: &Vehicle, map: &Map) -> bool {
+        if let Some(path) = map.pathfind(self.remaining_request(vehicle, map)) {
+            self.path.replace_after_current_step(path, map);
+            true
+        } else {
+            false
+        }
+    }
+
+    // Like reroute_after_edits, but using custom costs. False if there's no path or it's the same
+    // as the current one.
+    pub fn reroute_with_costs<F: Fn(&Lane, &Turn, usize) -> Option<usize>>(
+        &mut self,
+        vehicle: &Vehicle,
+        map: &Map,
+        cost: F,
+    ) -> bool {
+        match map.pathfind_with_costs(self.remaining_request(vehicle, map), cost) {
+            Some(path) => {
+                let mut new_path = self.path.clone();
+                new_path.replace_after_current_step(path, map);
+                if new_path.get_steps() == self.path.get_steps() {
+                    return false;
+                }
+                self.path = new_path;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn remaining_request(&self, vehicle: &Vehicle, map: &Map) -> PathRequest {
+        let end_dist = match self.goal {
+            Goal::EndAtBorder { end_dist, .. } => end_dist,
+            Goal::ParkNearBuilding {
+                spot,
+                stuck_end_dist,
+                ..
+            } => stuck_end_dist
+                .or_else(|| spot.map(|(_, dist)| dist))
+                .unwrap_or_else(|| self.path.end_dist()),
+            Goal::BikeThenStop { end_dist } => end_dist,
+            Goal::FollowBusRoute { end_dist } => end_dist,
+            Goal::DeliverTo {
+                spot,
+                double_park_dist,
+                ..
+            } => spot.map(|(_, dist)| dist).unwrap_or(double_park_dist),
+            Goal::RespondTo { end_dist, .. } => end_dist,
+        };
+        PathRequest {
+            start: self.path.current_step_end(map),
+            end: Position::new(self.path.last_step().as_lane(), end_dist),
+            constraints: vehicle.vehicle_type.to_constraints(),
+        }
+    }
+
     // Returns the step just finished
     pub fn advance(
         &mut self,
@@ -258,6 +373,52 @@ impl Router {
                     None
                 }
             }
+            Goal::DeliverTo {
+                ref mut spot,
+                ref mut double_park_dist,
+                ..
+            } => {
+                let need_new_spot = match spot {
+                    Some((s, _)) => !parking.is_free(*s),
+                    None => true,
+                };
+                if need_new_spot {
+                    // Unlike parking, don't go searching elsewhere.
+                    let current_lane = self.path.current_step().as_lane();
+                    *spot = parking
+                        .get_first_free_spot(Position::new(current_lane, front), vehicle, map)
+                        .map(|(s, pos)| (s, pos.dist_along()));
+                    // If the spot we were heading for got taken after we passed the building,
+                    // stop at the end of the lane instead.
+                    if spot.is_none() && front > *double_park_dist {
+                        *double_park_dist = map.get_l(current_lane).length();
+                    }
+                }
+
+                match spot {
+                    Some((s, dist)) => {
+                        if *dist == front {
+                            Some(ActionAtEnd::StartParking(*s))
+                        } else {
+                            None
+                        }
+                    }
+                    None => {
+                        if *double_park_dist == front {
+                            Some(ActionAtEnd::DoubleParkForDelivery)
+                        } else {
+                            None
+                        }
+                    }
+                }
+            }
+            Goal::RespondTo { end_dist, .. } => {
+                if end_dist == front {
+                    Some(ActionAtEnd::EmergencyVehicleArrived)
+                } else {
+                    None
+                }
+            }
         }
     }
 
@@ -329,13 +490,107 @@ impl Router {
         self.path.modify_step(2, PathStep::Lane(best_lane), map);
         self.path.modify_step(3, PathStep::Turn(turn2), map);
     }
+
+    // If the vehicle moved over to a parallel lane partway along the current one, how would it
+    // rejoin the rest of the path? Returns the steps to replace the start of the path with.
+    pub fn midblock_lanechange(&self, to: LaneID, map: &Map) -> Option<Vec<PathStep>> {
+        let steps = self.path.get_steps();
+        if steps.len() < 3 {
+            return None;
+        }
+        let orig_turn = match steps[1] {
+            PathStep::Turn(t) => t,
+            _ => {
+                return None;
+            }
+        };
+
+        // Easy case: the new lane can make the same turn.
+        let turn = TurnID {
+            parent: orig_turn.parent,
+            src: to,
+            dst: orig_turn.dst,
+        };
+        if map.maybe_get_t(turn).is_some() {
+            return Some(vec![PathStep::Lane(to), PathStep::Turn(turn)]);
+        }
+
+        // Otherwise turn into a sibling of the original destination and rejoin the path after
+        // that, same as opportunistically_lanechange.
+        if steps.len() < 5 {
+            return None;
+        }
+        let next_lane = match (steps[3], steps[4]) {
+            (PathStep::Turn(_), PathStep::Lane(l)) => l,
+            _ => {
+                return None;
+            }
+        };
+        let next_parent = map.get_l(next_lane).src_i;
+        let orig_lt = map.get_l(orig_turn.dst).lane_type;
+        let parent = map.get_parent(orig_turn.dst);
+        let siblings = if parent.is_forwards(orig_turn.dst) {
+            &parent.children_forwards
+        } else {
+            &parent.children_backwards
+        };
+        for (l, lt) in siblings {
+            if *lt != orig_lt {
+                continue;
+            }
+            let turn1 = TurnID {
+                parent: orig_turn.parent,
+                src: to,
+                dst: *l,
+            };
+            let turn2 = TurnID {
+                parent: next_parent,
+                src: *l,
+                dst: next_lane,
+            };
+            if map.maybe_get_t(turn1).is_some() && map.maybe_get_t(turn2).is_some() {
+                return Some(vec![
+                    PathStep::Lane(to),
+                    PathStep::Turn(turn1),
+                    PathStep::Lane(*l),
+                    PathStep::Turn(turn2),
+                ]);
+            }
+        }
+        None
+    }
+
+    // Steps must come from midblock_lanechange.
+    pub fn change_lanes(&mut self, steps: Vec<PathStep>, map: &Map) {
+        for (idx, step) in steps.into_iter().enumerate() {
+            if idx == 0 {
+                self.path.change_current_lane(step.as_lane(), map);
+            } else {
+                self.path.modify_step(idx, step, map);
+            }
+        }
+    }
+}
+
+// Where to stop along the last lane of the path to be in front of a building. The last lane might
+// be somewhere else entirely, if it's a parking blackhole; then just stop at the end.
+fn dist_in_front_of(path: &Path, bldg: BuildingID, vehicle: &Vehicle, map: &Map) -> Distance {
+    let last_lane = path.last_step().as_lane();
+    let len = map.get_l(last_lane).length();
+    let front = map.get_b(bldg).front_path.sidewalk;
+    let dist = if map.get_l(front.lane()).parent == map.get_l(last_lane).parent {
+        front.equiv_pos(last_lane, vehicle.length, map).dist_along()
+    } else {
+        len
+    };
+    dist.max(vehicle.length).min(len)
 }
 
 // Unrealistically assumes the driver has knowledge of currently free parking spots, even if
 // they're far away. Since they don't reserve the spot in advance, somebody else can still beat
 // them there, producing some nice, realistic churn if there's too much contention.
 // The first PathStep is the turn after start, NOT PathStep::Lane(start).
-fn path_to_free_parking_spot(
+pub(crate) fn path_to_free_parking_spot(
     start: LaneID,
     vehicle: &Vehicle,
     map: &Map,
@@ -1,7 +1,7 @@
-use crate::{AgentID, CarID, CreateCar, CreatePedestrian, PedestrianID};
+use crate::{AgentID, CarID, CreateCar, CreatePedestrian, PedestrianID, PersonID};
 use derivative::Derivative;
 use geom::{Duration, DurationHistogram, Time};
-use map_model::{IntersectionID, PathRequest};
+use map_model::{BusRouteID, IntersectionID, PathRequest};
 use serde_derive::{Deserialize, Serialize};
 use std::cmp::Ordering;
 use std::collections::{BTreeMap, BinaryHeap};
@@ -14,8 +14,20 @@ pub enum Command {
     UpdateCar(CarID),
     // Distinguish this from UpdateCar to avoid confusing things
     UpdateLaggyHead(CarID),
+    // Only used when SimOptions enables rerouting
+    RerouteIfBlocked(CarID),
+    // Only used for emergency vehicles, or when SimOptions enables mid-block lane-changing
+    ChangeLanesIfBlocked(CarID),
     UpdatePed(PedestrianID),
     UpdateIntersection(IntersectionID),
+    // The next departure in the route's timetable
+    DispatchBus(BusRouteID),
+    // The person's next trip is ready to start
+    StartTripForPerson(PersonID),
+    // Indexes into the scenario's list of incidents
+    StartIncident(usize),
+    EndIncident(usize),
+    CheckForGridlock(Duration),
     Savestate(Duration),
 }
 
@@ -33,8 +45,14 @@ impl Command {
             Command::SpawnPed(ref create, _) => CommandType::Ped(create.id),
             Command::UpdateCar(id) => CommandType::Car(*id),
             Command::UpdateLaggyHead(id) => CommandType::CarLaggyHead(*id),
+            Command::RerouteIfBlocked(id) => CommandType::CarReroute(*id),
+            Command::ChangeLanesIfBlocked(id) => CommandType::CarLaneChange(*id),
             Command::UpdatePed(id) => CommandType::Ped(*id),
             Command::UpdateIntersection(id) => CommandType::Intersection(*id),
+            Command::DispatchBus(id) => CommandType::BusRoute(*id),
+            Command::StartTripForPerson(id) => CommandType::Person(*id),
+            Command::StartIncident(idx) | Command::EndIncident(idx) => CommandType::Incident(*idx),
+            Command::CheckForGridlock(_) => CommandType::CheckForGridlock,
             Command::Savestate(_) => CommandType::Savestate,
         }
     }
@@ -46,8 +64,14 @@ impl Command {
 pub enum CommandType {
     Car(CarID),
     CarLaggyHead(CarID),
+    CarReroute(CarID),
+    CarLaneChange(CarID),
     Ped(PedestrianID),
     Intersection(IntersectionID),
+    BusRoute(BusRouteID),
+    Person(PersonID),
+    Incident(usize),
+    CheckForGridlock,
     Savestate,
 }
 
@@ -152,6 +176,11 @@ impl Scheduler {
         });
     }
 
+    // Only for fixing up agents that haven't spawned yet, after live map edits.
+    pub fn queued_commands_mut(&mut self) -> impl Iterator<Item = &mut Command> {
+        self.queued_commands.values_mut().map(|(cmd, _)| cmd)
+    }
+
     pub fn cancel(&mut self, cmd: Command) {
         // It's fine if a previous command hasn't actually been scheduled.
         self.queued_commands.remove(&cmd.to_type());
@@ -1,20 +1,21 @@
 use crate::{
     AgentID, AgentMetadata, Analytics, CarID, Command, CreateCar, DrawCarInput, DrawPedCrowdInput,
-    DrawPedestrianInput, DrivingGoal, DrivingSimState, Event, GetDrawAgents, IntersectionSimState,
-    ParkedCar, ParkingSimState, ParkingSpot, PedestrianID, Router, Scheduler, SidewalkPOI,
-    SidewalkSpot, TransitSimState, TripCount, TripEnd, TripID, TripLeg, TripManager, TripMode,
-    TripPositions, TripResult, TripSpawner, TripSpec, TripStart, UnzoomedAgent, VehicleSpec,
-    VehicleType, WalkingSimState, BUS_LENGTH,
+    DrawPedestrianInput, DrivingGoal, DrivingSimState, EmissionStats, Event, GetDrawAgents,
+    HoldingStrategy, Incident, IntersectionSimState, ParkedCar, ParkingSimState, ParkingSpot,
+    PedestrianID, PersonID, Router, ScheduledIncident, Scheduler, SidewalkPOI, SidewalkSpot,
+    TransitSimState, TravelTimes, TripCount, TripEnd, TripID, TripLeg, TripManager, TripMode,
+    TripPaths, TripPositions, TripResult, TripSpawner, TripSpec, TripStart, UnzoomedAgent,
+    VehicleSpec, VehicleType, WalkingSimState, BUS_LENGTH, TRAIN_LENGTH,
 };
 use abstutil::Timer;
 use derivative::Derivative;
-use geom::{Distance, Duration, PolyLine, Pt2D, Time};
+use geom::{Distance, Duration, PolyLine, Pt2D, Speed, Time};
 use map_model::{
-    BuildingID, BusRoute, BusRouteID, IntersectionID, LaneID, Map, Path, PathConstraints,
-    PathRequest, PathStep, Traversable,
+    BuildingID, BusRoute, BusRouteID, BusStopID, IntersectionID, LaneID, Map, MapEdits, Path,
+    PathConstraints, PathRequest, PathStep, Traversable,
 };
 use serde_derive::{Deserialize, Serialize};
-use std::collections::{BTreeMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::panic;
 use std::time::Instant;
 
@@ -35,6 +36,10 @@ pub struct Sim {
     time: Time,
     car_id_counter: usize,
     ped_id_counter: usize,
+    incidents: Vec<ScheduledIncident>,
+    // Indices into incidents
+    active_incidents: BTreeSet<usize>,
+    resolve_gridlock: bool,
 
     // TODO Reconsider these
     pub(crate) map_name: String,
@@ -62,6 +67,24 @@ pub struct SimOptions {
     pub use_freeform_policy_everywhere: bool,
     pub disable_block_the_box: bool,
     pub recalc_lanechanging: bool,
+    // If set, vehicles waiting at the front of a queue for this long try to find a less congested
+    // way to their destination. They also avoid closed lanes as soon as they reach them.
+    pub reroute_blocked_after: Option<Duration>,
+    // Vehicles stuck behind somebody partway along a road move over to an adjacent lane when
+    // there's a gap, instead of only changing lanes at intersections.
+    pub midblock_lanechanging: bool,
+    // If set, periodically look for vehicles waiting on each other in a cycle.
+    pub check_gridlock_every: Option<Duration>,
+    // When gridlock is found, delete the vehicle in each cycle that's been stuck the longest.
+    pub resolve_gridlock: bool,
+    // Route vehicles using travel times saved from a previous run, instead of free-flow costs.
+    pub travel_times_from: Option<String>,
+    // Force trips to use vehicle paths saved from a previous run, like traffic assignment.
+    pub paths_from: Option<String>,
+    // If set, buses on every route wait at some stops to keep from bunching.
+    pub bus_holding: Option<HoldingStrategy>,
+    // Estimate emissions with factors loaded from this JSON file, instead of the defaults.
+    pub emission_factors_from: Option<String>,
 }
 
 impl SimOptions {
@@ -72,6 +95,14 @@ impl SimOptions {
             use_freeform_policy_everywhere: false,
             disable_block_the_box: false,
             recalc_lanechanging: true,
+            reroute_blocked_after: None,
+            midblock_lanechanging: false,
+            check_gridlock_every: None,
+            resolve_gridlock: false,
+            travel_times_from: None,
+            paths_from: None,
+            bus_holding: None,
+            emission_factors_from: None,
         }
     }
 }
@@ -83,8 +114,27 @@ impl Sim {
         if let Some(d) = opts.savestate_every {
             scheduler.push(Time::START_OF_DAY + d, Command::Savestate(d));
         }
+        if let Some(d) = opts.check_gridlock_every {
+            scheduler.push(Time::START_OF_DAY + d, Command::CheckForGridlock(d));
+        }
+        let mut trips = TripManager::new();
+        if let Some(path) = opts.travel_times_from {
+            trips.use_travel_times(abstutil::read_binary(path, timer));
+        }
+        if let Some(path) = opts.paths_from {
+            trips.use_fixed_paths(abstutil::read_binary(path, timer));
+        }
+        let mut analytics = Analytics::new();
+        if let Some(path) = opts.emission_factors_from {
+            analytics.emissions = EmissionStats::new(abstutil::read_json(path, timer));
+        }
         Sim {
-            driving: DrivingSimState::new(map, opts.recalc_lanechanging),
+            driving: DrivingSimState::new(
+                map,
+                opts.recalc_lanechanging,
+                opts.reroute_blocked_after,
+                opts.midblock_lanechanging,
+            ),
             parking: ParkingSimState::new(map, timer),
             walking: WalkingSimState::new(),
             intersections: IntersectionSimState::new(
@@ -93,13 +143,16 @@ impl Sim {
                 opts.use_freeform_policy_everywhere,
                 opts.disable_block_the_box,
             ),
-            transit: TransitSimState::new(),
-            trips: TripManager::new(),
+            transit: TransitSimState::new(opts.bus_holding.clone()),
+            trips,
             spawner: TripSpawner::new(),
             scheduler,
             time: Time::START_OF_DAY,
             car_id_counter: 0,
             ped_id_counter: 0,
+            incidents: Vec::new(),
+            active_incidents: BTreeSet::new(),
+            resolve_gridlock: opts.resolve_gridlock,
 
             map_name: map.get_name().to_string(),
             // TODO
@@ -108,7 +161,7 @@ impl Sim {
             step_count: 0,
             trip_positions: None,
 
-            analytics: Analytics::new(),
+            analytics,
         }
     }
 
@@ -117,6 +170,26 @@ impl Sim {
         start_time: Time,
         spec: TripSpec,
         map: &Map,
+    ) -> (Option<PedestrianID>, Option<CarID>) {
+        self.schedule_trip_for(start_time, None, spec, map)
+    }
+
+    // Each trip starts once the previous one is done and the dwell time has passed, but never
+    // before its departure time.
+    pub fn schedule_person(&mut self, id: PersonID, trips: Vec<(Time, Duration, TripSpec)>) {
+        if let Some((depart, _, _)) = trips.first() {
+            self.scheduler
+                .push(*depart, Command::StartTripForPerson(id));
+        }
+        self.trips.new_person(id, trips);
+    }
+
+    fn schedule_trip_for(
+        &mut self,
+        start_time: Time,
+        person: Option<PersonID>,
+        spec: TripSpec,
+        map: &Map,
     ) -> (Option<PedestrianID>, Option<CarID>) {
         let (ped_id, car_id) = match spec {
             TripSpec::CarAppearing {
@@ -139,22 +212,41 @@ impl Sim {
             TripSpec::UsingParkedCar { .. }
             | TripSpec::MaybeUsingParkedCar { .. }
             | TripSpec::JustWalking { .. }
-            | TripSpec::UsingTransit { .. } => {
+            | TripSpec::UsingTransit { .. }
+            | TripSpec::DriveThenTransit { .. } => {
                 let id = PedestrianID(self.ped_id_counter);
                 self.ped_id_counter += 1;
                 (Some(id), None)
             }
-            TripSpec::UsingBike { .. } => {
+            TripSpec::UsingBike { .. } | TripSpec::BikeThenTransit { .. } => {
                 let ped = PedestrianID(self.ped_id_counter);
                 self.ped_id_counter += 1;
                 let car = CarID(self.car_id_counter, VehicleType::Bike);
                 self.car_id_counter += 1;
                 (Some(ped), Some(car))
             }
+            TripSpec::Delivery {
+                ref vehicle_spec, ..
+            }
+            | TripSpec::Emergency {
+                ref vehicle_spec, ..
+            } => {
+                let car = CarID(self.car_id_counter, vehicle_spec.vehicle_type);
+                self.car_id_counter += 1;
+                (None, Some(car))
+            }
         };
 
-        self.spawner
-            .schedule_trip(start_time, ped_id, car_id, spec, map, &self.parking);
+        self.spawner.schedule_trip(
+            start_time,
+            ped_id,
+            car_id,
+            person,
+            spec,
+            map,
+            &self.parking,
+            &mut self.trips,
+        );
         (ped_id, car_id)
     }
 
@@ -212,74 +304,17 @@ impl Sim {
 
         // Try to spawn just ONE bus anywhere.
         // TODO Be more realistic. One bus per stop is too much, one is too little.
-        for (next_stop_idx, mut path, end_dist) in
+        for (next_stop_idx, path, end_dist) in
             self.transit.create_empty_route(route, map).into_iter()
         {
-            let id = CarID(self.car_id_counter, VehicleType::Bus);
-            self.car_id_counter += 1;
-
-            // For now, no desire for randomness. Caller can pass in list of specs if that ever
-            // changes.
-            let vehicle = VehicleSpec {
-                vehicle_type: VehicleType::Bus,
-                length: BUS_LENGTH,
-                max_speed: None,
-            }
-            .make(id, None);
-
-            // TODO The path analytics (total dist, dist crossed so far) will be wrong for the
-            // first round of buses.
-            // Same for this TripStart, though it doesn't matter too much.
-            let trip = self.trips.new_trip(
-                self.time,
-                TripStart::Border(map.get_l(path.current_step().as_lane()).src_i),
-                vec![TripLeg::ServeBusRoute(id, route.id)],
-            );
-
-            loop {
-                if path.is_last_step() {
-                    timer.warn(format!(
-                        "Giving up on seeding a bus headed towards stop {} of {} ({})",
-                        next_stop_idx, route.name, route.id
-                    ));
-                    self.trips.abort_trip_failed_start(trip);
-                    break;
-                }
-                let start_lane = if let PathStep::Lane(l) = path.current_step() {
-                    l
-                } else {
-                    path.shift(map);
-                    continue;
-                };
-                if map.get_l(start_lane).length() < vehicle.length {
-                    path.shift(map);
-                    continue;
-                }
-
-                // Bypass some layers of abstraction that don't make sense for buses.
-                if self.driving.start_car_on_lane(
-                    self.time,
-                    CreateCar {
-                        start_dist: vehicle.length,
-                        vehicle: vehicle.clone(),
-                        router: Router::follow_bus_route(path.clone(), end_dist),
-                        maybe_parked_car: None,
-                        trip,
-                    },
-                    map,
-                    &self.intersections,
-                    &self.parking,
-                    &mut self.scheduler,
-                ) {
-                    self.trips.agent_starting_trip_leg(AgentID::Car(id), trip);
-                    self.transit.bus_created(id, route.id, next_stop_idx);
-                    self.analytics.record_demand(&path, map);
-                    results.push(id);
-                    return results;
-                } else {
-                    path.shift(map);
-                }
+            if let Some(id) = self.start_bus(route, next_stop_idx, path, end_dist, None, map) {
+                results.push(id);
+                return results;
             }
+            timer.warn(format!(
+                "Giving up on seeding a bus headed towards stop {} of {} ({})",
+                next_stop_idx, route.name, route.id
+            ));
         }
         if results.is_empty() {
             // TODO Bigger failure
@@ -288,9 +323,150 @@ impl Sim {
         results
     }
 
+    // Instead of seeding buses now, start one from the first stop of every run in the route's
+    // timetable.
+    pub fn schedule_bus_route(&mut self, route: &BusRoute, map: &Map) {
+        self.transit.create_empty_route(route, map);
+        if let Some(t) = self.transit.start_timetable(route.id, self.time) {
+            self.scheduler.push(t, Command::DispatchBus(route.id));
+        }
+    }
+
+    fn start_bus(
+        &mut self,
+        route: &BusRoute,
+        next_stop_idx: usize,
+        mut path: Path,
+        end_dist: Distance,
+        run: Option<usize>,
+        map: &Map,
+    ) -> Option<CarID> {
+        let (vehicle_type, length) = if route.route_type == PathConstraints::Train {
+            (VehicleType::Train, TRAIN_LENGTH)
+        } else {
+            (VehicleType::Bus, BUS_LENGTH)
+        };
+        let id = CarID(self.car_id_counter, vehicle_type);
+        self.car_id_counter += 1;
+
+        // For now, no desire for randomness. Caller can pass in list of specs if that ever
+        // changes.
+        let (max_accel, max_decel) = vehicle_type.default_accel_limits();
+        let vehicle = VehicleSpec {
+            vehicle_type,
+            length,
+            max_speed: None,
+            max_accel,
+            max_decel,
+        }
+        .make(id, None);
+
+        // TODO The path analytics (total dist, dist crossed so far) will be wrong for the
+        // first round of buses.
+        // Same for this TripStart, though it doesn't matter too much.
+        let trip = self.trips.new_trip(
+            self.time,
+            TripStart::Border(map.get_l(path.current_step().as_lane()).src_i),
+            vec![TripLeg::ServeBusRoute(id, route.id)],
+            None,
+        );
+
+        loop {
+            // Starting on the last lane only works if there's room behind the stop.
+            if path.is_last_step() && end_dist <= vehicle.length {
+                self.trips.abort_trip_failed_start(trip);
+                return None;
+            }
+            let start_lane = if let PathStep::Lane(l) = path.current_step() {
+                l
+            } else {
+                path.shift(map);
+                continue;
+            };
+            if map.get_l(start_lane).length() < vehicle.length {
+                path.shift(map);
+                continue;
+            }
+
+            // Bypass some layers of abstraction that don't make sense for buses.
+            if self.driving.start_car_on_lane(
+                self.time,
+                CreateCar {
+                    start_dist: vehicle.length,
+                    vehicle: vehicle.clone(),
+                    router: Router::follow_bus_route(path.clone(), end_dist),
+                    maybe_parked_car: None,
+                    trip,
+                },
+                map,
+                &mut self.intersections,
+                &self.parking,
+                &mut self.scheduler,
+            ) {
+                self.trips.agent_starting_trip_leg(AgentID::Car(id), trip);
+                self.transit.bus_created(id, route.id, next_stop_idx, run);
+                self.analytics.record_demand(&path, map);
+                return Some(id);
+            } else if path.is_last_step() {
+                self.trips.abort_trip_failed_start(trip);
+                return None;
+            } else {
+                path.shift(map);
+            }
+        }
+    }
+
+    // Buses on this route will only hold at these stops. False if the route hasn't been seeded.
+    pub fn set_bus_holding(
+        &mut self,
+        route: BusRouteID,
+        time_points: Vec<BusStopID>,
+        holding: Option<HoldingStrategy>,
+    ) -> bool {
+        self.transit.set_holding(route, time_points, holding)
+    }
+
     pub fn set_name(&mut self, name: String) {
         self.run_name = name;
     }
+
+    // Route vehicles that haven't started yet using these. They could come from a previous run,
+    // or what's been observed so far in this one.
+    pub fn use_travel_times(&mut self, travel_times: TravelTimes) {
+        self.trips.use_travel_times(travel_times);
+    }
+
+    pub fn get_travel_times(&self) -> Option<&TravelTimes> {
+        self.trips.get_travel_times()
+    }
+
+    // Trips that haven't started yet will use these paths, when they match what the trip needs.
+    pub fn use_fixed_paths(&mut self, paths: TripPaths) {
+        self.trips.use_fixed_paths(paths);
+    }
+
+    // Remember the path that every trip's vehicle starts with from now on.
+    pub fn record_paths(&mut self) {
+        self.trips.record_paths();
+    }
+
+    pub fn get_recorded_paths(&self) -> Option<&TripPaths> {
+        self.trips.get_recorded_paths()
+    }
+
+    pub fn schedule_incident(
+        &mut self,
+        incident: ScheduledIncident,
+        map: &Map,
+    ) -> Result<(), String> {
+        incident.validate(map)?;
+        self.scheduler.push(
+            incident.start_time,
+            Command::StartIncident(self.incidents.len()),
+        );
+        self.incidents.push(incident);
+        Ok(())
+    }
 }
 
 // Drawing
@@ -380,7 +556,7 @@ impl Sim {
                         self.time,
                         create_car.clone(),
                         map,
-                        &self.intersections,
+                        &mut self.intersections,
                         &self.parking,
                         &mut self.scheduler,
                     ) {
@@ -426,27 +602,14 @@ impl Sim {
                             };
                             if let Some(path) = map.pathfind(req.clone()) {
                                 create_ped.path = path;
-                                let mut legs = vec![
+                                let legs = vec![
                                     TripLeg::Walk(
                                         create_ped.id,
                                         create_ped.speed,
                                         create_ped.goal.clone(),
                                     ),
-                                    TripLeg::Drive(
-                                        parked_car.vehicle.clone(),
-                                        driving_goal.clone(),
-                                    ),
+                                    TripLeg::Drive(parked_car.vehicle.clone(), driving_goal),
                                 ];
-                                match driving_goal {
-                                    DrivingGoal::ParkNear(b) => {
-                                        legs.push(TripLeg::Walk(
-                                            create_ped.id,
-                                            create_ped.speed,
-                                            SidewalkSpot::building(b, map),
-                                        ));
-                                    }
-                                    DrivingGoal::Border(_, _) => {}
-                                }
                                 self.trips.dynamically_override_legs(create_ped.trip, legs);
                                 true
                             } else {
@@ -536,6 +699,24 @@ impl Sim {
                         &mut self.scheduler,
                     );
                 }
+                Command::RerouteIfBlocked(car) => {
+                    self.driving.reroute_if_blocked(
+                        car,
+                        self.time,
+                        map,
+                        &mut self.intersections,
+                        &mut self.scheduler,
+                    );
+                }
+                Command::ChangeLanesIfBlocked(car) => {
+                    self.driving.change_lanes_if_blocked(
+                        car,
+                        self.time,
+                        map,
+                        &mut self.intersections,
+                        &mut self.scheduler,
+                    );
+                }
                 Command::UpdatePed(ped) => {
                     self.walking.update_ped(
                         ped,
@@ -552,6 +733,67 @@ impl Sim {
                     self.intersections
                         .update_intersection(self.time, i, map, &mut self.scheduler);
                 }
+                Command::DispatchBus(route) => {
+                    let (new_bus, next) = self.transit.dispatch_bus(self.time, route);
+                    if let Some(t) = next {
+                        self.scheduler.push(t, Command::DispatchBus(route));
+                    }
+                    if let Some((first_stop, mut path, end_dist, run, departure)) = new_bus {
+                        let r = map.get_br(route);
+                        // Appear right behind the first stop if there's room, so the bus is there
+                        // on time.
+                        let length = if r.route_type == PathConstraints::Train {
+                            TRAIN_LENGTH
+                        } else {
+                            BUS_LENGTH
+                        };
+                        if end_dist > length {
+                            while path.isnt_last_step() {
+                                path.shift(map);
+                            }
+                        }
+                        if self
+                            .start_bus(r, first_stop, path, end_dist, Some(run), map)
+                            .is_none()
+                        {
+                            println!(
+                                "WARNING: Couldn't dispatch the {} bus of {} ({})",
+                                departure, r.name, route
+                            );
+                        }
+                    }
+                }
+                Command::StartTripForPerson(person) => {
+                    if let Some(spec) = self.trips.next_trip_for_person(person, &self.parking, map)
+                    {
+                        self.schedule_trip_for(self.time, Some(person), spec, map);
+                        self.spawn_all_trips(map, &mut Timer::throwaway(), true);
+                    }
+                }
+                Command::StartIncident(idx) => {
+                    let incident = self.incidents[idx].clone();
+                    self.apply_incident(idx, true, map);
+                    self.scheduler
+                        .push(incident.end_time, Command::EndIncident(idx));
+                    events.push(Event::IncidentStarted(incident.incident));
+                }
+                Command::EndIncident(idx) => {
+                    let incident = self.incidents[idx].incident.clone();
+                    self.apply_incident(idx, false, map);
+                    events.push(Event::IncidentEnded(incident));
+                }
+                Command::CheckForGridlock(frequency) => {
+                    self.scheduler
+                        .push(self.time + frequency, Command::CheckForGridlock(frequency));
+                    for (cars, lanes, intersections) in
+                        self.driving.find_gridlock(map, &self.intersections)
+                    {
+                        if self.resolve_gridlock {
+                            self.kill_stuck_car(cars[0], map);
+                        }
+                        events.push(Event::Gridlock(cars, lanes, intersections));
+                    }
+                }
                 Command::Savestate(frequency) => {
                     self.scheduler
                         .push(self.time + frequency, Command::Savestate(frequency));
@@ -567,6 +809,16 @@ impl Sim {
             events.extend(self.walking.collect_events());
             events.extend(self.intersections.collect_events());
             for ev in events {
+                // People start their next trip after finishing the previous one, even if it
+                // was aborted.
+                if let Event::TripFinished(trip, _, _) | Event::TripAborted(trip) = ev {
+                    if let Some((person, depart)) =
+                        self.trips.next_departure_for_person(trip, self.time)
+                    {
+                        self.scheduler
+                            .push(depart, Command::StartTripForPerson(person));
+                    }
+                }
                 self.analytics.event(ev, self.time, map);
             }
         }
@@ -579,6 +831,70 @@ impl Sim {
         self.trip_positions = None;
     }
 
+    // Incidents can overlap and affect the same thing, so one ending only undoes its effects if
+    // nothing still active causes them too.
+    fn apply_incident(&mut self, idx: usize, active: bool, map: &Map) {
+        if active {
+            self.active_incidents.insert(idx);
+        } else {
+            self.active_incidents.remove(&idx);
+        }
+        let all_active: Vec<Incident> = self
+            .active_incidents
+            .iter()
+            .map(|i| self.incidents[*i].incident.clone())
+            .collect();
+
+        match self.incidents[idx].incident {
+            Incident::BlockLane(l) => {
+                let blocked = all_active.contains(&Incident::BlockLane(l));
+                self.intersections.set_lane_blocked(
+                    self.time,
+                    l,
+                    blocked,
+                    map,
+                    &mut self.scheduler,
+                );
+            }
+            Incident::CloseIntersection(i) => {
+                let closed = all_active.contains(&Incident::CloseIntersection(i));
+                self.intersections.set_intersection_closed(
+                    self.time,
+                    i,
+                    closed,
+                    map,
+                    &mut self.scheduler,
+                );
+            }
+            Incident::SignalFailure(i) => {
+                let failed = all_active.contains(&Incident::SignalFailure(i));
+                self.intersections.set_signal_failed(
+                    self.time,
+                    i,
+                    failed,
+                    map,
+                    &mut self.scheduler,
+                );
+            }
+            Incident::ReduceSpeed(r, _) => {
+                // The strictest cap wins.
+                let mut cap: Option<Speed> = None;
+                for incident in &all_active {
+                    if let Incident::ReduceSpeed(r2, speed) = incident {
+                        if *r2 == r {
+                            cap = Some(cap.map(|c| c.min(*speed)).unwrap_or(*speed));
+                        }
+                    }
+                }
+                if let Some(speed) = cap {
+                    self.driving.cap_speed(r, speed);
+                } else {
+                    self.driving.uncap_speed(r);
+                }
+            }
+        }
+    }
+
     pub fn timed_step(&mut self, map: &Map, dt: Duration, timer: &mut Timer) {
         // TODO Ideally print every second or so
         let orig_time = self.time;
@@ -787,6 +1103,16 @@ impl Sim {
         self.intersections.debug(id, map);
     }
 
+    // Only for traffic signals. The phase index and the time until the signal reconsiders it.
+    pub fn current_phase_and_remaining_time(
+        &self,
+        i: IntersectionID,
+        map: &Map,
+    ) -> (usize, Duration) {
+        self.intersections
+            .current_phase_and_remaining_time(self.time, i, map)
+    }
+
     pub fn debug_lane(&self, id: LaneID) {
         self.driving.debug_lane(id);
     }
@@ -800,9 +1126,15 @@ impl Sim {
     pub fn car_tooltip(&self, car: CarID) -> Vec<String> {
         if let Some(mut lines) = self.driving.tooltip_lines(car, self.time) {
             lines.extend(self.trips.tooltip_lines(AgentID::Car(car)));
-            if car.1 == VehicleType::Bus {
+            if car.1.is_transit() {
                 let passengers = self.transit.get_passengers(car);
-                lines.push(format!("{} passengers riding", passengers.len()));
+                let (seated, standing) = self.transit.get_capacity(car);
+                lines.push(format!(
+                    "{} passengers riding ({} seats, {} standing room)",
+                    passengers.len(),
+                    seated,
+                    standing
+                ));
                 for (id, stop) in passengers {
                     lines.push(format!("- {} till {:?}", id, stop));
                 }
@@ -825,7 +1157,7 @@ impl Sim {
     }
 
     pub fn bus_route_id(&self, maybe_bus: CarID) -> Option<BusRouteID> {
-        if maybe_bus.1 == VehicleType::Bus {
+        if maybe_bus.1.is_transit() {
             Some(self.transit.bus_route(maybe_bus))
         } else {
             None
@@ -849,17 +1181,26 @@ impl Sim {
     }
 
     pub fn lookup_car_id(&self, idx: usize) -> Option<CarID> {
-        for vt in &[VehicleType::Car, VehicleType::Bike, VehicleType::Bus] {
+        for vt in &[
+            VehicleType::Car,
+            VehicleType::Bike,
+            VehicleType::Bus,
+            VehicleType::Train,
+            VehicleType::Truck,
+            VehicleType::Emergency,
+        ] {
             let id = CarID(idx, *vt);
             if self.driving.tooltip_lines(id, self.time).is_some() {
                 return Some(id);
             }
         }
 
-        let id = CarID(idx, VehicleType::Car);
-        // Only cars can be parked.
-        if self.parking.tooltip_lines(id).is_some() {
-            return Some(id);
+        // Only cars (and trucks at a loading zone) can be parked.
+        for vt in &[VehicleType::Car, VehicleType::Truck] {
+            let id = CarID(idx, *vt);
+            if self.parking.tooltip_lines(id).is_some() {
+                return Some(id);
+            }
         }
 
         None
@@ -981,4 +1322,132 @@ impl Sim {
             println!("{} has no trip?!", id);
         }
     }
+
+    // Keep the simulation running after map edits. Call this after the edits are applied and
+    // pathfinding is recalculated, passing the edits the simulation was running with before.
+    // Agents whose paths cross anything that changed are rerouted, cars parked on removed parking
+    // lanes are moved, and edited traffic signals restart. Agents that can't finish their trip
+    // anymore are deleted.
+    pub fn handle_live_edits(&mut self, old_edits: &MapEdits, map: &Map, timer: &mut Timer) {
+        // Before anything else, so agents being deleted don't wake up requests for turns that
+        // don't exist.
+        let changed = map.get_edits().changed_since(old_edits, map);
+        self.intersections
+            .handle_live_edits(self.time, &changed, map, &mut self.scheduler);
+
+        let moved_cars: Vec<(ParkingSpot, SidewalkSpot)> = self
+            .parking
+            .handle_live_edits(map, timer)
+            .into_iter()
+            .map(|(old, new)| (old, SidewalkSpot::parking_spot(new, map, &self.parking)))
+            .collect();
+        for (old, new) in &moved_cars {
+            self.trips.parked_car_moved(*old, new);
+        }
+
+        let mut num_deleted = 0;
+        for car in self.driving.handle_live_edits(
+            self.time,
+            map,
+            &mut self.scheduler,
+            &mut self.intersections,
+        ) {
+            self.trips.abort_active_trip(AgentID::Car(car));
+            num_deleted += 1;
+            if self.transit.serves_route(car) {
+                let (riding, waiting) = self.transit.bus_deleted(car);
+                for ped in riding {
+                    self.trips.abort_active_trip(AgentID::Pedestrian(ped));
+                    num_deleted += 1;
+                }
+                for ped in waiting {
+                    self.walking.delete_ped_waiting_for_bus(ped);
+                    self.trips.abort_active_trip(AgentID::Pedestrian(ped));
+                    num_deleted += 1;
+                }
+            }
+        }
+        for ped in self.walking.handle_live_edits(
+            self.time,
+            &moved_cars,
+            map,
+            &mut self.intersections,
+            &mut self.scheduler,
+        ) {
+            self.trips.abort_active_trip(AgentID::Pedestrian(ped));
+            num_deleted += 1;
+        }
+
+        // Agents that haven't spawned yet
+        let mut failed_spawns: Vec<(Command, TripID)> = Vec::new();
+        for cmd in self.scheduler.queued_commands_mut() {
+            match cmd {
+                Command::SpawnCar(create_car, _, _) => {
+                    let start_lane = map.get_l(create_car.router.head().as_lane());
+                    // TODO Could start the car from the new spot instead.
+                    let car_moved = create_car
+                        .maybe_parked_car
+                        .as_ref()
+                        .map(|p| moved_cars.iter().any(|(old, _)| *old == p.spot))
+                        .unwrap_or(false);
+                    if car_moved
+                        || !create_car
+                            .vehicle
+                            .vehicle_type
+                            .to_constraints()
+                            .can_use(start_lane, map)
+                        || (create_car
+                            .router
+                            .path_broken_by_edits(&create_car.vehicle, map)
+                            && !create_car
+                                .router
+                                .reroute_after_edits(&create_car.vehicle, map))
+                    {
+                        failed_spawns
+                            .push((Command::UpdateCar(create_car.vehicle.id), create_car.trip));
+                    }
+                }
+                Command::SpawnPed(create_ped, req) => {
+                    let mut goal_changed = false;
+                    if let SidewalkPOI::ParkingSpot(spot) = create_ped.goal.connection {
+                        if let Some((_, new_goal)) = moved_cars.iter().find(|(old, _)| *old == spot)
+                        {
+                            create_ped.goal = new_goal.clone();
+                            req.end = new_goal.sidewalk_pos;
+                            goal_changed = true;
+                        }
+                    }
+                    // The path for a deferred spot isn't calculated until spawning.
+                    if let SidewalkPOI::DeferredParkingSpot(_, _) = create_ped.goal.connection {
+                        continue;
+                    }
+                    if !goal_changed
+                        && create_ped
+                            .path
+                            .is_valid_after_current_step(PathConstraints::Pedestrian, map)
+                    {
+                        continue;
+                    }
+                    if let Some(path) = map.pathfind(req.clone()) {
+                        create_ped.path = path;
+                    } else {
+                        failed_spawns.push((Command::UpdatePed(create_ped.id), create_ped.trip));
+                    }
+                }
+                _ => {}
+            }
+        }
+        for (cmd, trip) in failed_spawns {
+            // Cancelling only looks at the agent ID, not the specific command.
+            self.scheduler.cancel(cmd);
+            self.trips.abort_trip_failed_start(trip);
+            num_deleted += 1;
+        }
+
+        timer.note(format!(
+            "After live map edits, moved {} parked cars and deleted {} agents",
+            moved_cars.len(),
+            num_deleted
+        ));
+    }
 }
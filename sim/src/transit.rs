@@ -1,27 +1,106 @@
-use crate::{CarID, Event, PedestrianID, Router, Scheduler, TripManager, WalkingSimState};
-use abstutil::{deserialize_btreemap, serialize_btreemap};
-use geom::{Distance, Time};
-use map_model::{
-    BusRoute, BusRouteID, BusStopID, Map, Path, PathConstraints, PathRequest, Position,
+use crate::{
+    AgentID, CarID, Event, PedestrianID, Router, Scheduler, TripManager, VehicleType,
+    WalkingSimState, BUS_SEATED_CAPACITY, BUS_STANDING_CAPACITY, TRAIN_SEATED_CAPACITY,
+    TRAIN_STANDING_CAPACITY,
 };
+use abstutil::{deserialize_btreemap, serialize_btreemap};
+use geom::{Distance, Duration, Time};
+use map_model::{BusRoute, BusRouteID, BusStopID, Map, Path, PathRequest, Position};
 use serde_derive::{Deserialize, Serialize};
 use std::collections::BTreeMap;
 
 // These index stops along a route, not stops along a single sidewalk.
 type StopIdx = usize;
 
+// Opening and closing the doors, even if nobody gets on or off
+const MIN_DWELL_TIME: Duration = Duration::const_seconds(10.0);
+// Per passenger. People board at the front and alight at the back at the same time.
+const TIME_TO_BOARD: Duration = Duration::const_seconds(3.0);
+const TIME_TO_ALIGHT: Duration = Duration::const_seconds(2.0);
+// Once the seats are full, people have to squeeze past everybody standing in the aisle.
+const CROWDED_BOARDING_SLOWDOWN: f64 = 1.5;
+// Timetabled buses appear this long before their run starts, so they're waiting at the first stop.
+const DISPATCH_EARLY: Duration = Duration::const_seconds(60.0);
+// When holding is turned on for every route, buses only hold at every this-many stops.
+// TODO Use the timepoint column from GTFS instead.
+const TIME_POINT_SPACING: usize = 4;
+
+// How buses wait at time-point stops to keep from bunching up
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub enum HoldingStrategy {
+    // Don't leave until at least this long after the previous bus on the route left the stop.
+    Headway(Duration),
+    // Don't leave before the timetable says to. Only affects routes with a timetable.
+    Schedule,
+}
+
+impl HoldingStrategy {
+    // "schedule" or a target headway like "10:00"
+    pub fn parse(x: &str) -> Result<HoldingStrategy, abstutil::Error> {
+        if x == "schedule" {
+            Ok(HoldingStrategy::Schedule)
+        } else {
+            Ok(HoldingStrategy::Headway(Duration::parse(x)?))
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, PartialEq)]
 struct StopForRoute {
     id: BusStopID,
     driving_pos: Position,
     path_to_next_stop: Path,
     next_stop_idx: StopIdx,
+    // Buses might hold here
+    time_point: bool,
+    last_departure: Option<Time>,
 }
 
 #[derive(Serialize, Deserialize, PartialEq)]
 struct Route {
     stops: Vec<StopForRoute>,
     buses: Vec<CarID>,
+    schedule: Option<Schedule>,
+    holding: Option<HoldingStrategy>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq)]
+struct Schedule {
+    // When each run should leave its first stop, and which stop that is. Sorted by time.
+    runs: Vec<(Time, StopIdx)>,
+    // Parallel to the route's stops
+    stop_offsets: Vec<Duration>,
+    // Where runs start. Each run ends where the next direction starts.
+    first_stops: Vec<StopIdx>,
+    // The next run to dispatch
+    next_dispatch: usize,
+    // Runs that already have a bus. It might be waiting at the first stop.
+    claimed: Vec<bool>,
+}
+
+impl Schedule {
+    // When a bus on this run should reach the stop
+    fn expected_at(&self, run: usize, stop: StopIdx) -> Time {
+        let (depart, first) = self.runs[run];
+        depart + (self.stop_offsets[stop] - self.stop_offsets[first])
+    }
+
+    fn dispatch_time(&self, run: usize, now: Time) -> Option<Time> {
+        let (depart, _) = *self.runs.get(run)?;
+        if depart - Time::START_OF_DAY > DISPATCH_EARLY {
+            Some((depart - DISPATCH_EARLY).max(now))
+        } else {
+            Some(now)
+        }
+    }
+
+    // Claim the next run starting at this stop that doesn't have a bus yet.
+    fn claim_next(&mut self, stop: StopIdx) -> Option<usize> {
+        let run = (self.next_dispatch..self.runs.len())
+            .find(|idx| !self.claimed[*idx] && self.runs[*idx].1 == stop)?;
+        self.claimed[run] = true;
+        Some(run)
+    }
 }
 
 #[derive(Serialize, Deserialize, PartialEq)]
@@ -30,7 +109,23 @@ struct Bus {
     route: BusRouteID,
     // Where does each passenger want to deboard?
     passengers: Vec<(PedestrianID, BusStopID)>,
+    seated_capacity: usize,
+    standing_capacity: usize,
     state: BusState,
+    // Index into the route's timetable
+    run: Option<usize>,
+    // False while the bus is still heading to the first stop of its run
+    run_started: bool,
+}
+
+impl Bus {
+    fn is_full(&self) -> bool {
+        self.passengers.len() >= self.seated_capacity + self.standing_capacity
+    }
+
+    fn seats_full(&self) -> bool {
+        self.passengers.len() >= self.seated_capacity
+    }
 }
 
 #[derive(Serialize, Deserialize, PartialEq)]
@@ -54,16 +149,19 @@ pub struct TransitSimState {
     routes: BTreeMap<BusRouteID, Route>,
     // Can organize this more to make querying cheaper
     peds_waiting: Vec<(PedestrianID, BusStopID, BusRouteID, BusStopID)>,
+    // Used for every new route
+    default_holding: Option<HoldingStrategy>,
 
     events: Vec<Event>,
 }
 
 impl TransitSimState {
-    pub fn new() -> TransitSimState {
+    pub fn new(default_holding: Option<HoldingStrategy>) -> TransitSimState {
         TransitSimState {
             buses: BTreeMap::new(),
             routes: BTreeMap::new(),
             peds_waiting: Vec::new(),
+            default_holding,
             events: Vec::new(),
         }
     }
@@ -77,8 +175,31 @@ impl TransitSimState {
     ) -> Vec<(StopIdx, Path, Distance)> {
         assert!(bus_route.stops.len() > 1);
 
+        let schedule = if bus_route.departures.is_empty() {
+            None
+        } else {
+            let mut runs: Vec<(Time, StopIdx)> =
+                bus_route.departures.iter().map(|t| (*t, 0)).collect();
+            let mut first_stops = vec![0];
+            if let Some(idx) = bus_route.return_stop {
+                runs.extend(bus_route.return_departures.iter().map(|t| (*t, idx)));
+                if idx != 0 {
+                    first_stops.push(idx);
+                }
+            }
+            runs.sort();
+            Some(Schedule {
+                claimed: vec![false; runs.len()],
+                runs,
+                stop_offsets: bus_route.stop_offsets.clone(),
+                first_stops,
+                next_dispatch: 0,
+            })
+        };
         let route = Route {
             buses: Vec::new(),
+            schedule,
+            holding: self.default_holding.clone(),
             stops: bus_route
                 .stops
                 .iter()
@@ -94,7 +215,7 @@ impl TransitSimState {
                         .pathfind(PathRequest {
                             start: stop1.driving_pos,
                             end: map.get_bs(bus_route.stops[stop2_idx]).driving_pos,
-                            constraints: PathConstraints::Bus,
+                            constraints: bus_route.route_type,
                         })
                         .expect(&format!(
                             "No route between bus stops {:?} and {:?}",
@@ -105,6 +226,8 @@ impl TransitSimState {
                         driving_pos: stop1.driving_pos,
                         path_to_next_stop: path,
                         next_stop_idx: stop2_idx,
+                        time_point: idx % TIME_POINT_SPACING == 0,
+                        last_departure: None,
                     }
                 })
                 .collect(),
@@ -125,19 +248,108 @@ impl TransitSimState {
         stops
     }
 
-    pub fn bus_created(&mut self, bus: CarID, route: BusRouteID, next_stop_idx: StopIdx) {
+    // Only the time points listed will hold buses. None turns off holding for the route. False if
+    // the route isn't running.
+    pub fn set_holding(
+        &mut self,
+        route: BusRouteID,
+        time_points: Vec<BusStopID>,
+        holding: Option<HoldingStrategy>,
+    ) -> bool {
+        let r = match self.routes.get_mut(&route) {
+            Some(r) => r,
+            None => {
+                return false;
+            }
+        };
+        r.holding = holding;
+        for stop in r.stops.iter_mut() {
+            stop.time_point = time_points.contains(&stop.id);
+        }
+        true
+    }
+
+    // Skip runs that already left. Returns the time of the first dispatch, if the route has a
+    // timetable.
+    pub fn start_timetable(&mut self, route: BusRouteID, now: Time) -> Option<Time> {
+        let schedule = self.routes.get_mut(&route).unwrap().schedule.as_mut()?;
+        let idx = schedule
+            .runs
+            .iter()
+            .position(|(t, _)| *t >= now)
+            .unwrap_or_else(|| schedule.runs.len());
+        schedule.next_dispatch = idx;
+        schedule.dispatch_time(idx, now)
+    }
+
+    // Called a little before each run starts. If no bus is already waiting to take this run,
+    // returns (first stop, path to it, end distance for it, run, departure) for a new bus, which
+    // should wait at the first stop until it's time to leave. Also returns when to dispatch next.
+    pub fn dispatch_bus(
+        &mut self,
+        now: Time,
+        route: BusRouteID,
+    ) -> (Option<(StopIdx, Path, Distance, usize, Time)>, Option<Time>) {
+        let r = self.routes.get_mut(&route).unwrap();
+        let schedule = r.schedule.as_mut().unwrap();
+        let run = schedule.next_dispatch;
+        schedule.next_dispatch += 1;
+        let next = schedule.dispatch_time(schedule.next_dispatch, now);
+
+        if schedule.claimed[run] {
+            return (None, next);
+        }
+        schedule.claimed[run] = true;
+        let (departure, first_stop) = schedule.runs[run];
+        // The path from the previous stop ends at the first one.
+        let prev = r
+            .stops
+            .iter()
+            .find(|s| s.next_stop_idx == first_stop)
+            .unwrap();
+        (
+            Some((
+                first_stop,
+                prev.path_to_next_stop.clone(),
+                r.stops[first_stop].driving_pos.dist_along(),
+                run,
+                departure,
+            )),
+            next,
+        )
+    }
+
+    pub fn bus_created(
+        &mut self,
+        bus: CarID,
+        route: BusRouteID,
+        next_stop_idx: StopIdx,
+        run: Option<usize>,
+    ) {
         self.routes.get_mut(&route).unwrap().buses.push(bus);
+        let (seated_capacity, standing_capacity) = if bus.1 == VehicleType::Train {
+            (TRAIN_SEATED_CAPACITY, TRAIN_STANDING_CAPACITY)
+        } else {
+            (BUS_SEATED_CAPACITY, BUS_STANDING_CAPACITY)
+        };
         self.buses.insert(
             bus,
             Bus {
                 car: bus,
                 route,
                 passengers: Vec::new(),
+                seated_capacity,
+                standing_capacity,
                 state: BusState::DrivingToStop(next_stop_idx),
+                run,
+                run_started: false,
             },
         );
     }
 
+    // Returns when the bus should leave the stop. That depends on how many people get on and off,
+    // and maybe waiting for the next scheduled departure. None means the bus finished its last run
+    // and is out of service.
     pub fn bus_arrived_at_stop(
         &mut self,
         now: Time,
@@ -146,19 +358,73 @@ impl TransitSimState {
         walking: &mut WalkingSimState,
         scheduler: &mut Scheduler,
         map: &Map,
-    ) {
+    ) -> Option<Time> {
         let mut bus = self.buses.get_mut(&id).unwrap();
         match bus.state {
             BusState::DrivingToStop(stop_idx) => {
                 bus.state = BusState::AtStop(stop_idx);
-                let stop = self.routes[&bus.route].stops[stop_idx].id;
+                let route = self.routes.get_mut(&bus.route).unwrap();
+                let stop = route.stops[stop_idx].id;
                 self.events
                     .push(Event::BusArrivedAtStop(id, bus.route, stop));
 
+                let mut layover_until = None;
+                let mut retire = false;
+                if let Some(ref mut schedule) = route.schedule {
+                    match bus.run {
+                        Some(run) if !bus.run_started => {
+                            // Just dispatched to the first stop; wait there until it's time to go.
+                            bus.run_started = true;
+                            layover_until = Some(schedule.runs[run].0);
+                        }
+                        _ if schedule.first_stops.contains(&stop_idx) => {
+                            // Finished a run. Take the next one starting here that nobody has
+                            // claimed, or go out of service after the last one.
+                            bus.run = schedule.claim_next(stop_idx);
+                            match bus.run {
+                                Some(run) => {
+                                    layover_until = Some(schedule.runs[run].0);
+                                }
+                                None => {
+                                    retire = true;
+                                }
+                            }
+                        }
+                        Some(run) => {
+                            self.events.push(Event::BusArrivalVsTimetable(
+                                id,
+                                bus.route,
+                                stop,
+                                schedule.expected_at(run, stop_idx),
+                            ));
+                        }
+                        None => {}
+                    }
+                }
+
+                // Should the bus wait here to even out the gaps?
+                let mut hold_until = None;
+                if route.stops[stop_idx].time_point {
+                    match route.holding {
+                        Some(HoldingStrategy::Headway(headway)) => {
+                            hold_until = route.stops[stop_idx].last_departure.map(|t| t + headway);
+                        }
+                        Some(HoldingStrategy::Schedule) => {
+                            if let (Some(run), Some(schedule)) = (bus.run, &route.schedule) {
+                                hold_until = Some(schedule.expected_at(run, stop_idx));
+                            }
+                        }
+                        None => {}
+                    }
+                }
+
                 // Deboard existing passengers.
+                let crowded_on_arrival = bus.seats_full();
                 let mut still_riding = Vec::new();
+                let mut num_alighted = 0;
                 for (ped, stop2) in bus.passengers.drain(..) {
                     if stop == stop2 {
+                        num_alighted += 1;
                         self.events.push(Event::PedLeavesBus(ped, id, bus.route));
                         trips.ped_left_bus(now, ped, map, scheduler);
                     } else {
@@ -167,10 +433,25 @@ impl TransitSimState {
                 }
                 bus.passengers = still_riding;
 
-                // Board new passengers.
+                if retire {
+                    self.bus_retired(now, id, trips, walking);
+                    return None;
+                }
+
+                // Board new passengers, as long as there's room. The rest wait for the next bus.
                 let mut still_waiting = Vec::new();
+                let mut board = Duration::ZERO;
                 for (ped, stop1, route, stop2) in self.peds_waiting.drain(..) {
-                    if stop == stop1 && bus.route == route {
+                    if stop == stop1 && bus.route == route && bus.is_full() {
+                        self.events
+                            .push(Event::PedPassedUpByBus(ped, id, route, stop));
+                        still_waiting.push((ped, stop1, route, stop2));
+                    } else if stop == stop1 && bus.route == route {
+                        board += if bus.seats_full() {
+                            TIME_TO_BOARD * CROWDED_BOARDING_SLOWDOWN
+                        } else {
+                            TIME_TO_BOARD
+                        };
                         bus.passengers.push((ped, stop2));
                         self.events.push(Event::PedEntersBus(ped, id, route));
                         let trip = trips.ped_boarded_bus(ped, walking);
@@ -179,7 +460,7 @@ impl TransitSimState {
                             Some(PathRequest {
                                 start: map.get_bs(stop1).driving_pos,
                                 end: map.get_bs(stop2).driving_pos,
-                                constraints: PathConstraints::Bus,
+                                constraints: map.get_br(route).route_type,
                             }),
                             format!("{} riding {}", ped, route),
                         ));
@@ -188,22 +469,70 @@ impl TransitSimState {
                     }
                 }
                 self.peds_waiting = still_waiting;
+
+                let mut alight = TIME_TO_ALIGHT * (num_alighted as f64);
+                if crowded_on_arrival {
+                    alight = alight * CROWDED_BOARDING_SLOWDOWN;
+                }
+                let mut depart = now + MIN_DWELL_TIME + if board > alight { board } else { alight };
+                for t in layover_until.into_iter().chain(hold_until) {
+                    depart = depart.max(t);
+                }
+                Some(depart)
             }
             BusState::AtStop(_) => unreachable!(),
-        };
+        }
     }
 
-    pub fn bus_departed_from_stop(&mut self, id: CarID) -> Router {
+    // Anybody still riding is stranded. Once the route's last bus is done, so is everybody
+    // waiting for it.
+    fn bus_retired(
+        &mut self,
+        now: Time,
+        id: CarID,
+        trips: &mut TripManager,
+        walking: &mut WalkingSimState,
+    ) {
+        trips.bus_retired(now, id);
+        let (riding, waiting) = self.bus_deleted(id);
+        for ped in riding {
+            trips.abort_active_trip(AgentID::Pedestrian(ped));
+        }
+        for ped in waiting {
+            walking.delete_ped_waiting_for_bus(ped);
+            trips.abort_active_trip(AgentID::Pedestrian(ped));
+        }
+    }
+
+    // No more buses will come.
+    pub fn route_finished(&self, route: BusRouteID) -> bool {
+        let r = &self.routes[&route];
+        match r.schedule {
+            Some(ref schedule) => {
+                r.buses.is_empty() && schedule.next_dispatch == schedule.runs.len()
+            }
+            None => false,
+        }
+    }
+
+    pub fn bus_departed_from_stop(&mut self, now: Time, id: CarID) -> Router {
         let mut bus = self.buses.get_mut(&id).unwrap();
         match bus.state {
             BusState::DrivingToStop(_) => unreachable!(),
             BusState::AtStop(stop_idx) => {
+                self.routes.get_mut(&bus.route).unwrap().stops[stop_idx].last_departure = Some(now);
                 let route = &self.routes[&bus.route];
                 let stop = &route.stops[stop_idx];
 
                 bus.state = BusState::DrivingToStop(stop.next_stop_idx);
                 self.events
                     .push(Event::BusDepartedFromStop(id, bus.route, stop.id));
+                self.events.push(Event::BusLoadLeavingStop(
+                    id,
+                    bus.route,
+                    stop.id,
+                    bus.passengers.len(),
+                ));
                 Router::follow_bus_route(
                     stop.path_to_next_stop.clone(),
                     route.stops[stop.next_stop_idx].driving_pos.dist_along(),
@@ -225,6 +554,11 @@ impl TransitSimState {
         for bus in &route.buses {
             if let BusState::AtStop(idx) = self.buses[bus].state {
                 if route.stops[idx].id == stop1 {
+                    if self.buses[bus].is_full() {
+                        self.events
+                            .push(Event::PedPassedUpByBus(ped, *bus, route_id, stop1));
+                        continue;
+                    }
                     self.buses
                         .get_mut(bus)
                         .unwrap()
@@ -241,6 +575,32 @@ impl TransitSimState {
         false
     }
 
+    // Live map edits deleted a bus. Returns the passengers stranded on board, and if nothing's
+    // left to serve the route, everybody waiting for it.
+    pub fn bus_deleted(&mut self, id: CarID) -> (Vec<PedestrianID>, Vec<PedestrianID>) {
+        let bus = self.buses.remove(&id).unwrap();
+        let riding = bus.passengers.into_iter().map(|(ped, _)| ped).collect();
+
+        let route = self.routes.get_mut(&bus.route).unwrap();
+        route.buses.retain(|b| *b != id);
+        let mut waiting = Vec::new();
+        let finished = match route.schedule {
+            Some(ref schedule) => schedule.next_dispatch == schedule.runs.len(),
+            None => true,
+        };
+        if route.buses.is_empty() && finished {
+            self.peds_waiting.retain(|(ped, _, r, _)| {
+                if *r == bus.route {
+                    waiting.push(*ped);
+                    false
+                } else {
+                    true
+                }
+            });
+        }
+        (riding, waiting)
+    }
+
     pub fn collect_events(&mut self) -> Vec<Event> {
         self.events.drain(..).collect()
     }
@@ -249,6 +609,16 @@ impl TransitSimState {
         &self.buses[&bus].passengers
     }
 
+    // (seated, standing)
+    pub fn get_capacity(&self, bus: CarID) -> (usize, usize) {
+        let bus = &self.buses[&bus];
+        (bus.seated_capacity, bus.standing_capacity)
+    }
+
+    pub fn serves_route(&self, car: CarID) -> bool {
+        self.buses.contains_key(&car)
+    }
+
     pub fn bus_route(&self, bus: CarID) -> BusRouteID {
         self.buses[&bus].route
     }
@@ -0,0 +1,330 @@
+use crate::TripID;
+use abstutil::{deserialize_btreemap, serialize_btreemap, Timer};
+use derivative::Derivative;
+use geom::{Duration, Time};
+use map_model::{
+    Lane, Map, Path, PathConstraints, PathRequest, Traversable, Turn, VehiclePathfinder,
+};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+
+// How long vehicles actually took to cross each lane and turn, grouped into buckets by the time
+// they entered. Collected by Analytics during one run, then optionally used to route vehicles in
+// another. Multi-day runs wrap around to the same buckets, so this doesn't grow without limit.
+#[derive(Clone, Serialize, Deserialize, Derivative)]
+#[derivative(PartialEq, Debug)]
+pub struct TravelTimes {
+    bucket_size: Duration,
+    // Total time spent crossing and number of vehicles
+    #[serde(
+        serialize_with = "serialize_btreemap",
+        deserialize_with = "deserialize_btreemap"
+    )]
+    observations: BTreeMap<(Traversable, usize), (Duration, usize)>,
+
+    // Graphs weighted by the observations in one bucket. Expensive to build, so they're only made
+    // for buckets that somebody departs in, and then kept.
+    #[serde(skip_serializing, skip_deserializing)]
+    #[derivative(PartialEq = "ignore", Debug = "ignore")]
+    graphs: BTreeMap<(PathConstraints, usize), Arc<VehiclePathfinder>>,
+}
+
+impl TravelTimes {
+    pub fn new(bucket_size: Duration) -> TravelTimes {
+        TravelTimes {
+            bucket_size,
+            observations: BTreeMap::new(),
+            graphs: BTreeMap::new(),
+        }
+    }
+
+    pub fn record(&mut self, on: Traversable, entered: Time, dt: Duration) {
+        let entry = self
+            .observations
+            .entry((on, self.bucket(entered)))
+            .or_insert((Duration::ZERO, 0));
+        entry.0 += dt;
+        entry.1 += 1;
+    }
+
+    pub fn average(&self, on: Traversable, time: Time) -> Option<Duration> {
+        self.observations
+            .get(&(on, self.bucket(time)))
+            .map(|(total, cnt)| *total / (*cnt as f64))
+    }
+
+    // Build the graph that pathfind uses for vehicles departing at this time, unless it already
+    // exists. Without this, pathfind falls back to something much slower.
+    pub fn prepare(&mut self, constraints: PathConstraints, depart: Time, map: &Map) {
+        let bucket = self.bucket(depart);
+        if self.graphs.contains_key(&(constraints, bucket)) {
+            return;
+        }
+        // Everything in the same bucket
+        let graph = map.vehicle_pathfinder_with_costs(constraints, &|lane, turn, _| {
+            let t = self.expected(Traversable::Lane(lane.id), depart, map)
+                + self.expected(Traversable::Turn(turn.id), depart, map);
+            t.inner_seconds().round() as usize
+        });
+        self.graphs.insert((constraints, bucket), Arc::new(graph));
+    }
+
+    // Find the fastest path, using how long vehicles took to cross each lane and turn around the
+    // time they'd reach it. Costs are in seconds, the same as path_cost.
+    pub fn pathfind(&self, req: PathRequest, depart: Time, map: &Map) -> Option<Path> {
+        // The graph only knows about the departure bucket. If the best path it finds is over
+        // before that bucket ends, then so is anything faster, so nothing else could've been
+        // better.
+        if let Some(graph) = self.graphs.get(&(req.constraints, self.bucket(depart))) {
+            let path = graph.pathfind(&req, map)?;
+            let arrive = depart + self.path_cost(&path, depart, map);
+            if arrive - depart < self.bucket_size && self.bucket(arrive) == self.bucket(depart) {
+                return Some(path);
+            }
+        }
+        map.pathfind_with_dynamic_costs(req, |lane, turn, _, so_far| {
+            Some(self.step_cost(lane, turn, depart + Duration::seconds(so_far as f64), map))
+        })
+    }
+
+    // Like pathfind for lots of requests at once, building one graph per bucket instead of
+    // falling back to the slow way for each request.
+    pub fn pathfind_many(
+        &mut self,
+        requests: Vec<(Time, PathRequest)>,
+        map: &Map,
+        timer: &mut Timer,
+    ) -> Vec<Option<Path>> {
+        let mut batches = BTreeMap::new();
+        for (idx, (depart, req)) in requests.into_iter().enumerate() {
+            batches
+                .entry(self.bucket(depart))
+                .or_insert_with(Vec::new)
+                .push((idx, depart, req));
+        }
+        let mut results = Vec::new();
+        for (_, batch) in batches {
+            let constraints: BTreeSet<PathConstraints> = batch
+                .iter()
+                .map(|(_, _, req)| req.constraints)
+                .filter(|c| *c != PathConstraints::Pedestrian)
+                .collect();
+            for c in constraints {
+                self.prepare(c, batch[0].1, map);
+            }
+            let tt = &*self;
+            results.extend(
+                timer.parallelize("calculate paths", batch, |(idx, depart, req)| {
+                    (idx, pathfind_with_travel_times(Some(tt), req, depart, map))
+                }),
+            );
+        }
+        results.sort_by_key(|(idx, _)| *idx);
+        results.into_iter().map(|(_, path)| path).collect()
+    }
+
+    // Reaching the lane at some time, how long until the end of the turn after it?
+    fn step_cost(&self, lane: &Lane, turn: &Turn, time: Time, map: &Map) -> usize {
+        let lane_time = self.expected(Traversable::Lane(lane.id), time, map);
+        let t = lane_time + self.expected(Traversable::Turn(turn.id), time + lane_time, map);
+        t.inner_seconds().round() as usize
+    }
+
+    // Observed if possible, free-flow otherwise
+    fn expected(&self, on: Traversable, time: Time, map: &Map) -> Duration {
+        self.average(on, time)
+            .unwrap_or_else(|| on.length(map) / on.speed_limit(map))
+    }
+
+    // Mix in another set of observations, giving them this weight. Used to average travel times
+    // over many runs (the method of successive averages).
+    pub fn blend(&mut self, other: &TravelTimes, weight: f64) {
+        assert_eq!(self.bucket_size, other.bucket_size);
+        for (key, (total, cnt)) in &other.observations {
+            let new = *total / (*cnt as f64);
+            let avg = if let Some((old_total, old_cnt)) = self.observations.get(key) {
+                *old_total / (*old_cnt as f64) * (1.0 - weight) + new * weight
+            } else {
+                new
+            };
+            self.observations.insert(*key, (avg, 1));
+        }
+    }
+
+    // How long the path should take, using observations around the time each step is reached when
+    // possible and free-flow speed otherwise.
+    pub fn path_cost(&self, path: &Path, depart: Time, map: &Map) -> Duration {
+        let mut total = Duration::ZERO;
+        for step in path.get_steps() {
+            total += self.expected(step.as_traversable(), depart + total, map);
+        }
+        total
+    }
+
+    pub(crate) fn bucket(&self, time: Time) -> usize {
+        let per_day = (Duration::hours(24) / self.bucket_size).ceil() as usize;
+        (((time - Time::START_OF_DAY) / self.bucket_size).floor() as usize) % per_day
+    }
+}
+
+// The path each trip's vehicle started with, along with the request and departure time that
+// produced it. Saved from one run to force the same routes in another.
+#[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
+pub struct TripPaths {
+    #[serde(
+        serialize_with = "serialize_btreemap",
+        deserialize_with = "deserialize_btreemap"
+    )]
+    paths: BTreeMap<TripID, (Time, PathRequest, Path)>,
+}
+
+impl TripPaths {
+    pub fn new() -> TripPaths {
+        TripPaths {
+            paths: BTreeMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, trip: TripID, depart: Time, req: PathRequest, path: Path) {
+        self.paths.insert(trip, (depart, req, path));
+    }
+
+    // Only if the trip is asking for exactly the same thing as before.
+    pub fn get(&self, trip: TripID, req: &PathRequest) -> Option<&Path> {
+        match self.paths.get(&trip) {
+            Some((_, ref old_req, ref path)) if old_req == req => Some(path),
+            _ => None,
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&TripID, &(Time, PathRequest, Path))> {
+        self.paths.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.paths.len()
+    }
+
+    // For traffic assignment, before the next iteration, 1 / (iteration + 1) of the trips are
+    // chosen to reroute; the rest keep their previous path. Deterministic, so runs are
+    // reproducible.
+    pub fn keep_for_iteration(&self, iteration: usize) -> TripPaths {
+        let mut keep = TripPaths::new();
+        for (trip, (depart, req, path)) in &self.paths {
+            if (trip.0 + iteration) % (iteration + 1) != 0 {
+                keep.insert(*trip, *depart, req.clone(), path.clone());
+            }
+        }
+        keep
+    }
+
+    // How much longer the chosen paths take than the best paths, given the current costs. 0 means
+    // nobody could do better by switching.
+    pub fn relative_gap(&self, costs: &mut TravelTimes, map: &Map, timer: &mut Timer) -> f64 {
+        let requests = self
+            .paths
+            .values()
+            .map(|(depart, req, _)| (*depart, req.clone()))
+            .collect();
+        let alts = costs.pathfind_many(requests, map, timer);
+
+        let mut chosen = Duration::ZERO;
+        let mut best = Duration::ZERO;
+        for ((depart, _, path), alt) in self.paths.values().zip(alts) {
+            let current = costs.path_cost(path, *depart, map);
+            chosen += current;
+            let alt = alt
+                .map(|p| costs.path_cost(&p, *depart, map))
+                .unwrap_or(current);
+            best += if alt < current { alt } else { current };
+        }
+        if chosen == Duration::ZERO {
+            return 0.0;
+        }
+        (chosen - best) / chosen
+    }
+}
+
+// How each trip's vehicle picks a path.
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub(crate) struct VehicleRouting {
+    // If set, vehicles are routed using these instead of free-flow costs.
+    pub travel_times: Option<TravelTimes>,
+    // These trips skip pathfinding and use a path decided ahead of time.
+    pub fixed_paths: TripPaths,
+    // If set, remember the path every trip started with.
+    pub recorded_paths: Option<TripPaths>,
+}
+
+impl VehicleRouting {
+    pub fn new() -> VehicleRouting {
+        VehicleRouting {
+            travel_times: None,
+            fixed_paths: TripPaths::new(),
+            recorded_paths: None,
+        }
+    }
+
+    pub fn pathfind(
+        &mut self,
+        trip: TripID,
+        req: &PathRequest,
+        depart: Time,
+        map: &Map,
+    ) -> Option<Path> {
+        let path = if let Some(p) = self.fixed_paths.get(trip, req) {
+            Some(p.clone())
+        } else {
+            if let Some(ref mut tt) = self.travel_times {
+                if req.constraints != PathConstraints::Pedestrian {
+                    tt.prepare(req.constraints, depart, map);
+                }
+            }
+            pathfind_with_travel_times(self.travel_times.as_ref(), req.clone(), depart, map)
+        };
+        self.record(trip, req, depart, path)
+    }
+
+    // For paths calculated before the trip existed.
+    pub fn precalculated(
+        &mut self,
+        trip: TripID,
+        req: &PathRequest,
+        depart: Time,
+        path: Option<Path>,
+    ) -> Option<Path> {
+        let path = if let Some(p) = self.fixed_paths.get(trip, req) {
+            Some(p.clone())
+        } else {
+            path
+        };
+        self.record(trip, req, depart, path)
+    }
+
+    fn record(
+        &mut self,
+        trip: TripID,
+        req: &PathRequest,
+        depart: Time,
+        path: Option<Path>,
+    ) -> Option<Path> {
+        if let (Some(recorded), Some(p)) = (&mut self.recorded_paths, &path) {
+            recorded.insert(trip, depart, req.clone(), p.clone());
+        }
+        path
+    }
+}
+
+// Vehicles use observed travel times when they're available.
+pub(crate) fn pathfind_with_travel_times(
+    travel_times: Option<&TravelTimes>,
+    req: PathRequest,
+    depart: Time,
+    map: &Map,
+) -> Option<Path> {
+    match travel_times {
+        Some(tt) if req.constraints != PathConstraints::Pedestrian => tt.pathfind(req, depart, map),
+        _ => map.pathfind(req),
+    }
+}
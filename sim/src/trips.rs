@@ -1,12 +1,15 @@
+use crate::travel_times::VehicleRouting;
 use crate::{
     AgentID, CarID, Command, CreateCar, CreatePedestrian, DrivingGoal, Event, ParkingSimState,
-    ParkingSpot, PedestrianID, Scheduler, SidewalkPOI, SidewalkSpot, TransitSimState, TripID,
-    Vehicle, VehicleType, WalkingSimState,
+    ParkingSpot, PedestrianID, PersonID, Router, Scheduler, SidewalkPOI, SidewalkSpot,
+    TransitSimState, TravelTimes, TripID, TripPaths, TripSpec, Vehicle, VehicleType,
+    WalkingSimState,
 };
 use abstutil::{deserialize_btreemap, serialize_btreemap, Counter};
-use geom::{Speed, Time};
+use geom::{Duration, Speed, Time};
 use map_model::{
-    BuildingID, BusRouteID, BusStopID, IntersectionID, Map, PathConstraints, PathRequest, Position,
+    BuildingID, BusRouteID, BusStopID, IntersectionID, Map, Path, PathConstraints, PathRequest,
+    Position,
 };
 use serde_derive::{Deserialize, Serialize};
 use std::collections::{BTreeMap, VecDeque};
@@ -22,6 +25,12 @@ pub struct TripManager {
     active_trip_mode: BTreeMap<AgentID, TripID>,
     num_bus_trips: usize,
     unfinished_trips: usize,
+    routing: VehicleRouting,
+    #[serde(
+        serialize_with = "serialize_btreemap",
+        deserialize_with = "deserialize_btreemap"
+    )]
+    people: BTreeMap<PersonID, Person>,
 
     events: Vec<Event>,
 }
@@ -33,11 +42,113 @@ impl TripManager {
             active_trip_mode: BTreeMap::new(),
             num_bus_trips: 0,
             unfinished_trips: 0,
+            routing: VehicleRouting::new(),
+            people: BTreeMap::new(),
             events: Vec::new(),
         }
     }
 
-    pub fn new_trip(&mut self, spawned_at: Time, start: TripStart, legs: Vec<TripLeg>) -> TripID {
+    pub fn use_travel_times(&mut self, travel_times: TravelTimes) {
+        self.routing.travel_times = Some(travel_times);
+    }
+
+    pub fn get_travel_times(&self) -> Option<&TravelTimes> {
+        self.routing.travel_times.as_ref()
+    }
+
+    pub(crate) fn get_travel_times_mut(&mut self) -> Option<&mut TravelTimes> {
+        self.routing.travel_times.as_mut()
+    }
+
+    pub fn use_fixed_paths(&mut self, paths: TripPaths) {
+        self.routing.fixed_paths = paths;
+    }
+
+    pub fn record_paths(&mut self) {
+        self.routing.recorded_paths = Some(TripPaths::new());
+    }
+
+    pub fn get_recorded_paths(&self) -> Option<&TripPaths> {
+        self.routing.recorded_paths.as_ref()
+    }
+
+    // For vehicle paths calculated before the trip was created
+    pub fn vehicle_path_for_new_trip(
+        &mut self,
+        trip: TripID,
+        req: &PathRequest,
+        depart: Time,
+        path: Option<Path>,
+    ) -> Option<Path> {
+        self.routing.precalculated(trip, req, depart, path)
+    }
+
+    pub fn new_person(&mut self, id: PersonID, trips: Vec<(Time, Duration, TripSpec)>) {
+        assert!(!self.people.contains_key(&id));
+        self.people.insert(
+            id,
+            Person {
+                remaining_trips: VecDeque::from(trips),
+                car: None,
+            },
+        );
+    }
+
+    // If the person drove somewhere earlier, they'll go back to that car, instead of looking for
+    // one owned by the building they're starting from.
+    pub fn next_trip_for_person(
+        &mut self,
+        id: PersonID,
+        parking: &ParkingSimState,
+        map: &Map,
+    ) -> Option<TripSpec> {
+        let person = self.people.get_mut(&id).unwrap();
+        let (_, _, spec) = person.remaining_trips.pop_front()?;
+        let spot = if let Some(p) = person.car.and_then(|car| parking.lookup_parked_car(car)) {
+            p.spot
+        } else {
+            return Some(spec);
+        };
+        Some(match spec {
+            TripSpec::MaybeUsingParkedCar {
+                start_bldg,
+                goal,
+                ped_speed,
+            } => TripSpec::UsingParkedCar {
+                start: SidewalkSpot::building(start_bldg, map),
+                spot,
+                goal,
+                ped_speed,
+            },
+            TripSpec::UsingParkedCar {
+                start,
+                goal,
+                ped_speed,
+                ..
+            } => TripSpec::UsingParkedCar {
+                start,
+                spot,
+                goal,
+                ped_speed,
+            },
+            x => x,
+        })
+    }
+
+    // When a person's trip is done, returns when their next one should start.
+    pub fn next_departure_for_person(&self, trip: TripID, now: Time) -> Option<(PersonID, Time)> {
+        let id = self.trips[trip.0].person?;
+        let (depart, dwell, _) = self.people[&id].remaining_trips.front()?;
+        Some((id, (*depart).max(now + *dwell)))
+    }
+
+    pub fn new_trip(
+        &mut self,
+        spawned_at: Time,
+        start: TripStart,
+        legs: Vec<TripLeg>,
+        person: Option<PersonID>,
+    ) -> TripID {
         assert!(!legs.is_empty());
         // TODO Make sure the legs constitute a valid state machine.
 
@@ -50,7 +161,7 @@ impl TripManager {
                         mode = TripMode::Drive;
                     }
                 }
-                TripLeg::Drive(_, _) => {
+                TripLeg::Drive(_, _) | TripLeg::Deliver(_, _, _) => {
                     mode = TripMode::Drive;
                 }
                 TripLeg::RideBus(_, _, _) => {
@@ -88,6 +199,7 @@ impl TripManager {
             legs: VecDeque::from(legs),
             start,
             end,
+            person,
         };
         if !trip.is_bus_trip() {
             self.unfinished_trips += 1;
@@ -96,11 +208,14 @@ impl TripManager {
         id
     }
 
+    // This is only for peds using a previously unknown parked car. The first leg (walking to the
+    // deferred parking spot) is replaced; anything after driving is kept.
     pub fn dynamically_override_legs(&mut self, id: TripID, legs: Vec<TripLeg>) {
         let trip = &mut self.trips[id.0];
-        trip.legs = VecDeque::from(legs);
-        // This is only for peds using a previously unknown parked car
-        trip.mode = TripMode::Drive;
+        trip.legs.pop_front();
+        for l in legs.into_iter().rev() {
+            trip.legs.push_front(l);
+        }
     }
 
     pub fn agent_starting_trip_leg(&mut self, agent: AgentID, trip: TripID) {
@@ -127,8 +242,43 @@ impl TripManager {
 
         match trip.legs.pop_front() {
             Some(TripLeg::Drive(vehicle, DrivingGoal::ParkNear(_))) => assert_eq!(car, vehicle.id),
+            Some(TripLeg::Deliver(vehicle, _, dwell)) => {
+                assert_eq!(car, vehicle.id);
+                // Unload at the curb, then head off.
+                let parked_car = parking.get_car_at_spot(spot).unwrap().clone();
+                let start = parking.spot_to_driving_pos(spot, &vehicle, map);
+                if let Some((router, req)) =
+                    trip.route_after_delivery(start, now + dwell, &mut self.routing, map)
+                {
+                    scheduler.push(
+                        now + dwell,
+                        Command::SpawnCar(
+                            CreateCar::for_parked_car(
+                                parked_car,
+                                router,
+                                start.dist_along(),
+                                trip.id,
+                            ),
+                            req,
+                            true,
+                        ),
+                    );
+                } else {
+                    println!(
+                        "Aborting {} at {} because no path after delivering from {:?}",
+                        trip.id, now, spot
+                    );
+                    self.unfinished_trips -= 1;
+                    trip.aborted = true;
+                    self.events.push(Event::TripAborted(trip.id));
+                }
+                return;
+            }
             _ => unreachable!(),
         };
+        if let Some(person) = trip.person.and_then(|p| self.people.get_mut(&p)) {
+            person.car = Some(car);
+        }
 
         match &trip.legs[0] {
             TripLeg::Walk(_, _, to) => match (spot, &to.connection) {
@@ -157,9 +307,41 @@ impl TripManager {
             scheduler,
         ) {
             self.unfinished_trips -= 1;
+            trip.aborted = true;
+            self.events.push(Event::TripAborted(trip.id));
         }
     }
 
+    // The truck couldn't find a loading zone, so it's stopping in the lane. Returns how long to
+    // stay there and where to go afterwards, or None if the trip was aborted.
+    pub fn truck_double_parked(
+        &mut self,
+        now: Time,
+        car: CarID,
+        pos: Position,
+        map: &Map,
+    ) -> Option<(Router, Duration)> {
+        let trip = &mut self.trips[self.active_trip_mode[&AgentID::Car(car)].0];
+        let dwell = match trip.legs.pop_front() {
+            Some(TripLeg::Deliver(vehicle, _, dwell)) => {
+                assert_eq!(car, vehicle.id);
+                dwell
+            }
+            _ => unreachable!(),
+        };
+        if let Some((router, _)) =
+            trip.route_after_delivery(pos, now + dwell, &mut self.routing, map)
+        {
+            return Some((router, dwell));
+        }
+        println!(
+            "Aborting {} at {} because no path after delivering from {}",
+            trip.id, now, pos
+        );
+        self.abort_active_trip(AgentID::Car(car));
+        None
+    }
+
     pub fn ped_reached_parking_spot(
         &mut self,
         now: Time,
@@ -195,7 +377,7 @@ impl TripManager {
             end,
             constraints: PathConstraints::Car,
         };
-        let path = if let Some(p) = map.pathfind(req.clone()) {
+        let path = if let Some(p) = self.routing.pathfind(trip.id, &req, now, map) {
             p
         } else {
             println!(
@@ -249,7 +431,7 @@ impl TripManager {
             end,
             constraints: PathConstraints::Bike,
         };
-        let path = if let Some(p) = map.pathfind(req.clone()) {
+        let path = if let Some(p) = self.routing.pathfind(trip.id, &req, now, map) {
             p
         } else {
             println!(
@@ -294,6 +476,8 @@ impl TripManager {
 
         if !trip.spawn_ped(now, bike_rack, map, scheduler) {
             self.unfinished_trips -= 1;
+            trip.aborted = true;
+            self.events.push(Event::TripAborted(trip.id));
         }
     }
 
@@ -322,7 +506,8 @@ impl TripManager {
         ));
     }
 
-    // If no route is returned, the pedestrian boarded a bus immediately.
+    // If no route is returned, the pedestrian boarded a bus immediately, or no more buses are
+    // coming and the trip was aborted.
     pub fn ped_reached_bus_stop(
         &mut self,
         ped: PedestrianID,
@@ -341,6 +526,10 @@ impl TripManager {
         }
         match trip.legs[1] {
             TripLeg::RideBus(_, route, stop2) => {
+                if transit.route_finished(route) {
+                    self.abort_active_trip(AgentID::Pedestrian(ped));
+                    return None;
+                }
                 self.events.push(Event::TripPhaseStarting(
                     trip.id,
                     None,
@@ -384,6 +573,8 @@ impl TripManager {
 
         if !trip.spawn_ped(now, start, map, scheduler) {
             self.unfinished_trips -= 1;
+            trip.aborted = true;
+            self.events.push(Event::TripAborted(trip.id));
         }
     }
 
@@ -430,6 +621,33 @@ impl TripManager {
         ));
     }
 
+    pub fn emergency_vehicle_arrived(&mut self, now: Time, car: CarID) {
+        let trip = &mut self.trips[self.active_trip_mode.remove(&AgentID::Car(car)).unwrap().0];
+        let b = match trip.legs.pop_front().unwrap() {
+            TripLeg::Drive(_, DrivingGoal::ParkNear(b)) => b,
+            _ => unreachable!(),
+        };
+        assert!(trip.legs.is_empty());
+        assert!(!trip.finished_at.is_some());
+        trip.finished_at = Some(now);
+        self.unfinished_trips -= 1;
+        self.events
+            .push(Event::EmergencyResponse(car, b, now - trip.spawned_at));
+        self.events.push(Event::TripFinished(
+            trip.id,
+            trip.mode,
+            now - trip.spawned_at,
+        ));
+    }
+
+    // Buses don't count as trips, so there's no event.
+    pub fn bus_retired(&mut self, now: Time, bus: CarID) {
+        let trip = &mut self.trips[self.active_trip_mode.remove(&AgentID::Car(bus)).unwrap().0];
+        assert!(trip.is_bus_trip());
+        trip.legs.clear();
+        trip.finished_at = Some(now);
+    }
+
     pub fn abort_trip_failed_start(&mut self, id: TripID) {
         self.trips[id.0].aborted = true;
         if !self.trips[id.0].is_bus_trip() {
@@ -438,6 +656,30 @@ impl TripManager {
         self.events.push(Event::TripAborted(id));
     }
 
+    // The agent was deleted partway through the trip, like when live map edits made its path
+    // impossible.
+    pub fn abort_active_trip(&mut self, agent: AgentID) {
+        let trip = self.active_trip_mode.remove(&agent).unwrap();
+        self.abort_trip_failed_start(trip);
+    }
+
+    // Live map edits moved a parked car, so anybody planning to walk to it needs to go somewhere
+    // else.
+    pub fn parked_car_moved(&mut self, old: ParkingSpot, new: &SidewalkSpot) {
+        for trip in self.trips.iter_mut() {
+            if trip.finished_at.is_some() || trip.aborted {
+                continue;
+            }
+            for leg in trip.legs.iter_mut() {
+                if let TripLeg::Walk(_, _, ref mut spot) = leg {
+                    if spot.connection == SidewalkPOI::ParkingSpot(old) {
+                        *spot = new.clone();
+                    }
+                }
+            }
+        }
+    }
+
     pub fn abort_trip_impossible_parking(&mut self, car: CarID) {
         let trip = self.active_trip_mode.remove(&AgentID::Car(car)).unwrap();
         assert!(!self.trips[trip.0].is_bus_trip());
@@ -466,7 +708,9 @@ impl TripManager {
 
         match &trip.legs[0] {
             TripLeg::Walk(id, _, _) => TripResult::Ok(AgentID::Pedestrian(*id)),
-            TripLeg::Drive(vehicle, _) => TripResult::Ok(AgentID::Car(vehicle.id)),
+            TripLeg::Drive(vehicle, _) | TripLeg::Deliver(vehicle, _, _) => {
+                TripResult::Ok(AgentID::Car(vehicle.id))
+            }
             // TODO Should be the bus, but apparently transit sim tracks differently?
             TripLeg::RideBus(ped, _, _) => TripResult::Ok(AgentID::Pedestrian(*ped)),
             TripLeg::ServeBusRoute(id, _) => TripResult::Ok(AgentID::Car(*id)),
@@ -505,7 +749,7 @@ impl TripManager {
     }
 
     pub fn is_done(&self) -> bool {
-        self.unfinished_trips == 0
+        self.unfinished_trips == 0 && self.people.values().all(|p| p.remaining_trips.is_empty())
     }
 
     pub fn collect_events(&mut self) -> Vec<Event> {
@@ -580,6 +824,15 @@ struct Trip {
     mode: TripMode,
     start: TripStart,
     end: TripEnd,
+    person: Option<PersonID>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+struct Person {
+    // (departure time, dwell time since the previous trip, what to do)
+    remaining_trips: VecDeque<(Time, Duration, TripSpec)>,
+    // The last vehicle this person parked
+    car: Option<CarID>,
 }
 
 impl Trip {
@@ -591,7 +844,9 @@ impl Trip {
             },
             // No need to look up the contents of a SidewalkPOI::ParkingSpot. If a trip uses a
             // specific parked car, then there'll be a TripLeg::Drive with it already.
-            TripLeg::Drive(ref vehicle, _) => vehicle.id == id,
+            TripLeg::Drive(ref vehicle, _) | TripLeg::Deliver(ref vehicle, _, _) => {
+                vehicle.id == id
+            }
             _ => false,
         })
     }
@@ -649,6 +904,33 @@ impl Trip {
         true
     }
 
+    // After a delivery, how to get from where the truck stopped to the next stop or the end.
+    fn route_after_delivery(
+        &self,
+        start: Position,
+        depart: Time,
+        routing: &mut VehicleRouting,
+        map: &Map,
+    ) -> Option<(Router, PathRequest)> {
+        let (vehicle, goal) = match self.legs[0] {
+            TripLeg::Deliver(ref vehicle, b, _) => (vehicle, DrivingGoal::ParkNear(b)),
+            TripLeg::Drive(ref vehicle, ref goal) => (vehicle, goal.clone()),
+            _ => unreachable!(),
+        };
+        let req = PathRequest {
+            start,
+            end: goal.goal_pos(PathConstraints::Car, map),
+            constraints: PathConstraints::Car,
+        };
+        let path = routing.pathfind(self.id, &req, depart, map)?;
+        let router = if let TripLeg::Deliver(_, b, _) = self.legs[0] {
+            Router::deliver_to(path, b, vehicle, map)
+        } else {
+            goal.make_router(path, map, vehicle.vehicle_type)
+        };
+        Some((router, req))
+    }
+
     fn assert_walking_leg(&mut self, ped: PedestrianID, goal: SidewalkSpot) {
         match self.legs.pop_front() {
             Some(TripLeg::Walk(p, _, spot)) => {
@@ -666,6 +948,8 @@ impl Trip {
 pub enum TripLeg {
     Walk(PedestrianID, Speed, SidewalkSpot),
     Drive(Vehicle, DrivingGoal),
+    // Drive near a building and stop there for some time, then keep going
+    Deliver(Vehicle, BuildingID, Duration),
     RideBus(PedestrianID, BusRouteID, BusStopID),
     ServeBusRoute(CarID, BusRouteID),
 }
@@ -692,9 +976,9 @@ impl TripMode {
         match id {
             AgentID::Pedestrian(_) => TripMode::Walk,
             AgentID::Car(id) => match id.1 {
-                VehicleType::Car => TripMode::Drive,
+                VehicleType::Car | VehicleType::Truck | VehicleType::Emergency => TripMode::Drive,
                 VehicleType::Bike => TripMode::Bike,
-                VehicleType::Bus => TripMode::Transit,
+                VehicleType::Bus | VehicleType::Train => TripMode::Transit,
             },
         }
     }
@@ -1,5 +1,6 @@
 mod geom;
 mod map_conversion;
+mod models;
 mod parking;
 mod runner;
 mod sim_completion;
@@ -31,6 +32,7 @@ fn main() {
 
     geom::run(t.suite("geom"));
     map_conversion::run(t.suite("map_conversion"));
+    models::run(t.suite("models"));
     parking::run(t.suite("parking"));
     sim_completion::run(t.suite("sim_completion"));
     sim_determinism::run(t.suite("sim_determinism"));
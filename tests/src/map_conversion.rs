@@ -8,6 +8,7 @@ pub fn run(t: &mut TestRunner) {
             offstreet_parking: Some("../data/input/offstreet_parking.kml".to_string()),
             sidewalks: Some("../data/input/sidewalks.bin".to_string()),
             gtfs: Some("../data/input/google_transit_2018_18_08".to_string()),
+            gtfs_day: "monday".to_string(),
             neighborhoods: Some("../data/input/neighborhoods.geojson".to_string()),
             clip: Some(abstutil::path_polygon("montlake")),
             output: "convert_osm_twice.bin".to_string(),
@@ -0,0 +1,561 @@
+use crate::runner::TestRunner;
+use abstutil::Timer;
+use geom::{Distance, Duration, Speed, Time};
+use map_model::{
+    coordinate_corridor, trace_corridor, webster_timing, EditCmd, IntersectionType, LaneID,
+    PathConstraints, PathRequest, PathStep, Position, Traversable, Turn, TurnPriority, TurnType,
+};
+use rand::SeedableRng;
+use rand_xorshift::XorShiftRng;
+use sim::{
+    crowded_speed, find_cycles, AgentID, CarID, ConflictDetector, DrivingModes, EmissionFactors,
+    Emissions, ModeChoiceModel, SimFlags, SpeedProfile, TravelTimes, TripID, TripMode, TripPaths,
+    VehicleType,
+};
+use std::collections::{BTreeMap, BTreeSet};
+
+pub fn run(t: &mut TestRunner) {
+    t.run_fast("speed_profile_reaches_cruise_speed", |_| {
+        let len = Distance::meters(200.0);
+        let cruise = Speed::meters_per_second(10.0);
+        let p = SpeedProfile::new(len, Speed::ZERO, cruise, cruise, 2.0, 2.5);
+
+        assert_eq!(p.peak_speed, cruise);
+        assert_eq!(p.end_speed, cruise);
+        // Accelerating from a stop costs 2.5s over just cruising the whole way.
+        assert!((p.total_time().inner_seconds() - 22.5).abs() < 0.01);
+        assert!((p.dist_at(p.total_time()) - len).inner_meters().abs() < 0.01);
+        assert_eq!(
+            p.speed_at(Duration::seconds(1.0)),
+            Speed::meters_per_second(2.0)
+        );
+    });
+
+    t.run_fast("speed_profile_short_distance", |_| {
+        let len = Distance::meters(10.0);
+        let p = SpeedProfile::new(
+            len,
+            Speed::ZERO,
+            Speed::meters_per_second(20.0),
+            Speed::ZERO,
+            2.0,
+            2.0,
+        );
+
+        // Not enough room to reach the cruising speed
+        assert!(p.peak_speed < Speed::meters_per_second(20.0));
+        assert_eq!(p.end_speed, Speed::ZERO);
+        assert!((p.dist_at(p.total_time()) - len).inner_meters().abs() < 0.01);
+    });
+
+    t.run_fast("speed_profile_hard_braking", |_| {
+        let len = Distance::meters(5.0);
+        let p = SpeedProfile::new(
+            len,
+            Speed::meters_per_second(15.0),
+            Speed::meters_per_second(15.0),
+            Speed::ZERO,
+            2.0,
+            2.5,
+        );
+
+        assert_eq!(p.end_speed, Speed::ZERO);
+        assert!((p.dist_at(p.total_time()) - len).inner_meters().abs() < 0.01);
+    });
+
+    t.run_fast("speed_profile_zero_limits", |_| {
+        let len = Distance::meters(50.0);
+        for (cruise, accel, decel) in vec![
+            (Speed::ZERO, 2.0, 2.5),
+            (Speed::meters_per_second(10.0), 0.0, 0.0),
+            (Speed::ZERO, 0.0, 0.0),
+        ] {
+            let p = SpeedProfile::new(len, Speed::ZERO, cruise, Speed::ZERO, accel, decel);
+            assert!(p.total_time().inner_seconds().is_finite());
+            assert!(p.total_time() > Duration::ZERO);
+        }
+    });
+
+    t.run_fast("speed_profile_time_to_reach", |_| {
+        let len = Distance::meters(100.0);
+        let p = SpeedProfile::new(
+            len,
+            Speed::meters_per_second(3.0),
+            Speed::meters_per_second(12.0),
+            Speed::ZERO,
+            2.0,
+            2.5,
+        );
+        for i in 0..10 {
+            let t = p.total_time() * (i as f64 / 10.0);
+            let dt = p.time_to_reach(p.dist_at(t)) - t;
+            assert!(dt.inner_seconds().abs() < 0.01);
+        }
+        assert_eq!(p.time_to_reach(len * 2.0), p.total_time());
+    });
+
+    t.run_fast("emission_factors_estimate", |_| {
+        let factors = EmissionFactors::default_factors();
+
+        let idle = factors.estimate(VehicleType::Car, &DrivingModes::idle(Duration::hours(1)));
+        assert!((idle.co2 - 2800.0).abs() < 0.01);
+
+        // 1km at 50km/h
+        let mut modes = DrivingModes::idle(Duration::ZERO);
+        modes.cruising = (Duration::seconds(72.0), Distance::meters(1000.0));
+        let cruise = factors.estimate(VehicleType::Car, &modes);
+        assert!((cruise.co2 - 160.0).abs() < 0.01);
+
+        // Accelerating over the same stretch costs more, braking less.
+        modes.accelerating = modes.cruising;
+        modes.cruising = (Duration::ZERO, Distance::ZERO);
+        assert!(factors.estimate(VehicleType::Car, &modes).co2 > cruise.co2);
+        modes.braking = modes.accelerating;
+        modes.accelerating = (Duration::ZERO, Distance::ZERO);
+        assert!(factors.estimate(VehicleType::Car, &modes).co2 < cruise.co2);
+
+        assert_eq!(factors.estimate(VehicleType::Bike, &modes), Emissions::ZERO);
+    });
+
+    t.run_fast("mode_choice_logit", |_| {
+        let model = ModeChoiceModel::new();
+        let mut rng = XorShiftRng::from_seed([42; 16]);
+        let options = vec![
+            (TripMode::Walk, Duration::minutes(30)),
+            (TripMode::Drive, Duration::minutes(10)),
+            (TripMode::Transit, Duration::minutes(20)),
+        ];
+        // Each option's utility is its constant, minus per_minute for each minute and per_dollar
+        // for each dollar.
+        let utilities = vec![
+            model.walk_constant - model.per_minute * 30.0,
+            model.drive_constant - model.per_minute * 10.0 - model.per_dollar * model.drive_cost,
+            model.transit_constant
+                - model.per_minute * 20.0
+                - model.per_dollar * model.transit_fare,
+        ];
+        let total: f64 = utilities.iter().map(|u| u.exp()).sum();
+
+        let draws = 20_000;
+        let mut counts = vec![0; options.len()];
+        for _ in 0..draws {
+            counts[model.choose(&options, &mut rng)] += 1;
+        }
+        for (u, cnt) in utilities.iter().zip(counts) {
+            let expected = u.exp() / total;
+            let actual = (cnt as f64) / (draws as f64);
+            assert!(
+                (expected - actual).abs() < 0.02,
+                "expected {}, but chose {}",
+                expected,
+                actual
+            );
+        }
+
+        // Making the walk much faster shifts the share towards it.
+        let faster = vec![
+            (TripMode::Walk, Duration::minutes(5)),
+            (TripMode::Drive, Duration::minutes(10)),
+        ];
+        let walks = (0..draws)
+            .filter(|_| model.choose(&faster, &mut rng) == 0)
+            .count();
+        assert!(walks > draws / 2);
+
+        // Huge utilities don't overflow.
+        let slow = vec![
+            (TripMode::Walk, Duration::hours(1000)),
+            (TripMode::Bike, Duration::hours(2000)),
+        ];
+        assert_eq!(model.choose(&slow, &mut rng), 0);
+        assert_eq!(
+            model.choose(&[(TripMode::Bike, Duration::ZERO)], &mut rng),
+            0
+        );
+    });
+
+    t.run_fast("crowded_walking_speed", |_| {
+        let free = Speed::meters_per_second(1.4);
+        // A 100m sidewalk is 250 square meters.
+        let len = Distance::meters(100.0);
+
+        // Sparse crowds don't slow anybody down.
+        assert_eq!(crowded_speed(free, 1, len), free);
+        assert_eq!(crowded_speed(free, 75, len), free);
+
+        // Past that, more people means slower walking.
+        let mut last = free;
+        for crowd in vec![100, 250, 500, 1000] {
+            let speed = crowded_speed(free, crowd, len);
+            assert!(speed < last);
+            last = speed;
+        }
+        // 1 person per square meter walks at about 80% of the free speed.
+        let speed = crowded_speed(free, 250, len);
+        assert!(
+            (speed.inner_meters_per_second() / free.inner_meters_per_second() - 0.79).abs() < 0.01
+        );
+
+        // Even packed past jam density, people keep shuffling along.
+        assert_eq!(crowded_speed(free, 5000, len), free * 0.1);
+        // Tiny crosswalks count as at least a square meter.
+        assert_eq!(crowded_speed(free, 1, Distance::meters(0.1)), speed);
+    });
+
+    t.run_fast("find_gridlock_cycles", |_| {
+        // 4 waits on the 1-2-3 cycle, but isn't part of it. 7 and 8 are just in a line.
+        let waits_for = vec![(1, 2), (2, 3), (3, 1), (4, 1), (5, 6), (6, 5), (7, 8)];
+        assert_eq!(find_cycles(&waits_for), vec![vec![1, 2, 3], vec![5, 6]]);
+
+        assert!(find_cycles(&vec![(1, 2), (2, 3), (3, 4)]).is_empty());
+        assert!(find_cycles::<usize>(&Vec::new()).is_empty());
+    });
+
+    t.run_fast("travel_times_buckets", |_| {
+        let on = Traversable::Lane(LaneID(0));
+        let at =
+            |h: usize, m: usize| Time::START_OF_DAY + Duration::hours(h) + Duration::minutes(m);
+
+        let mut tt = TravelTimes::new(Duration::minutes(15));
+        tt.record(on, at(7, 5), Duration::seconds(60.0));
+        tt.record(on, at(7, 10), Duration::seconds(120.0));
+        assert_eq!(tt.average(on, at(7, 14)), Some(Duration::seconds(90.0)));
+        assert_eq!(tt.average(on, at(7, 15)), None);
+        assert_eq!(tt.average(on, at(6, 59)), None);
+        // The next day uses the same buckets.
+        assert_eq!(tt.average(on, at(31, 0)), Some(Duration::seconds(90.0)));
+
+        let mut other = TravelTimes::new(Duration::minutes(15));
+        other.record(on, at(7, 0), Duration::seconds(30.0));
+        other.record(on, at(8, 0), Duration::seconds(200.0));
+        tt.blend(&other, 0.5);
+        assert_eq!(tt.average(on, at(7, 0)), Some(Duration::seconds(60.0)));
+        // Buckets only one side has are copied over.
+        assert_eq!(tt.average(on, at(8, 0)), Some(Duration::seconds(200.0)));
+    });
+
+    t.run_slow("conflict_post_encroachment_time", |_| {
+        let (map, _, _) =
+            SimFlags::for_test("conflict_post_encroachment_time").load(&mut Timer::throwaway());
+        // Two vehicle turns crossing partway through
+        let vehicle_turns: Vec<_> = map
+            .all_turns()
+            .values()
+            .filter(|t| {
+                t.turn_type != TurnType::Crosswalk && t.turn_type != TurnType::SharedSidewalkCorner
+            })
+            .collect();
+        let (t1, t2, dist1, dist2) = vehicle_turns
+            .iter()
+            .find_map(|t1| {
+                vehicle_turns.iter().find_map(|t2| {
+                    if t1.id.parent != t2.id.parent || !t1.conflicts_with(t2) {
+                        return None;
+                    }
+                    let (pt, _) = t1.geom.intersection(&t2.geom)?;
+                    let (dist1, _) = t1.geom.dist_along_of_point(pt)?;
+                    let (dist2, _) = t2.geom.dist_along_of_point(pt)?;
+                    if dist1 > Distance::meters(1.0) && dist2 > Distance::meters(1.0) {
+                        Some((*t1, *t2, dist1, dist2))
+                    } else {
+                        None
+                    }
+                })
+            })
+            .unwrap();
+
+        let speed = Speed::meters_per_second(5.0);
+        let length = Distance::meters(5.0);
+        // Both cars cross at a steady speed. The second reaches the crossing point some time after
+        // the first one does.
+        let conflicts_with_gap = |gap: Duration| {
+            let mut detector = ConflictDetector::new();
+            let mut cross = |car: CarID, turn: &Turn, start: Time| {
+                let len = turn.geom.length();
+                detector.agent_entered(AgentID::Car(car), Traversable::Turn(turn.id), start, &map);
+                detector.vehicle_moved(
+                    car,
+                    length,
+                    turn.id,
+                    start,
+                    Distance::ZERO,
+                    SpeedProfile::new(len, speed, speed, speed, 2.0, 2.0),
+                );
+                detector.agent_entered(
+                    AgentID::Car(car),
+                    Traversable::Lane(turn.id.dst),
+                    start + len / speed,
+                    &map,
+                );
+            };
+            let arrive1 = Time::START_OF_DAY + Duration::minutes(1);
+            cross(CarID(0, VehicleType::Car), t1, arrive1 - dist1 / speed);
+            cross(
+                CarID(1, VehicleType::Car),
+                t2,
+                arrive1 + gap - dist2 / speed,
+            );
+            detector.all_conflicts().cloned().collect::<Vec<_>>()
+        };
+
+        // The first car's back clears the point a second after its front reaches it, so the PET
+        // is a second less than the gap between arrivals.
+        let conflicts = conflicts_with_gap(Duration::seconds(3.0));
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(
+            conflicts[0].first.0,
+            AgentID::Car(CarID(0, VehicleType::Car))
+        );
+        assert!((conflicts[0].pet.inner_seconds() - 2.0).abs() < 0.05);
+        assert!(!conflicts[0].is_severe());
+
+        // Arriving before the first car is out of the way is as bad as it gets.
+        let conflicts = conflicts_with_gap(Duration::seconds(0.5));
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].pet, Duration::ZERO);
+        assert!(conflicts[0].is_severe());
+
+        // Far enough apart, it's not a conflict at all.
+        assert!(conflicts_with_gap(Duration::seconds(10.0)).is_empty());
+    });
+
+    t.run_slow("coordinate_corridor", |_| {
+        let mut timer = Timer::throwaway();
+        let (mut map, _, _) = SimFlags::for_test("coordinate_corridor").load(&mut timer);
+        // Find some corridor with a few signals along it.
+        let (roads, timing) = map
+            .all_intersections()
+            .iter()
+            .filter(|i| i.is_traffic_signal())
+            .flat_map(|i| i.roads.iter().map(move |r| (i.id, *r)))
+            .find_map(|(i, r)| {
+                let roads = trace_corridor(&map, i, r);
+                match coordinate_corridor(&map, &roads, None, None) {
+                    Ok(timing) if timing.num_signals >= 2 => Some((roads, timing)),
+                    _ => None,
+                }
+            })
+            .unwrap();
+        assert!(timing.bandwidth > Duration::ZERO);
+        assert!(timing.bandwidth <= timing.cycle_length);
+
+        assert!(coordinate_corridor(&map, &vec![roads[0]], None, None).is_err());
+        let far_away = map
+            .all_roads()
+            .iter()
+            .find(|r| {
+                let r0 = map.get_r(roads[0]);
+                r.src_i != r0.src_i
+                    && r.src_i != r0.dst_i
+                    && r.dst_i != r0.src_i
+                    && r.dst_i != r0.dst_i
+            })
+            .unwrap()
+            .id;
+        assert!(coordinate_corridor(&map, &vec![roads[0], far_away], None, None).is_err());
+
+        // Once the offsets are applied, coordinating again shouldn't change anything.
+        let mut edits = map.get_edits().clone();
+        edits.commands.extend(timing.edits);
+        map.apply_edits(edits, &mut timer);
+        let again = coordinate_corridor(&map, &roads, None, None).unwrap();
+        assert_eq!(again.cycle_length, timing.cycle_length);
+        for cmd in again.edits {
+            if let EditCmd::ChangeTrafficSignal(signal) = cmd {
+                let diff = (signal.offset - map.get_traffic_signal(signal.id).offset)
+                    .inner_seconds()
+                    .abs()
+                    % timing.cycle_length.inner_seconds();
+                assert!(diff.min(timing.cycle_length.inner_seconds() - diff) < 0.1);
+            }
+        }
+    });
+
+    t.run_slow("roundabouts", |_| {
+        let (map, _, _) = SimFlags::for_test("roundabouts").load(&mut Timer::throwaway());
+        for r in map.all_roads().iter().filter(|r| r.is_roundabout()) {
+            let mut ring = BTreeSet::new();
+            let mut queue = vec![r.src_i];
+            while let Some(i) = queue.pop() {
+                if ring.insert(i) {
+                    for r in &map.get_i(i).roads {
+                        let road = map.get_r(*r);
+                        if road.is_roundabout() {
+                            queue.push(road.src_i);
+                            queue.push(road.dst_i);
+                        }
+                    }
+                }
+            }
+            let types: Vec<IntersectionType> = ring
+                .into_iter()
+                .map(|i| map.get_i(i).intersection_type)
+                .collect();
+            // Every node of a ring becomes part of the roundabout, unless a signal somewhere left
+            // the whole ring alone.
+            if !types.contains(&IntersectionType::TrafficSignal) {
+                assert!(!types.contains(&IntersectionType::StopSign));
+            }
+        }
+
+        for i in map.all_intersections().iter().filter(|i| i.is_roundabout()) {
+            assert!(i.roads.iter().any(|r| map.get_r(*r).is_roundabout()));
+            // Only people already on the ring, or walking, have right-of-way.
+            for turn in map.get_turns_in_intersection(i.id) {
+                let expected =
+                    if turn.between_sidewalks() || map.get_parent(turn.id.src).is_roundabout() {
+                        TurnPriority::Protected
+                    } else {
+                        TurnPriority::Yield
+                    };
+                assert_eq!(map.get_roundabout_priority(turn.id), expected);
+            }
+        }
+    });
+
+    t.run_slow("pathfind_with_costs_loop_around", |_| {
+        let (map, _, _) =
+            SimFlags::for_test("pathfind_with_costs_loop_around").load(&mut Timer::throwaway());
+        let request = |l, from: f64, to: f64| {
+            let len = map.get_l(l).length();
+            PathRequest {
+                start: Position::new(l, len * from),
+                end: Position::new(l, len * to),
+                constraints: PathConstraints::Car,
+            }
+        };
+
+        // Starting past the end of the same lane means going around the block.
+        let (l, path) = map
+            .all_lanes()
+            .iter()
+            .filter(|l| l.is_driving())
+            .find_map(|l| {
+                map.pathfind_with_costs(request(l.id, 0.9, 0.1), |_, _, cost| Some(cost))
+                    .map(|path| (l.id, path))
+            })
+            .unwrap();
+        let steps = path.get_steps();
+        assert!(steps.len() > 1);
+        assert_eq!(steps[0], PathStep::Lane(l));
+        assert_eq!(steps[steps.len() - 1], PathStep::Lane(l));
+        // No turns allowed, no way around.
+        assert!(map
+            .pathfind_with_costs(request(l, 0.9, 0.1), |_, _, _| None)
+            .is_none());
+
+        // But heading forwards along the lane doesn't need any turns.
+        let path = map
+            .pathfind_with_costs(request(l, 0.1, 0.9), |_, _, _| None)
+            .unwrap();
+        assert_eq!(path.get_steps().len(), 1);
+    });
+
+    t.run_slow("traffic_assignment_gap", |_| {
+        let mut timer = Timer::throwaway();
+        let (map, _, _) = SimFlags::for_test("traffic_assignment_gap").load(&mut timer);
+        let depart = Time::START_OF_DAY + Duration::hours(7);
+        let lanes: Vec<LaneID> = map
+            .all_lanes()
+            .iter()
+            .filter(|l| l.is_driving())
+            .map(|l| l.id)
+            .collect();
+
+        // Find some trip where jamming one road in the middle makes another route better.
+        let (req, path, gap) = lanes
+            .iter()
+            .zip(lanes.iter().rev())
+            .find_map(|(from, to)| {
+                let req = PathRequest {
+                    start: Position::new(*from, Distance::ZERO),
+                    end: Position::new(*to, map.get_l(*to).length()),
+                    constraints: PathConstraints::Car,
+                };
+                let path =
+                    TravelTimes::new(Duration::minutes(15)).pathfind(req.clone(), depart, &map)?;
+                let steps = path.get_steps();
+                let middle = steps
+                    .iter()
+                    .skip(steps.len() / 2)
+                    .map(|s| s.as_traversable())
+                    .find(|on| on.maybe_lane().is_some() && on.maybe_lane() != Some(*to))?;
+
+                let mut paths = TripPaths::new();
+                paths.insert(TripID(0), depart, req.clone(), path.clone());
+                let mut costs = TravelTimes::new(Duration::minutes(15));
+                for bucket in 0..96 {
+                    costs.record(
+                        middle,
+                        Time::START_OF_DAY + Duration::minutes(15 * bucket),
+                        Duration::minutes(10),
+                    );
+                }
+                let gap = paths.relative_gap(&mut costs, &map, &mut Timer::throwaway());
+                if gap > 0.0 {
+                    Some((req, path, gap))
+                } else {
+                    None
+                }
+            })
+            .unwrap();
+        assert!(gap < 1.0);
+
+        // With free-flow costs, the fastest path can't be improved on.
+        let mut paths = TripPaths::new();
+        for id in 0..10 {
+            paths.insert(TripID(id), depart, req.clone(), path.clone());
+        }
+        let mut costs = TravelTimes::new(Duration::minutes(15));
+        assert!(paths.relative_gap(&mut costs, &map, &mut timer) < 0.01);
+
+        // Half of the trips reroute after the first iteration, then fewer and fewer.
+        assert_eq!(paths.keep_for_iteration(1).len(), 5);
+        assert_eq!(paths.keep_for_iteration(4).len(), 8);
+    });
+
+    t.run_slow("webster_timing", |_| {
+        let (map, _, _) = SimFlags::for_test("webster_timing").load(&mut Timer::throwaway());
+        // Find a signal with a movement that only goes during the first phase.
+        let (signal, busy) = map
+            .all_intersections()
+            .iter()
+            .filter_map(|i| map.maybe_get_traffic_signal(i.id))
+            .filter(|signal| signal.phases.len() > 1)
+            .find_map(|signal| {
+                signal
+                    .turn_groups
+                    .keys()
+                    .find(|g| {
+                        g.crosswalk.is_none()
+                            && signal.phases[0].protected_groups.contains(*g)
+                            && signal.phases.iter().skip(1).all(|p| {
+                                !p.protected_groups.contains(*g) && !p.yield_groups.contains(*g)
+                            })
+                    })
+                    .map(|g| (signal, *g))
+            })
+            .unwrap();
+
+        assert!(webster_timing(signal, &BTreeMap::new()).is_none());
+
+        let mut flows = BTreeMap::new();
+        for g in signal.turn_groups.keys() {
+            if g.crosswalk.is_none() {
+                flows.insert(*g, 50.0);
+            }
+        }
+        flows.insert(busy, 800.0);
+        let new_signal = webster_timing(signal, &flows).unwrap();
+
+        let cycle_length = new_signal
+            .phases
+            .iter()
+            .fold(Duration::ZERO, |sum, p| sum + p.duration);
+        assert!(cycle_length >= Duration::seconds(40.0));
+        assert!(cycle_length <= Duration::seconds(150.0));
+        for p in new_signal.phases.iter().skip(1) {
+            assert!(new_signal.phases[0].duration >= p.duration);
+        }
+    });
+}
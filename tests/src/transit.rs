@@ -1,7 +1,8 @@
 use crate::runner::TestRunner;
 use abstutil::Timer;
-use geom::{Duration, Time};
-use sim::{Event, Scenario, SidewalkSpot, SimFlags, TripSpec};
+use geom::{Duration, LonLat, Time};
+use map_model::BusRoute;
+use sim::{CarID, Event, Scenario, SidewalkSpot, SimFlags, TripSpec, VehicleType};
 
 pub fn run(t: &mut TestRunner) {
     t.run_slow("bus_reaches_stops", |h| {
@@ -79,4 +80,123 @@ pub fn run(t: &mut TestRunner) {
             Duration::minutes(9),
         );
     });
+
+    t.run_slow("timetabled_bus_waits_then_retires", |h| {
+        let (map, mut sim, _) =
+            SimFlags::for_test("timetabled_bus_waits_then_retires").load(&mut Timer::throwaway());
+        let route = map.get_bus_route("49").unwrap();
+        // One run around the route
+        let timetabled = BusRoute {
+            id: route.id,
+            name: route.name.clone(),
+            stops: route.stops.clone(),
+            departures: vec![Time::START_OF_DAY + Duration::minutes(5)],
+            stop_offsets: vec![Duration::ZERO; route.stops.len()],
+            return_stop: None,
+            return_departures: Vec::new(),
+            route_type: route.route_type,
+        };
+        sim.schedule_bus_route(&timetabled, &map);
+        let bus = CarID(0, VehicleType::Bus);
+        h.setup_done(&sim);
+
+        // The bus starts by picking people up at the first stop.
+        sim.run_until_expectations_met(
+            &map,
+            vec![
+                Event::BusArrivedAtStop(bus, route.id, route.stops[0]),
+                Event::BusDepartedFromStop(bus, route.id, route.stops[0]),
+                Event::BusArrivedAtStop(bus, route.id, route.stops[1]),
+            ],
+            Duration::minutes(10),
+        );
+        // And goes out of service once it's back.
+        sim.step(&map, Duration::hours(1));
+        assert!(sim.location_of_buses(route.id, &map).is_empty());
+    });
+
+    t.run_fast("gtfs_directions_and_service_day", |_| {
+        let dir = std::env::temp_dir().join("gtfs_directions_and_service_day");
+        std::fs::create_dir_all(&dir).unwrap();
+        for (file, contents) in vec![
+            (
+                "routes.txt",
+                "route_id,agency_id,route_short_name,route_long_name,route_desc,route_type\n\
+                 R1,A,49,,,3\n",
+            ),
+            (
+                "stops.txt",
+                "stop_id,stop_code,stop_name,stop_desc,stop_lat,stop_lon\n\
+                 S1,,,,47.0,-122.0\n\
+                 S2,,,,47.1,-122.1\n\
+                 S3,,,,47.2,-122.2\n",
+            ),
+            (
+                "calendar.txt",
+                "service_id,monday,tuesday,wednesday,thursday,friday,saturday,sunday\n\
+                 WEEKDAY,1,1,1,1,1,0,0\n\
+                 SATURDAY,0,0,0,0,0,1,0\n",
+            ),
+            (
+                "trips.txt",
+                "route_id,service_id,trip_id,trip_headsign,trip_short_name,direction_id\n\
+                 R1,WEEKDAY,T1,,,0\n\
+                 R1,WEEKDAY,T2,,,0\n\
+                 R1,WEEKDAY,T3,,,1\n\
+                 R1,SATURDAY,T4,,,0\n",
+            ),
+            (
+                "stop_times.txt",
+                "trip_id,arrival_time,departure_time,stop_id,stop_sequence\n\
+                 T1,07:00:00,07:00:00,S1,1\n\
+                 T1,07:10:00,07:10:00,S2,2\n\
+                 T1,07:20:00,07:20:00,S3,3\n\
+                 T2,08:00:00,08:00:00,S1,1\n\
+                 T2,08:10:00,08:10:00,S2,2\n\
+                 T2,08:20:00,08:20:00,S3,3\n\
+                 T3,07:30:00,07:30:00,S3,1\n\
+                 T3,07:35:00,07:35:00,S2,2\n\
+                 T3,07:45:00,07:45:00,S1,3\n\
+                 T4,09:00:00,09:00:00,S1,1\n\
+                 T4,09:15:00,09:15:00,S2,2\n\
+                 T4,09:30:00,09:30:00,S3,3\n",
+            ),
+        ] {
+            std::fs::write(dir.join(file), contents).unwrap();
+        }
+        let path = dir.to_str().unwrap();
+        let at = |h, m| Time::START_OF_DAY + Duration::hours(h) + Duration::minutes(m);
+        let (s1, s2, s3) = (
+            LonLat::new(-122.0, 47.0),
+            LonLat::new(-122.1, 47.1),
+            LonLat::new(-122.2, 47.2),
+        );
+
+        // Both directions run on weekdays, each with their own departures.
+        let monday = gtfs::load(path, "monday").unwrap();
+        assert_eq!(monday.len(), 1);
+        assert_eq!(monday[0].name, "49");
+        assert_eq!(monday[0].stops, vec![s1, s2, s3, s3, s2, s1]);
+        assert_eq!(
+            monday[0].stop_offsets,
+            vec![0, 10, 20, 20, 25, 35]
+                .into_iter()
+                .map(Duration::minutes)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(monday[0].departures, vec![at(7, 0), at(8, 0)]);
+        assert_eq!(monday[0].return_stop, Some(3));
+        assert_eq!(monday[0].return_departures, vec![at(7, 30)]);
+
+        // Only one direction runs on Saturdays.
+        let saturday = gtfs::load(path, "saturday").unwrap();
+        assert_eq!(saturday[0].stops, vec![s1, s2, s3]);
+        assert_eq!(saturday[0].departures, vec![at(9, 0)]);
+        assert_eq!(saturday[0].return_stop, None);
+        assert!(saturday[0].return_departures.is_empty());
+
+        // Nothing runs on Sundays.
+        assert!(gtfs::load(path, "sunday").unwrap().is_empty());
+        assert!(gtfs::load(path, "someday").is_err());
+    });
 }
@@ -2,7 +2,7 @@ use crate::runner::TestRunner;
 use abstutil::Timer;
 use geom::{Duration, Time};
 use map_model::{BuildingID, IntersectionID};
-use sim::{DrivingGoal, Event, Scenario, SidewalkSpot, SimFlags, TripSpec};
+use sim::{DrivingGoal, Event, PedestrianID, PersonID, Scenario, SidewalkSpot, SimFlags, TripSpec};
 
 pub fn run(t: &mut TestRunner) {
     t.run_slow("bike_from_border", |h| {
@@ -37,4 +37,56 @@ pub fn run(t: &mut TestRunner) {
         );
         sim.just_run_until_done(&map, Some(Duration::minutes(1)));
     });
+
+    t.run_slow("person_skips_unspawnable_trip", |h| {
+        let (map, mut sim, mut rng) =
+            SimFlags::for_test("person_skips_unspawnable_trip").load(&mut Timer::throwaway());
+        // Biking from here can't even be scheduled.
+        let home = map
+            .all_buildings()
+            .iter()
+            .find(|b| SidewalkSpot::bike_from_bike_rack(b.sidewalk(), &map).is_none())
+            .expect("every building can start biking")
+            .id;
+        let work = map
+            .all_buildings()
+            .iter()
+            .find(|b| b.sidewalk() != map.get_b(home).sidewalk())
+            .unwrap()
+            .id;
+        let ped_speed = Scenario::rand_ped_speed(&mut rng);
+        sim.schedule_person(
+            PersonID(0),
+            vec![
+                (
+                    Time::START_OF_DAY,
+                    Duration::ZERO,
+                    TripSpec::UsingBike {
+                        start: SidewalkSpot::building(home, &map),
+                        vehicle: Scenario::rand_bike(&mut rng),
+                        goal: DrivingGoal::ParkNear(work),
+                        ped_speed,
+                    },
+                ),
+                (
+                    Time::START_OF_DAY + Duration::minutes(1),
+                    Duration::ZERO,
+                    TripSpec::JustWalking {
+                        start: SidewalkSpot::building(home, &map),
+                        goal: SidewalkSpot::building(work, &map),
+                        ped_speed,
+                    },
+                ),
+            ],
+        );
+        h.setup_done(&sim);
+
+        // The bike trip is aborted, but the person still walks afterwards.
+        sim.run_until_expectations_met(
+            &map,
+            vec![Event::PedReachedBuilding(PedestrianID(1), work)],
+            Duration::minutes(30),
+        );
+        sim.just_run_until_done(&map, Some(Duration::minutes(1)));
+    });
 }